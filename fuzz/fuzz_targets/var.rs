@@ -0,0 +1,49 @@
+// Arbitrary bytes as a downloaded var: everything VarCleaner reads from an archive before
+// deciding what to merge must return errors, never panic, hang or exhaust memory.
+// Run with `cargo fuzz run var` from the repository root.
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use std::collections::HashMap;
+use std::io::Cursor;
+use std::path::PathBuf;
+use varcleaner::content;
+use varcleaner::index;
+use varcleaner::package::PackageId;
+use varcleaner::reader::VarReader;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(text) = std::str::from_utf8(data) {
+        let _ = PackageId::parse(text);
+    }
+    let Ok(mut var) = VarReader::from_reader("Fuzz.Input.1", Cursor::new(data)) else {
+        return;
+    };
+    let Ok(var_index) = var.index() else {
+        return;
+    };
+    index::fingerprint(&var_index);
+
+    // Entry names double as installed packages so references have something to resolve to
+    let installed: HashMap<String, PathBuf> = var_index
+        .entries
+        .iter()
+        .filter_map(|e| Some((PackageId::parse(&e.name)?.to_string(), PathBuf::from(&e.name))))
+        .collect();
+    if let Some(meta) = &var_index.meta {
+        for dep in index::dependencies(meta) {
+            let _ = index::resolve_dependency(&dep, &installed);
+        }
+    }
+    for entry in var_index.entries.iter() {
+        content::category(&entry.name);
+        if !content::is_text(&entry.name) {
+            continue;
+        }
+        if let Ok(bytes) = var.read_entry(&entry.name) {
+            let _ = index::parse_json(&bytes);
+            content::self_references(&String::from_utf8_lossy(&bytes));
+        }
+    }
+    let _ = var.hash_entries();
+});
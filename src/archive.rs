@@ -0,0 +1,98 @@
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+use walkdir::WalkDir;
+use zip::write::SimpleFileOptions;
+use zip::ZipWriter;
+
+// Copy every entry of `src` into `dst` without recompressing, swapping the
+// contents of the entries named in `replacements` and adding the ones `src` lacks
+pub fn rewrite_var(
+    src: &Path,
+    dst: &Path,
+    replacements: &HashMap<String, Vec<u8>>,
+) -> anyhow::Result<()> {
+    crate::readonly::check(dst)?;
+    let mut archive = crate::index::open_var(src)?;
+    let mut zip = ZipWriter::new(File::create(dst)?);
+    let options = SimpleFileOptions::default();
+    let mut replaced = HashSet::new();
+    for i in 0..archive.len() {
+        let file = archive.by_index_raw(i)?;
+        match replacements.get_key_value(file.name()) {
+            Some((name, data)) => {
+                drop(file);
+                zip.start_file(name.as_str(), options)?;
+                zip.write_all(data)?;
+                replaced.insert(name);
+            }
+            None => zip.raw_copy_file(file)?,
+        }
+    }
+    for (name, data) in replacements.iter().filter(|(n, _)| !replaced.contains(n)) {
+        zip.start_file(name.as_str(), options)?;
+        zip.write_all(data)?;
+    }
+    zip.finish()?;
+    Ok(())
+}
+
+// Rewrite `path` in place, moving the original to `backup_path`, or back when the rewrite
+// cannot take its place
+pub fn replace_var(
+    path: &Path,
+    backup_path: &Path,
+    replacements: &HashMap<String, Vec<u8>>,
+) -> anyhow::Result<()> {
+    let tmp_path = path.with_extension("var.tmp");
+    if let Err(e) = rewrite_var(path, &tmp_path, replacements) {
+        let _ = fs::remove_file(&tmp_path);
+        return Err(e);
+    }
+    if let Err(e) = crate::backup::move_verified(path, backup_path) {
+        let _ = fs::remove_file(&tmp_path);
+        return Err(e);
+    }
+    if let Err(e) = fs::rename(&tmp_path, path) {
+        let _ = fs::remove_file(&tmp_path);
+        crate::backup::move_verified(backup_path, path)?;
+        return Err(e.into());
+    }
+    Ok(())
+}
+
+pub fn partial_path(target: &Path) -> PathBuf {
+    let mut name = target.file_name().unwrap().to_os_string();
+    name.push(".partial");
+    target.with_file_name(name)
+}
+
+// Leftovers of an interrupted run
+pub fn remove_partials(folder: &Path) {
+    for entry in WalkDir::new(folder).into_iter().filter_map(|e| e.ok()) {
+        let name = entry.file_name().to_string_lossy();
+        if entry.file_type().is_file()
+            && (name.ends_with(".var.partial") || name.ends_with(".var.partial.tmp"))
+        {
+            let _ = fs::remove_file(entry.path());
+        }
+    }
+}
+
+// Read every entry to the end so the zip reader checks its CRC, returns the broken ones
+pub fn verify_crc(path: &Path) -> anyhow::Result<Vec<String>> {
+    let mut archive = crate::index::open_var(path)?;
+    let mut corrupt = Vec::new();
+    for i in 0..archive.len() {
+        let mut file = archive.by_index(i)?;
+        if let Err(e) = io::copy(&mut file, &mut io::sink()) {
+            corrupt.push(format!("{}: {e}", file.name()));
+        }
+    }
+    Ok(corrupt)
+}
@@ -0,0 +1,449 @@
+use crate::console::{error, info, success, summary, verbose, warning};
+use crate::format_size;
+use crate::hashing::hash_file;
+use crate::hashing::HashCache;
+use crate::index;
+use crate::journal::Journal;
+use crate::journal::Operation;
+use std::collections::BTreeSet;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use threadpool::ThreadPool;
+use threadpool_scope::scope_with;
+
+// A rename never loses data; across volumes copy, compare hashes, and only then delete.
+// An existing `dst` is never replaced.
+pub fn move_verified(src: &Path, dst: &Path) -> anyhow::Result<()> {
+    crate::readonly::check(src)?;
+    if dst.symlink_metadata().is_ok() {
+        anyhow::bail!("{} already exists", dst.to_string_lossy());
+    }
+    fs::create_dir_all(dst.parent().unwrap())?;
+    match fs::rename(src, dst) {
+        Ok(()) => return Ok(()),
+        Err(e) if !crate::platform::is_cross_device(&e) => return Err(e.into()),
+        Err(_) => {}
+    }
+    fs::copy(src, dst)?;
+    if hash_file(src)? != hash_file(dst)? {
+        let _ = fs::remove_file(dst);
+        anyhow::bail!("backup copy of {} does not match", src.to_string_lossy());
+    }
+    fs::remove_file(src)?;
+    Ok(())
+}
+
+// `path`, or while that is taken the first free " (n)" name next to it, as a download would be
+// named
+pub fn free_path(path: PathBuf) -> PathBuf {
+    if path.symlink_metadata().is_err() {
+        return path;
+    }
+    let file_name = path.file_name().unwrap().to_string_lossy().to_string();
+    let (stem, ext) = match file_name.rsplit_once('.') {
+        Some((stem, ext)) => (stem.to_string(), format!(".{ext}")),
+        None => (file_name.clone(), String::new()),
+    };
+    (1..)
+        .map(|n| path.with_file_name(format!("{stem} ({n}){ext}")))
+        .find(|candidate| candidate.symlink_metadata().is_err())
+        .unwrap()
+}
+
+// Back up every copy of a package in parallel, keeping its path relative to `var_folder`
+// unless an earlier backup holds it. Returns the moves made, also those before a failure.
+pub fn move_all<'a>(
+    sources: impl IntoIterator<Item = &'a PathBuf>,
+    var_folder: &Path,
+    backup_folder: &Path,
+) -> (Vec<(PathBuf, PathBuf)>, anyhow::Result<()>) {
+    let sources: Vec<&PathBuf> = sources.into_iter().collect();
+    let pool = ThreadPool::new(sources.len().max(1));
+    let moves: Mutex<Vec<(PathBuf, PathBuf)>> = Mutex::new(Vec::new());
+    let failure: Mutex<Option<anyhow::Error>> = Mutex::new(None);
+    scope_with(&pool, |scope| {
+        for source in sources.iter() {
+            let moves = &moves;
+            let failure = &failure;
+            scope.execute(move || {
+                let backup_path =
+                    free_path(backup_folder.join(source.strip_prefix(var_folder).unwrap()));
+                match move_verified(source, &backup_path) {
+                    Ok(()) => moves.lock().unwrap().push(((*source).clone(), backup_path)),
+                    Err(e) => {
+                        failure.lock().unwrap().get_or_insert(e);
+                    }
+                }
+            });
+        }
+    });
+    let result = match failure.into_inner().unwrap() {
+        Some(e) => Err(e),
+        None => Ok(()),
+    };
+    (moves.into_inner().unwrap(), result)
+}
+
+// Remove the folders the moved `sources` sat in once they are empty, and their parents up
+// to `var_folder`, deepest first
+pub fn remove_emptied_folders<'a>(
+    sources: impl IntoIterator<Item = &'a PathBuf>,
+    var_folder: &Path,
+    journal_folder: &Path,
+) {
+    let mut folders: BTreeSet<PathBuf> = BTreeSet::new();
+    for source in sources {
+        for folder in source.ancestors().skip(1) {
+            if !folder.starts_with(var_folder) || folder == var_folder {
+                break;
+            }
+            folders.insert(folder.to_path_buf());
+        }
+    }
+    let mut journal = Journal::new("remove-empty-folders");
+    // Reverse order visits children before their parents
+    for folder in folders.iter().rev() {
+        if crate::readonly::check(folder).is_err() {
+            break;
+        }
+        let empty = fs::read_dir(folder).is_ok_and(|mut entries| entries.next().is_none());
+        if empty && fs::remove_dir(folder).is_ok() {
+            verbose!("Removed empty folder {}", folder.to_string_lossy());
+            journal.record(Operation::RemoveDir {
+                path: folder.clone(),
+            });
+        }
+    }
+    if journal.operations.is_empty() {
+        return;
+    }
+    summary!(
+        "Removed {} emptied folders / 已删除{}个清空的文件夹",
+        journal.operations.len(),
+        journal.operations.len()
+    );
+    if let Err(e) = journal.save(journal_folder) {
+        error!("Failed to write the journal: {e}");
+    }
+}
+
+pub enum DedupeAction {
+    Report,
+    Hardlink,
+    Drop,
+}
+
+// Backup files whose bytes equal a live var, found by size first and hash second
+pub fn dedupe(vam_folder: &Path, action: DedupeAction) {
+    let var_folder = &crate::root::addon_packages(vam_folder);
+    let backup_folder = &crate::root::backup_folder(vam_folder);
+    let hashes = HashCache::load(vam_folder);
+    let mut live_by_size: HashMap<u64, Vec<PathBuf>> = HashMap::new();
+    for path in index::list_vars(var_folder) {
+        if let Ok(metadata) = fs::metadata(&path) {
+            live_by_size.entry(metadata.len()).or_default().push(path);
+        }
+    }
+
+    let backups = index::list_vars(backup_folder);
+    let mut identical: Vec<(PathBuf, PathBuf, u64)> = Vec::new();
+    let mut backup_size = 0;
+    for backup in backups.iter() {
+        let size = fs::metadata(backup).map(|m| m.len()).unwrap_or(0);
+        backup_size += size;
+        let live = match live_by_size.get(&size) {
+            Some(live) => live,
+            None => continue,
+        };
+        let backup_hash = match hashes.hash(backup) {
+            Ok(hash) => hash,
+            Err(_) => continue,
+        };
+        let matching = hashes
+            .hash_all(live)
+            .into_iter()
+            .zip(live.iter())
+            .find(|(hash, _)| hash.as_ref().ok() == Some(&backup_hash));
+        if let Some((_, live_path)) = matching {
+            identical.push((backup.clone(), live_path.clone(), size));
+        }
+    }
+    hashes.save();
+
+    for (backup, live, size) in identical.iter() {
+        verbose!(
+            "{} = {} ({})",
+            backup
+                .strip_prefix(backup_folder)
+                .unwrap()
+                .to_string_lossy(),
+            live.strip_prefix(var_folder).unwrap().to_string_lossy(),
+            format_size(*size)
+        );
+    }
+    let identical_size: u64 = identical.iter().map(|(_, _, size)| size).sum();
+    summary!(
+        "{} of {} backup files ({} of {}) are identical to live vars / {}个备份文件与现有Var完全相同",
+        identical.len(),
+        backups.len(),
+        format_size(identical_size),
+        format_size(backup_size),
+        identical.len()
+    );
+    if let DedupeAction::Report = action {
+        return;
+    }
+
+    let mut reclaimed = 0;
+    for (backup, live, size) in identical.iter() {
+        // The cache may be stale by now, compare the real bytes again before touching anything
+        let verified = match (hash_file(backup), hash_file(live)) {
+            (Ok(a), Ok(b)) => a == b,
+            _ => false,
+        };
+        if !verified {
+            warning!(
+                "Skip {}, it changed since the scan",
+                backup.to_string_lossy()
+            );
+            continue;
+        }
+        let result = crate::readonly::check(backup).and_then(|_| match action {
+            DedupeAction::Hardlink => hardlink(backup, live),
+            _ => fs::remove_file(backup),
+        });
+        match result {
+            Ok(()) => reclaimed += size,
+            Err(e) => error!("Failed to reclaim {}: {e}", backup.to_string_lossy()),
+        }
+    }
+    success!(
+        "Reclaimed {} / 释放了{}",
+        format_size(reclaimed),
+        format_size(reclaimed)
+    );
+}
+
+// Replace `backup` with a hardlink to `live`, keeping the backup if linking fails
+fn hardlink(backup: &Path, live: &Path) -> io::Result<()> {
+    let mut tmp = backup.as_os_str().to_os_string();
+    tmp.push(".link");
+    let tmp = PathBuf::from(tmp);
+    fs::hard_link(live, &tmp)?;
+    fs::rename(&tmp, backup).inspect_err(|_| {
+        let _ = fs::remove_file(&tmp);
+    })
+}
+
+// Undo clean runs: every original in the backup goes back where it was, unless something
+// took its place since, and a merged var whose original came back moves to
+// VarCleaner/Unmerged so VaM does not see the package twice. Journaled like every move.
+pub fn restore(vam_folder: &Path, assume_yes: bool) -> anyhow::Result<()> {
+    let var_folder = &crate::root::addon_packages(vam_folder);
+    let backup_folder = &crate::root::backup_folder(vam_folder);
+    let merged_folder = &var_folder.join("merged");
+    let unmerged_folder = &vam_folder.join("VarCleaner/Unmerged");
+    let originals: Vec<PathBuf> = walkdir::WalkDir::new(backup_folder)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_file())
+        .map(|e| e.into_path())
+        .collect();
+    let (free, taken): (Vec<&PathBuf>, Vec<&PathBuf>) = originals.iter().partition(|backup| {
+        !var_folder
+            .join(backup.strip_prefix(backup_folder).unwrap())
+            .exists()
+    });
+    let merged: BTreeSet<PathBuf> = free
+        .iter()
+        .filter(|backup| index::is_var(backup))
+        .map(|backup| merged_folder.join(backup.file_name().unwrap()))
+        .filter(|merged| merged.is_file())
+        .collect();
+    for backup in taken.iter() {
+        warning!(
+            "{} stays in the backup, its place is taken",
+            backup
+                .strip_prefix(backup_folder)
+                .unwrap()
+                .to_string_lossy()
+        );
+    }
+    if free.is_empty() {
+        summary!("Nothing to restore / 没有需要恢复的文件");
+        return Ok(());
+    }
+    info!(
+        "{} originals go back to AddonPackages, {} merged vars to VarCleaner/Unmerged",
+        free.len(),
+        merged.len()
+    );
+    if !assume_yes && !crate::ui::confirm("Restore them? / 确认恢复？") {
+        anyhow::bail!("Cancelled, pass --yes to restore without asking / 已取消");
+    }
+
+    let mut journal = Journal::new("restore");
+    let mut moves: Vec<(PathBuf, PathBuf)> = free
+        .iter()
+        .map(|backup| {
+            let original = var_folder.join(backup.strip_prefix(backup_folder).unwrap());
+            ((*backup).clone(), original)
+        })
+        .collect();
+    moves.extend(merged.into_iter().map(|merged| {
+        let unmerged = unmerged_folder.join(merged.file_name().unwrap());
+        (merged, unmerged)
+    }));
+    let mut failed = 0;
+    for (from, to) in moves {
+        match move_verified(&from, &to) {
+            Ok(()) => journal.record(Operation::Move { from, to }),
+            Err(e) => {
+                error!("Failed to move {}: {e}", from.to_string_lossy());
+                failed += 1;
+            }
+        }
+    }
+    journal.save(&vam_folder.join("VarCleaner/Journal"))?;
+    if failed > 0 {
+        anyhow::bail!("{failed} files could not be restored / {failed}个文件恢复失败");
+    }
+    success!(
+        "Restored {} originals / 已恢复{}个原始文件",
+        free.len(),
+        free.len()
+    );
+    Ok(())
+}
+
+// Undo one clean run from its journal: the merged vars and other files the run wrote are
+// deleted while unchanged since, then every original it backed up goes back exactly where it
+// was, also onto a path a deleted merge freed. Nothing is deleted while an original of the run
+// is gone from the backup.
+pub fn restore_run(vam_folder: &Path, run_id: &str, assume_yes: bool) -> anyhow::Result<()> {
+    let journal_folder = &vam_folder.join("VarCleaner/Journal");
+    let run: Journal = match fs::read(journal_folder.join(format!("{run_id}.json"))) {
+        Ok(bytes) => serde_json::from_slice(&bytes)?,
+        Err(_) => {
+            let mut runs: Vec<String> = fs::read_dir(journal_folder)
+                .into_iter()
+                .flatten()
+                .filter_map(|e| e.ok())
+                .map(|e| e.path())
+                .filter_map(|p| Some(p.file_stem()?.to_string_lossy().to_string()))
+                .filter(|id| id.starts_with("clean-"))
+                .collect();
+            runs.sort();
+            for id in runs.iter() {
+                info!("  {id}");
+            }
+            anyhow::bail!(
+                "No journal of run {run_id}, {} clean runs can be undone / 找不到运行记录{run_id}",
+                runs.len()
+            );
+        }
+    };
+    if run.command != "clean" {
+        anyhow::bail!(
+            "{run_id} is a {} run, only clean runs are undone by id / 只能按编号撤销清理",
+            run.command
+        );
+    }
+    let mut originals: Vec<(PathBuf, PathBuf)> = Vec::new();
+    let mut created: Vec<(PathBuf, Option<String>)> = Vec::new();
+    for operation in run.operations.iter() {
+        match operation {
+            Operation::Move { from, to } => originals.push((to.clone(), from.clone())),
+            Operation::Create { path, hash } => created.push((path.clone(), hash.clone())),
+            _ => {}
+        }
+    }
+    // A re-merge writes the new merged var where the old one it backed up was
+    let mut deletes: Vec<PathBuf> = Vec::new();
+    for (path, hash) in created.iter().filter(|(path, _)| path.exists()) {
+        match hash {
+            Some(hash) if hash_file(path).is_ok_and(|now| now == *hash) => {
+                deletes.push(path.clone())
+            }
+            _ => warning!("{} changed since {run_id}, kept", path.to_string_lossy()),
+        }
+    }
+    let mut moves: Vec<(PathBuf, PathBuf)> = Vec::new();
+    let mut missing = 0;
+    for (backup, original) in originals.iter() {
+        let taken = original.exists() && !deletes.contains(original);
+        match (backup.exists(), taken) {
+            (true, false) => moves.push((backup.clone(), original.clone())),
+            (true, true) => warning!(
+                "{} stays in the backup, its place is taken",
+                backup.to_string_lossy()
+            ),
+            // Put back by an earlier restore
+            (false, true) => {}
+            (false, false) => {
+                warning!("{} is gone from the backup", backup.to_string_lossy());
+                missing += 1;
+            }
+        }
+    }
+    if missing > 0 {
+        warning!(
+            "{missing} originals of {run_id} are gone, its merged vars are kept / {missing}个原始文件已不在备份中，保留合并后的Var"
+        );
+        deletes.clear();
+    }
+    if moves.is_empty() && deletes.is_empty() {
+        summary!("Nothing to restore / 没有需要恢复的文件");
+        return Ok(());
+    }
+    info!(
+        "{} files written by {run_id} are deleted, {} originals go back where they were",
+        deletes.len(),
+        moves.len()
+    );
+    if !assume_yes && !crate::ui::confirm("Restore them? / 确认恢复？") {
+        anyhow::bail!("Cancelled, pass --yes to restore without asking / 已取消");
+    }
+
+    for path in deletes {
+        match crate::readonly::check(&path).and_then(|_| fs::remove_file(&path)) {
+            Ok(()) => verbose!("Deleted {}", path.to_string_lossy()),
+            Err(e) => error!("Failed to delete {}: {e}", path.to_string_lossy()),
+        }
+    }
+    let mut journal = Journal::new("restore");
+    let mut failed = 0;
+    for (from, to) in moves {
+        // Its place is still held by a merge that could not be deleted
+        if to.exists() {
+            warning!(
+                "{} stays in the backup, its place is taken",
+                from.to_string_lossy()
+            );
+            continue;
+        }
+        match move_verified(&from, &to) {
+            Ok(()) => journal.record(Operation::Move { from, to }),
+            Err(e) => {
+                error!("Failed to move {}: {e}", from.to_string_lossy());
+                failed += 1;
+            }
+        }
+    }
+    if let Err(e) = journal.save(journal_folder) {
+        error!("Failed to write the journal: {e}");
+    }
+    if failed > 0 {
+        anyhow::bail!("{failed} originals could not be restored / {failed}个原始文件恢复失败");
+    }
+    success!(
+        "Undid {run_id}, {} originals are back / 已撤销{run_id}，恢复了{}个原始文件",
+        journal.operations.len(),
+        journal.operations.len()
+    );
+    Ok(())
+}
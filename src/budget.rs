@@ -0,0 +1,46 @@
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::sync::Mutex;
+
+// Caps how much one run changes, so a wrong setting or a bug cannot reorganize a whole
+// library in one go: packages touched and bytes of originals moved, 0 meaning no cap
+pub struct ChangeLimit {
+    max_changes: usize,
+    max_bytes: u64,
+    // Packages and bytes allowed so far
+    used: Mutex<(usize, u64)>,
+    reached: AtomicBool,
+}
+
+impl ChangeLimit {
+    pub fn new(max_changes: usize, max_bytes: u64) -> ChangeLimit {
+        ChangeLimit {
+            max_changes,
+            max_bytes,
+            used: Mutex::new((0, 0)),
+            reached: AtomicBool::new(false),
+        }
+    }
+
+    // Counts a package of `size` bytes against the limit, false once it would exceed it
+    pub fn allow(&self, size: u64) -> bool {
+        let mut used = self.used.lock().unwrap();
+        let (changes, bytes) = (used.0 + 1, used.1 + size);
+        if (self.max_changes > 0 && changes > self.max_changes)
+            || (self.max_bytes > 0 && bytes > self.max_bytes)
+        {
+            self.reached.store(true, Ordering::Relaxed);
+            return false;
+        }
+        *used = (changes, bytes);
+        true
+    }
+
+    pub fn reached(&self) -> bool {
+        self.reached.load(Ordering::Relaxed)
+    }
+
+    pub fn used(&self) -> (usize, u64) {
+        *self.used.lock().unwrap()
+    }
+}
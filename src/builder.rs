@@ -0,0 +1,279 @@
+use crate::config;
+use crate::console::{debug, warning};
+use crate::index;
+use crate::meta::Meta;
+use crate::reader;
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::Cursor;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use threadpool::ThreadPool;
+use threadpool_scope::scope_with;
+use zip::write::SimpleFileOptions;
+use zip::CompressionMethod;
+use zip::ZipArchive;
+use zip::ZipWriter;
+
+enum Source {
+    Bytes(Vec<u8>),
+    // Entry of another var in the builder's method: copied compressed when it already has it,
+    // otherwise decompressed and compressed again
+    Entry(PathBuf),
+    // Entry of another var, copied compressed as it is
+    Raw(PathBuf),
+}
+
+// Collects the entries of a new var and writes it in one go: meta.json is generated when
+// missing and its contentList pruned to entries that exist, entries are compressed with
+// one method, and the var only appears under its name once it is complete
+pub struct VarBuilder {
+    method: CompressionMethod,
+    entries: Vec<(String, Source)>,
+    names: HashSet<String>,
+}
+
+pub fn entry_options(method: CompressionMethod) -> SimpleFileOptions {
+    let options = SimpleFileOptions::default()
+        .compression_method(method)
+        .unix_permissions(0o755);
+    match config::get().alignment {
+        0 => options,
+        alignment => options.with_alignment(alignment),
+    }
+}
+
+impl VarBuilder {
+    pub fn new(method: CompressionMethod) -> VarBuilder {
+        VarBuilder {
+            method,
+            entries: Vec::new(),
+            names: HashSet::new(),
+        }
+    }
+
+    // The first entry added under a name wins, later ones return false
+    fn add(&mut self, name: &str, source: Source) -> bool {
+        if !self.names.insert(name.to_string()) {
+            return false;
+        }
+        self.entries.push((name.to_string(), source));
+        true
+    }
+
+    pub fn add_bytes(&mut self, name: &str, bytes: Vec<u8>) -> bool {
+        self.add(name, Source::Bytes(bytes))
+    }
+
+    pub fn add_entry(&mut self, name: &str, var: &Path) -> bool {
+        self.add(name, Source::Entry(var.to_path_buf()))
+    }
+
+    pub fn add_raw(&mut self, name: &str, var: &Path) -> bool {
+        self.add(name, Source::Raw(var.to_path_buf()))
+    }
+
+    // File entries other than meta.json, in the order they were added
+    pub fn content_names(&self) -> Vec<String> {
+        self.entries
+            .iter()
+            .filter(|(name, _)| name != "meta.json")
+            .map(|(name, _)| name.clone())
+            .collect()
+    }
+
+    fn read_source(name: &str, source: &Source) -> anyhow::Result<Vec<u8>> {
+        match source {
+            Source::Bytes(data) => Ok(data.clone()),
+            Source::Entry(var) | Source::Raw(var) => {
+                reader::read_whole(index::open_var(var)?.by_name(name)?)
+            }
+        }
+    }
+
+    // Generate meta.json for `package` when none was added, otherwise check its contentList
+    // against the entries, which VaM reports as broken when an item has no entry
+    fn check_meta(&mut self, package: &str) -> anyhow::Result<()> {
+        let content = self.content_names();
+        let Some(pos) = self
+            .entries
+            .iter()
+            .position(|(name, _)| name == "meta.json")
+        else {
+            let meta = Meta::generate(package, content);
+            self.add_bytes("meta.json", meta.to_vec());
+            return Ok(());
+        };
+        let bytes = Self::read_source("meta.json", &self.entries[pos].1)?;
+        let mut meta = match Meta::parse(&bytes) {
+            Ok(meta) => meta,
+            Err(e) => {
+                warning!("{package}: meta.json does not parse, kept as is: {e}");
+                return Ok(());
+            }
+        };
+        if meta.reconcile(package, &content) {
+            self.entries[pos].1 = Source::Bytes(meta.to_vec());
+        }
+        Ok(())
+    }
+
+    // Compresses `source` on a pool worker; None leaves it to the serial pass, which copies
+    // entries already in the builder's method as they are
+    fn compress_in_pool<'b>(
+        &self,
+        name: &str,
+        source: &'b Source,
+        options: SimpleFileOptions,
+        archives: &mut HashMap<&'b Path, ZipArchive<File>>,
+    ) -> Option<anyhow::Result<Vec<u8>>> {
+        let var = match source {
+            Source::Bytes(data) => return Some(compress_entry(name, data, options)),
+            Source::Entry(var) => var,
+            Source::Raw(_) => return None,
+        };
+        let archive = match archives.entry(var.as_path()) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => match index::open_var(var) {
+                Ok(archive) => entry.insert(archive),
+                Err(e) => return Some(Err(e)),
+            },
+        };
+        let mut read = || -> anyhow::Result<Option<Vec<u8>>> {
+            let index = archive
+                .index_for_name(name)
+                .ok_or(zip::result::ZipError::FileNotFound)?;
+            if self.copies_as_is(archive.by_index_raw(index)?.compression()) {
+                return Ok(None);
+            }
+            Ok(Some(reader::read_whole(archive.by_index(index)?)?))
+        };
+        match read() {
+            Ok(Some(data)) => Some(compress_entry(name, &data, options)),
+            Ok(None) => None,
+            Err(e) => Some(Err(e)),
+        }
+    }
+
+    // Whether an entry of another var compressed with `method` is copied without recompressing.
+    // Stored entries still go through start_file when aligning: a raw copy keeps no alignment,
+    // and copying stored data costs the same either way.
+    fn copies_as_is(&self, method: CompressionMethod) -> bool {
+        method == self.method
+            && (method != CompressionMethod::Stored || config::get().alignment == 0)
+    }
+
+    // Write to `dst` through a temporary file next to it, so `dst` is either complete or absent
+    pub fn write(mut self, dst: &Path) -> anyhow::Result<()> {
+        let file_name = dst.file_name().unwrap().to_string_lossy().to_string();
+        let package = file_name
+            .strip_suffix(".var")
+            .unwrap_or(&file_name)
+            .to_string();
+        crate::readonly::check(dst)?;
+        self.check_meta(&package)?;
+        fs::create_dir_all(dst.parent().unwrap())?;
+        let mut tmp_name = dst.file_name().unwrap().to_os_string();
+        tmp_name.push(".tmp");
+        let tmp = dst.with_file_name(tmp_name);
+        let written = self.write_zip(File::create(&tmp)?);
+        match written.and_then(|_| Ok(fs::rename(&tmp, dst)?)) {
+            Ok(()) => Ok(()),
+            Err(e) => {
+                let _ = fs::remove_file(&tmp);
+                Err(e)
+            }
+        }
+    }
+
+    fn write_zip(&self, file: File) -> anyhow::Result<()> {
+        let mut zip = ZipWriter::new(file);
+        let options = entry_options(self.method);
+        let mut archives: HashMap<&Path, ZipArchive<File>> = HashMap::new();
+        // Parallel compression holds whole compressed entries in memory
+        let parallel = self.method != CompressionMethod::Stored && !config::get().low_memory;
+        let threads = config::get().threads;
+        let pool = parallel.then(|| ThreadPool::new(threads));
+        // Each worker reads entries of other vars through archives of its own, kept open
+        // from batch to batch
+        let worker_archives: Vec<Mutex<HashMap<&Path, ZipArchive<File>>>> =
+            (0..threads).map(|_| Mutex::new(HashMap::new())).collect();
+        for batch in self.entries.chunks(if parallel { threads } else { 1 }) {
+            crate::queue::check_aborted()?;
+            let compressed: Vec<Mutex<Option<anyhow::Result<Vec<u8>>>>> =
+                batch.iter().map(|_| Mutex::new(None)).collect();
+            if let Some(pool) = &pool {
+                scope_with(pool, |scope| {
+                    for (((name, source), slot), archives) in batch
+                        .iter()
+                        .zip(compressed.iter())
+                        .zip(worker_archives.iter())
+                    {
+                        if matches!(source, Source::Raw(_)) {
+                            continue;
+                        }
+                        scope.execute(move || {
+                            let _guard = crate::queue::AbortOnPanic;
+                            let mut archives = archives.lock().unwrap();
+                            *slot.lock().unwrap() =
+                                self.compress_in_pool(name, source, options, &mut archives);
+                        });
+                    }
+                });
+            }
+            for ((name, source), slot) in batch.iter().zip(compressed) {
+                if let Some(data) = slot.into_inner().unwrap() {
+                    debug!("adding {name} ...");
+                    let mut single = ZipArchive::new(Cursor::new(data?))?;
+                    zip.raw_copy_file(single.by_index_raw(0)?)?;
+                    continue;
+                }
+                match source {
+                    Source::Bytes(data) => {
+                        zip.start_file(name.as_str(), options)?;
+                        zip.write_all(data)?;
+                    }
+                    Source::Entry(var) | Source::Raw(var) => {
+                        let archive = match archives.entry(var.as_path()) {
+                            Entry::Occupied(entry) => entry.into_mut(),
+                            Entry::Vacant(entry) => entry.insert(index::open_var(var)?),
+                        };
+                        let index = archive
+                            .index_for_name(name)
+                            .ok_or(zip::result::ZipError::FileNotFound)?;
+                        let method = archive.by_index_raw(index)?.compression();
+                        if matches!(source, Source::Raw(_)) || self.copies_as_is(method) {
+                            zip.raw_copy_file(archive.by_index_raw(index)?)?;
+                        } else {
+                            zip.start_file(name.as_str(), options)?;
+                            io::copy(&mut archive.by_index(index)?, &mut zip)?;
+                        }
+                    }
+                }
+            }
+        }
+        zip.finish()?;
+        Ok(())
+    }
+}
+
+// Compress one entry into a single-entry archive in memory, ready to be raw copied
+fn compress_entry(name: &str, data: &[u8], options: SimpleFileOptions) -> anyhow::Result<Vec<u8>> {
+    #[cfg(test)]
+    COMPRESSED_IN_POOL.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+    let mut single = ZipWriter::new(Cursor::new(Vec::new()));
+    single.start_file(name, options)?;
+    single.write_all(data)?;
+    Ok(single.finish()?.into_inner())
+}
+
+// Entries compressed by the pool so far, across every builder of the process
+#[cfg(test)]
+pub static COMPRESSED_IN_POOL: std::sync::atomic::AtomicUsize =
+    std::sync::atomic::AtomicUsize::new(0);
@@ -0,0 +1,108 @@
+use crate::console::{success, verbose};
+use crate::content;
+use path_slash::PathExt;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use walkdir::WalkDir;
+
+// Files VaM ships in its own Custom folder. A var that bundles an identical texture or morph
+// under the same path can leave it out when nothing refers to it with SELF:/, which would
+// only look inside the var
+pub struct BuiltIn {
+    // Lowercase `Custom/...` path -> size and file
+    files: HashMap<String, (u64, PathBuf)>,
+    hashes: Mutex<HashMap<String, Option<blake3::Hash>>>,
+}
+
+// Index the game's Custom folder, sizes only; files are hashed when a var entry matches
+pub fn load(vam_folder: &Path) -> BuiltIn {
+    let files: HashMap<String, (u64, PathBuf)> = WalkDir::new(vam_folder.join("Custom"))
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_file())
+        .filter_map(|e| {
+            let name = e.path().strip_prefix(vam_folder).ok()?.to_slash()?;
+            let size = e.metadata().ok()?.len();
+            Some((name.to_lowercase(), (size, e.into_path())))
+        })
+        .collect();
+    verbose!("Indexed {} built-in Custom files", files.len());
+    BuiltIn {
+        files,
+        hashes: Mutex::new(HashMap::new()),
+    }
+}
+
+impl BuiltIn {
+    fn candidate(&self, name: &str, size: u64) -> bool {
+        matches!(content::category(name), "textures" | "morphs")
+            && self
+                .files
+                .get(&name.to_lowercase())
+                .is_some_and(|(built_in, _)| *built_in == size)
+    }
+
+    fn hash(&self, name: &str) -> Option<blake3::Hash> {
+        let key = name.to_lowercase();
+        if let Some(hash) = self.hashes.lock().unwrap().get(&key) {
+            return *hash;
+        }
+        let (_, path) = self.files.get(&key)?;
+        let mut hasher = blake3::Hasher::new();
+        let hash = hasher
+            .update_reader(std::fs::File::open(path).ok()?)
+            .ok()
+            .map(|h| h.finalize());
+        self.hashes.lock().unwrap().insert(key, hash);
+        hash
+    }
+
+    // Names among `entries` (name, size) identical to a built-in file; `read` returns an
+    // entry's data, used for the matches and for text files that may refer to them
+    pub fn duplicates(
+        &self,
+        entries: &[(String, u64)],
+        read: impl Fn(&str) -> anyhow::Result<Vec<u8>>,
+    ) -> Vec<String> {
+        let candidates: Vec<&String> = entries
+            .iter()
+            .filter(|(name, size)| self.candidate(name, *size))
+            .map(|(name, _)| name)
+            .collect();
+        if candidates.is_empty() {
+            return Vec::new();
+        }
+        let mut referenced: HashSet<String> = HashSet::new();
+        for (name, _) in entries.iter().filter(|(name, _)| content::is_text(name)) {
+            if let Ok(bytes) = read(name) {
+                let text = String::from_utf8_lossy(&bytes);
+                referenced.extend(
+                    content::self_references(&text)
+                        .iter()
+                        .map(|r| r.to_lowercase()),
+                );
+            }
+        }
+        candidates
+            .into_iter()
+            .filter(|name| !referenced.contains(&name.to_lowercase()))
+            .filter(|name| {
+                read(name).is_ok_and(|bytes| Some(blake3::hash(&bytes)) == self.hash(name))
+            })
+            .cloned()
+            .collect()
+    }
+}
+
+pub fn report(target: &Path, left_out: usize, saved: u64) {
+    if left_out > 0 {
+        success!(
+            "Left {left_out} files VaM already ships out of {}, saved {}",
+            target.file_name().unwrap().to_string_lossy(),
+            crate::format_size(saved)
+        );
+    }
+}
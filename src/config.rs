@@ -0,0 +1,233 @@
+use crate::console::warning;
+use crate::maintain::Pass;
+use crate::maintain::ALL_PASSES;
+use clap::ValueEnum;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::OnceLock;
+
+#[derive(Clone)]
+pub struct Config {
+    pub threads: usize,
+    // Ask with a message box instead of the console when a run pauses on a full disk
+    pub pause_message_box: bool,
+    // Show message boxes at all; off leaves every message and question on the console
+    pub message_boxes: bool,
+    // Byte alignment of stored entries in merged vars, 0 disables it
+    pub alignment: u16,
+    // Extra VaM data folders (-vamoverridepath profiles) sharing this AddonPackages
+    pub profiles: Vec<PathBuf>,
+    // Two workers and no whole-entry buffers, for 8 GB machines
+    pub low_memory: bool,
+    // Keep a running total of reclaimed space in VarCleaner/savings.json
+    pub track_savings: bool,
+    // Drop var textures and morphs identical to files in VaM's own Custom folder
+    pub strip_builtin: bool,
+    // Keep meta.json, entry lists and scene JSON in VarCleaner/Cache/json for later runs
+    pub json_cache: bool,
+    // Most packages merged at once from each drive, e.g. D: = 1 for a slow hard drive; drives
+    // not listed only share the overall thread count
+    pub drive_threads: HashMap<String, usize>,
+    // Passes `maintain` runs when none are given
+    pub maintenance: Vec<Pass>,
+    // Vars changed more recently than this are left for the next run, 0 takes them all
+    pub defer_minutes: u64,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            threads: 12,
+            pause_message_box: true,
+            message_boxes: true,
+            alignment: 4096,
+            profiles: Vec::new(),
+            low_memory: false,
+            track_savings: false,
+            strip_builtin: false,
+            json_cache: true,
+            drive_threads: HashMap::new(),
+            maintenance: ALL_PASSES.to_vec(),
+            defer_minutes: 5,
+        }
+    }
+}
+
+// Every layer only overrides the keys it sets
+#[derive(Deserialize, Default)]
+struct ConfigLayer {
+    threads: Option<usize>,
+    pause_message_box: Option<bool>,
+    message_boxes: Option<bool>,
+    alignment: Option<u16>,
+    profiles: Option<Vec<PathBuf>>,
+    low_memory: Option<bool>,
+    track_savings: Option<bool>,
+    strip_builtin: Option<bool>,
+    json_cache: Option<bool>,
+    drive_threads: Option<HashMap<String, usize>>,
+    maintenance: Option<Vec<Pass>>,
+    defer_minutes: Option<u64>,
+}
+
+impl Config {
+    fn apply(&mut self, layer: ConfigLayer) {
+        if let Some(threads) = layer.threads {
+            self.threads = threads.max(1);
+        }
+        if let Some(pause_message_box) = layer.pause_message_box {
+            self.pause_message_box = pause_message_box;
+        }
+        if let Some(message_boxes) = layer.message_boxes {
+            self.message_boxes = message_boxes;
+        }
+        match layer.alignment {
+            Some(alignment @ (0 | 4 | 4096)) => self.alignment = alignment,
+            Some(alignment) => warning!("Ignore alignment {alignment}, use 0, 4 or 4096"),
+            None => {}
+        }
+        if let Some(profiles) = layer.profiles {
+            self.profiles = profiles;
+        }
+        if let Some(low_memory) = layer.low_memory {
+            self.low_memory = low_memory;
+        }
+        if let Some(track_savings) = layer.track_savings {
+            self.track_savings = track_savings;
+        }
+        if let Some(strip_builtin) = layer.strip_builtin {
+            self.strip_builtin = strip_builtin;
+        }
+        if let Some(json_cache) = layer.json_cache {
+            self.json_cache = json_cache;
+        }
+        if let Some(drive_threads) = layer.drive_threads {
+            self.drive_threads = drive_threads
+                .into_iter()
+                .map(|(drive, threads)| (crate::drives::normalize(&drive), threads.max(1)))
+                .collect();
+        }
+        if let Some(maintenance) = layer.maintenance {
+            self.maintenance = maintenance;
+        }
+        if let Some(defer_minutes) = layer.defer_minutes {
+            self.defer_minutes = defer_minutes;
+        }
+    }
+}
+
+static CONFIG: OnceLock<Config> = OnceLock::new();
+
+// Layers in increasing priority: next to the exe (portable), %APPDATA% or ~/.config (per user),
+// environment
+fn config_files() -> Vec<PathBuf> {
+    let mut result = Vec::new();
+    if let Some(dir) = env::current_exe()
+        .ok()
+        .and_then(|exe| exe.parent().map(|p| p.to_path_buf()))
+    {
+        result.push(dir.join("VarCleaner.toml"));
+    }
+    if let Some(folder) = crate::platform::config_folder() {
+        result.push(folder.join("VarCleaner/config.toml"));
+    }
+    result
+}
+
+fn env_value<T: FromStr>(name: &str) -> Option<T> {
+    let value = env::var(name).ok()?;
+    match value.parse() {
+        Ok(parsed) => Some(parsed),
+        Err(_) => {
+            warning!("Ignore invalid {name}={value}");
+            None
+        }
+    }
+}
+
+// VARCLEANER_DRIVE_THREADS=D:=1,E:=2
+fn env_drive_threads() -> Option<HashMap<String, usize>> {
+    let value = env::var("VARCLEANER_DRIVE_THREADS").ok()?;
+    let parsed: Option<HashMap<String, usize>> = value
+        .split(',')
+        .map(|pair| {
+            let (drive, threads) = pair.rsplit_once('=')?;
+            Some((drive.trim().to_string(), threads.trim().parse().ok()?))
+        })
+        .collect();
+    if parsed.is_none() {
+        warning!("Ignore invalid VARCLEANER_DRIVE_THREADS={value}");
+    }
+    parsed
+}
+
+// VARCLEANER_MAINTENANCE=scan,merge,report
+fn env_maintenance() -> Option<Vec<Pass>> {
+    let value = env::var("VARCLEANER_MAINTENANCE").ok()?;
+    let parsed: Option<Vec<Pass>> = value
+        .split(',')
+        .map(|pass| Pass::from_str(pass.trim(), true).ok())
+        .collect();
+    if parsed.is_none() {
+        warning!("Ignore invalid VARCLEANER_MAINTENANCE={value}");
+    }
+    parsed
+}
+
+fn env_layer() -> ConfigLayer {
+    ConfigLayer {
+        threads: env_value("VARCLEANER_THREADS"),
+        pause_message_box: env_value("VARCLEANER_PAUSE_MESSAGE_BOX"),
+        message_boxes: env_value("VARCLEANER_MESSAGE_BOXES"),
+        alignment: env_value("VARCLEANER_ALIGNMENT"),
+        profiles: env::var_os("VARCLEANER_PROFILES")
+            .map(|value| env::split_paths(&value).collect()),
+        low_memory: env_value("VARCLEANER_LOW_MEMORY"),
+        track_savings: env_value("VARCLEANER_TRACK_SAVINGS"),
+        strip_builtin: env_value("VARCLEANER_STRIP_BUILTIN"),
+        json_cache: env_value("VARCLEANER_JSON_CACHE"),
+        drive_threads: env_drive_threads(),
+        maintenance: env_maintenance(),
+        defer_minutes: env_value("VARCLEANER_DEFER_MINUTES"),
+    }
+}
+
+pub fn load() -> Config {
+    let mut config = Config::default();
+    for path in config_files() {
+        let text = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(_) => continue,
+        };
+        match toml::from_str::<ConfigLayer>(&text) {
+            Ok(layer) => config.apply(layer),
+            Err(e) => warning!("Ignore invalid config {}: {e}", path.to_string_lossy()),
+        }
+    }
+    config.apply(env_layer());
+    config
+}
+
+// Called once at startup, before anything reads the config; --threads wins over the config
+// files, --low-memory still caps it
+pub fn init(low_memory: bool, threads: Option<usize>) {
+    CONFIG.get_or_init(|| {
+        let mut config = load();
+        if let Some(threads) = threads {
+            config.threads = threads.max(1);
+        }
+        config.low_memory |= low_memory;
+        if config.low_memory {
+            config.threads = config.threads.min(2);
+        }
+        config
+    });
+}
+
+pub fn get() -> &'static Config {
+    CONFIG.get_or_init(load)
+}
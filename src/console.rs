@@ -0,0 +1,93 @@
+use std::io::IsTerminal;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::AtomicU8;
+use std::sync::atomic::Ordering;
+
+#[derive(Clone, Copy)]
+pub enum Level {
+    Error,
+    Summary,
+    Warn,
+    Success,
+    Info,
+    Verbose,
+    Debug,
+}
+
+impl Level {
+    // Lowest verbosity the message is shown at: 1 with -q, 2 by default, 3 with -v, 4 with -vv
+    fn visibility(self) -> u8 {
+        match self {
+            Level::Error => 0,
+            Level::Summary => 1,
+            Level::Warn | Level::Success | Level::Info => 2,
+            Level::Verbose => 3,
+            Level::Debug => 4,
+        }
+    }
+
+    fn color(self) -> Option<&'static str> {
+        match self {
+            Level::Error => Some("31"),
+            Level::Summary | Level::Success => Some("32"),
+            Level::Warn => Some("33"),
+            Level::Debug => Some("2"),
+            Level::Info | Level::Verbose => None,
+        }
+    }
+}
+
+static VERBOSITY: AtomicU8 = AtomicU8::new(2);
+static COLOR: AtomicBool = AtomicBool::new(false);
+
+pub fn init(quiet: bool, verbose: u8, ansi_supported: bool) {
+    let verbosity = if quiet { 1 } else { 2 + verbose };
+    VERBOSITY.store(verbosity, Ordering::Relaxed);
+    let color =
+        ansi_supported && std::io::stdout().is_terminal() && std::env::var_os("NO_COLOR").is_none();
+    COLOR.store(color, Ordering::Relaxed);
+}
+
+pub fn enabled(level: Level) -> bool {
+    level.visibility() <= VERBOSITY.load(Ordering::Relaxed)
+}
+
+pub fn print(level: Level, message: &str) {
+    crate::eventlog::report(level, message);
+    if !enabled(level) {
+        return;
+    }
+    match level.color() {
+        Some(code) if COLOR.load(Ordering::Relaxed) => println!("\x1b[{code}m{message}\x1b[0m"),
+        _ => println!("{message}"),
+    }
+}
+
+macro_rules! error {
+    ($($arg:tt)*) => { $crate::console::print($crate::console::Level::Error, &format!($($arg)*)) };
+}
+macro_rules! summary {
+    ($($arg:tt)*) => { $crate::console::print($crate::console::Level::Summary, &format!($($arg)*)) };
+}
+macro_rules! warning {
+    ($($arg:tt)*) => { $crate::console::print($crate::console::Level::Warn, &format!($($arg)*)) };
+}
+macro_rules! success {
+    ($($arg:tt)*) => { $crate::console::print($crate::console::Level::Success, &format!($($arg)*)) };
+}
+macro_rules! info {
+    ($($arg:tt)*) => { $crate::console::print($crate::console::Level::Info, &format!($($arg)*)) };
+}
+macro_rules! verbose {
+    ($($arg:tt)*) => { $crate::console::print($crate::console::Level::Verbose, &format!($($arg)*)) };
+}
+macro_rules! debug {
+    ($($arg:tt)*) => { $crate::console::print($crate::console::Level::Debug, &format!($($arg)*)) };
+}
+pub(crate) use debug;
+pub(crate) use error;
+pub(crate) use info;
+pub(crate) use success;
+pub(crate) use summary;
+pub(crate) use verbose;
+pub(crate) use warning;
@@ -0,0 +1,258 @@
+use crate::backup;
+use crate::builder::VarBuilder;
+use crate::console::{error, success, summary, verbose, warning};
+use crate::content;
+use crate::index;
+use crate::journal::Journal;
+use crate::journal::Operation;
+use crate::protect::Protected;
+use crate::reader::VarReader;
+use crate::root;
+use serde_json::json;
+use serde_json::Map;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+use walkdir::WalkDir;
+
+struct AssetVar {
+    path: PathBuf,
+    package: String,
+    category: &'static str,
+    meta: Option<Value>,
+}
+
+const ASSET_CATEGORIES: [&str; 7] = [
+    "textures", "morphs", "audio", "plugins", "hair", "clothing", "assets",
+];
+
+// A var is consolidated only when every content entry falls into a single asset category
+fn classify(path: &Path) -> anyhow::Result<Option<AssetVar>> {
+    let var_index = crate::jsoncache::index(path)?;
+    let categories: HashSet<&'static str> = var_index
+        .entries
+        .iter()
+        .filter(|e| e.name.contains('/'))
+        .map(|e| content::category(&e.name))
+        .collect();
+    if categories.len() != 1 {
+        return Ok(None);
+    }
+    let category = categories.into_iter().next().unwrap();
+    if !ASSET_CATEGORIES.contains(&category) {
+        return Ok(None);
+    }
+    Ok(Some(AssetVar {
+        path: path.to_path_buf(),
+        package: var_index.package,
+        category,
+        meta: var_index.meta,
+    }))
+}
+
+// Packages that other vars or loose saves point at must keep their name
+fn referenced_packages(
+    vam_folder: &Path,
+    var_folder: &Path,
+    candidates: &[AssetVar],
+) -> HashSet<String> {
+    let mut referenced = crate::deps::depended_on(&index::installed_packages(var_folder));
+
+    for entry in root::profiles(vam_folder)
+        .iter()
+        .flat_map(|profile| WalkDir::new(profile.join("Saves")))
+        .filter_map(|e| e.ok())
+    {
+        let name = entry.file_name().to_string_lossy().to_ascii_lowercase();
+        if !entry.file_type().is_file() || !(name.ends_with(".json") || name.ends_with(".vap")) {
+            continue;
+        }
+        let text = match fs::read(entry.path()) {
+            Ok(bytes) => String::from_utf8_lossy(&bytes).to_string(),
+            Err(_) => continue,
+        };
+        for var in candidates.iter() {
+            let base = var
+                .package
+                .rsplit_once('.')
+                .map(|(b, _)| b)
+                .unwrap_or(&var.package);
+            if text.contains(&format!("{base}.")) {
+                referenced.insert(var.package.clone());
+            }
+        }
+    }
+    referenced
+}
+
+fn next_free_name(var_folder: &Path, base: &str) -> String {
+    let installed = index::installed_packages(var_folder);
+    let mut version = 1;
+    while installed.contains_key(&format!("{base}.{version}")) {
+        version += 1;
+    }
+    format!("{base}.{version}")
+}
+
+fn build_meta(
+    creator: &str,
+    package_name: &str,
+    sources: &[&AssetVar],
+    content_list: Vec<String>,
+) -> Value {
+    let source_names: HashSet<&str> = sources.iter().map(|v| v.package.as_str()).collect();
+    let mut dependencies = Map::new();
+    let mut licenses = HashSet::new();
+    let mut preload = false;
+    for var in sources.iter() {
+        let meta = match &var.meta {
+            Some(meta) => meta,
+            None => continue,
+        };
+        if let Some(deps) = meta.get("dependencies").and_then(|d| d.as_object()) {
+            for (name, value) in deps {
+                if !source_names.contains(name.as_str()) {
+                    dependencies.entry(name.clone()).or_insert(value.clone());
+                }
+            }
+        }
+        if let Some(license) = meta.get("licenseType").and_then(|l| l.as_str()) {
+            licenses.insert(license.to_string());
+        }
+        preload |= meta
+            .pointer("/customOptions/preloadMorphs")
+            .and_then(|p| p.as_str())
+            == Some("true");
+    }
+    let license = if licenses.len() == 1 {
+        licenses.into_iter().next().unwrap()
+    } else {
+        "Questionable".to_string()
+    };
+    let mut names: Vec<&str> = source_names.into_iter().collect();
+    names.sort();
+    json!({
+        "licenseType": license,
+        "creatorName": creator,
+        "packageName": package_name,
+        "standardReferenceVersionOption": "Latest",
+        "scriptReferenceVersionOption": "Exact",
+        "description": format!("Consolidated by VarCleaner from: {}", names.join(", ")),
+        "credits": "",
+        "instructions": "",
+        "promotionalLink": "",
+        "programVersion": "",
+        "contentList": content_list,
+        "dependencies": dependencies,
+        "customOptions": { "preloadMorphs": if preload { "true" } else { "false" } },
+        "hadReferenceIssues": "false",
+        "referenceIssues": [],
+    })
+}
+
+fn write_consolidated(
+    target: &Path,
+    creator: &str,
+    package_name: &str,
+    sources: &[&AssetVar],
+) -> anyhow::Result<()> {
+    let mut builder = VarBuilder::new(zip::CompressionMethod::Stored);
+    for var in sources.iter() {
+        for entry in VarReader::open(&var.path)?.entries()? {
+            if &*entry.name != "meta.json" {
+                builder.add_raw(&entry.name, &var.path);
+            }
+        }
+    }
+    let meta = build_meta(creator, package_name, sources, builder.content_names());
+    builder.add_bytes("meta.json", serde_json::to_vec_pretty(&meta)?);
+    builder.write(target)
+}
+
+pub fn run(vam_folder: &Path, creator: &str, max_size: u64, protected: &Protected) {
+    let var_folder = &root::addon_packages(vam_folder);
+    let backup_folder = &crate::root::backup_folder(vam_folder);
+    let paths: Vec<PathBuf> = index::installed_packages(var_folder)
+        .into_iter()
+        .filter(|(package, path)| {
+            package
+                .split('.')
+                .next()
+                .is_some_and(|c| c.eq_ignore_ascii_case(creator))
+                && fs::metadata(path).is_ok_and(|m| m.len() <= max_size)
+                && !protected.skips(path)
+        })
+        .map(|(_, path)| path)
+        .collect();
+    let mut candidates = Vec::new();
+    for path in paths.iter() {
+        match classify(path) {
+            Ok(Some(var)) => candidates.push(var),
+            Ok(None) => {}
+            Err(_) => warning!("zipfile {} is invaild", path.to_string_lossy()),
+        }
+    }
+    let referenced = referenced_packages(vam_folder, var_folder, &candidates);
+    let mut groups: BTreeMap<&'static str, Vec<&AssetVar>> = BTreeMap::new();
+    for var in candidates.iter() {
+        if referenced.contains(&var.package) {
+            warning!("Skip {}, other packages or saves reference it", var.package);
+            continue;
+        }
+        groups.entry(var.category).or_default().push(var);
+    }
+
+    let mut journal = Journal::new("consolidate");
+    for (category, mut sources) in groups.into_iter() {
+        if sources.len() < 2 {
+            continue;
+        }
+        sources.sort_by(|a, b| a.package.cmp(&b.package));
+        let mut chars = category.chars();
+        let title: String = chars.next().unwrap().to_uppercase().chain(chars).collect();
+        let package_name = format!("Consolidated{title}");
+        let full_name = next_free_name(var_folder, &format!("{creator}.{package_name}"));
+        let target = var_folder
+            .join("consolidated")
+            .join(format!("{full_name}.var"));
+        if let Err(e) = write_consolidated(&target, creator, &package_name, &sources) {
+            error!("Failed to consolidate {category} of {creator}: {e}");
+            let _ = fs::remove_file(&target);
+            continue;
+        }
+        journal.record(Operation::Create {
+            path: target.clone(),
+            hash: None,
+        });
+        for var in sources.iter() {
+            let backup_path =
+                backup::free_path(backup_folder.join(var.path.strip_prefix(var_folder).unwrap()));
+            match backup::move_verified(&var.path, &backup_path) {
+                Ok(()) => journal.record(Operation::Move {
+                    from: var.path.clone(),
+                    to: backup_path,
+                }),
+                Err(e) => error!(
+                    "Failed to back up {}, it stays next to {full_name}: {e}",
+                    var.path.to_string_lossy()
+                ),
+            }
+        }
+        success!(
+            "Consolidated {} {category} vars into {full_name}",
+            sources.len()
+        );
+        success!("已将{}个{category}类Var合并为{full_name}", sources.len());
+    }
+    if journal.operations.is_empty() {
+        summary!("Nothing to consolidate / 没有可合并的Var");
+        return;
+    }
+    match journal.save(&vam_folder.join("VarCleaner/Journal")) {
+        Ok(journal_path) => verbose!("Journal written to {}", journal_path.to_string_lossy()),
+        Err(e) => error!("Failed to write the journal: {e}"),
+    }
+}
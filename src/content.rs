@@ -0,0 +1,75 @@
+const TEXT_EXTENSIONS: [&str; 5] = ["json", "vap", "vaj", "vam", "cslist"];
+
+// Scene, preset and item files that may point at other entries by path
+pub fn is_text(name: &str) -> bool {
+    let lower = name.to_ascii_lowercase();
+    TEXT_EXTENSIONS
+        .iter()
+        .any(|ext| lower.ends_with(&format!(".{ext}")))
+}
+
+// Text entries that hold JSON, everything but .cslist
+pub fn is_json(name: &str) -> bool {
+    is_text(name) && !name.to_ascii_lowercase().ends_with(".cslist")
+}
+
+// Categories `category` returns, meta.json aside
+pub const CATEGORIES: [&str; 10] = [
+    "scenes", "plugins", "hair", "clothing", "morphs", "audio", "textures", "assets", "looks",
+    "other",
+];
+
+pub fn parse_category(text: &str) -> Result<String, String> {
+    let text = text.trim().to_ascii_lowercase();
+    if CATEGORIES.contains(&text.as_str()) {
+        Ok(text)
+    } else {
+        Err(format!(
+            "unknown category, expected one of {}",
+            CATEGORIES.join(", ")
+        ))
+    }
+}
+
+// Content category of an entry, inferred from the folder layout VaM expects inside a var
+pub fn category(name: &str) -> &'static str {
+    let lower = name.to_ascii_lowercase();
+    let ext = lower.rsplit_once('.').map(|(_, ext)| ext).unwrap_or("");
+    if lower == "meta.json" {
+        "meta"
+    } else if lower.starts_with("saves/scene/") {
+        "scenes"
+    } else if lower.starts_with("custom/scripts/") || ext == "cs" || ext == "cslist" || ext == "dll"
+    {
+        "plugins"
+    } else if lower.starts_with("custom/hair/") {
+        "hair"
+    } else if lower.starts_with("custom/clothing/") {
+        "clothing"
+    } else if lower.contains("/morphs/") || ext == "vmi" || ext == "vmb" {
+        "morphs"
+    } else if ["wav", "mp3", "ogg"].contains(&ext) || lower.starts_with("custom/sounds/") {
+        "audio"
+    } else if lower.contains("/textures/") {
+        "textures"
+    } else if lower.starts_with("custom/assets/") || ext == "assetbundle" {
+        "assets"
+    } else if lower.starts_with("custom/atom/person/") || lower.starts_with("saves/person/") {
+        "looks"
+    } else {
+        "other"
+    }
+}
+
+// Targets of the `SELF:/path` references in a scene, preset or item file
+pub fn self_references(text: &str) -> Vec<&str> {
+    let mut result = Vec::new();
+    let mut rest = text;
+    while let Some(pos) = rest.find("SELF:/") {
+        let after = &rest[pos + "SELF:/".len()..];
+        let end = after.find('"').unwrap_or(after.len());
+        result.push(&after[..end]);
+        rest = &after[end..];
+    }
+    result
+}
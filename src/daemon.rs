@@ -0,0 +1,158 @@
+use crate::console::{info, warning};
+use crate::platform;
+use crate::protect::Protected;
+use crate::queue;
+use crate::CleanArgs;
+use serde::Deserialize;
+use serde_json::json;
+use serde_json::Value;
+use std::io;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::Write;
+use std::path::Path;
+use std::sync::Mutex;
+
+// One request per line: {"id": 1, "method": "scan" | "merge" | "restore", "params": {...}}
+// "restore" undoes clean runs like the restore command, only the one given by {"run_id": ...}
+// when set; "restore-version" ({"package": ...}) rebuilds a version archive-versions stored
+// "pause", "resume", "skip" and "prioritize" ({"package": ...}) steer a merge running for
+// another client
+// One response per line: {"id": 1, "result": ...} or {"id": 1, "error": "..."}
+#[derive(Deserialize)]
+struct Request {
+    #[serde(default)]
+    id: Value,
+    method: String,
+    #[serde(default)]
+    params: Value,
+}
+
+#[derive(Deserialize)]
+struct PackageParams {
+    package: String,
+}
+
+#[derive(Deserialize, Default)]
+struct RestoreParams {
+    #[serde(default)]
+    run_id: Option<String>,
+}
+
+fn scan(vam_folder: &Path) -> anyhow::Result<Value> {
+    let var_folder = &crate::root::addon_packages(vam_folder);
+    let groups = crate::generate_duplicate_var_files(var_folder)?;
+    let duplicates: Vec<Value> = groups
+        .values()
+        .filter(|copies| copies.len() > 1)
+        .map(|copies| {
+            let copies: Vec<String> = copies
+                .iter()
+                .map(|p| {
+                    p.strip_prefix(var_folder)
+                        .unwrap()
+                        .to_string_lossy()
+                        .to_string()
+                })
+                .collect();
+            json!({ "live": copies[0], "copies": copies })
+        })
+        .collect();
+    Ok(json!({ "duplicates": duplicates }))
+}
+
+// Only one request may change the library at a time
+static CHANGING: Mutex<()> = Mutex::new(());
+
+fn handle(vam_folder: &Path, protected: &Protected, request: &Request) -> anyhow::Result<Value> {
+    let _changing = match request.method.as_str() {
+        "merge" | "restore" | "restore-version" => match CHANGING.try_lock() {
+            Ok(guard) => Some(guard),
+            Err(_) => anyhow::bail!("another merge or restore is running"),
+        },
+        _ => None,
+    };
+    match request.method.as_str() {
+        "scan" => scan(vam_folder),
+        "merge" => {
+            let args: CleanArgs = match &request.params {
+                Value::Null => CleanArgs::default(),
+                params => serde_json::from_value(params.clone())?,
+            };
+            crate::clean(vam_folder, &args, protected)?;
+            Ok(json!({ "done": true }))
+        }
+        "restore" => {
+            let params: RestoreParams = match &request.params {
+                Value::Null => RestoreParams::default(),
+                params => serde_json::from_value(params.clone())?,
+            };
+            match params.run_id {
+                Some(run_id) => crate::backup::restore_run(vam_folder, &run_id, true)?,
+                None => crate::backup::restore(vam_folder, true)?,
+            }
+            Ok(json!({ "done": true }))
+        }
+        "restore-version" => {
+            let params: PackageParams = serde_json::from_value(request.params.clone())?;
+            crate::delta::restore(vam_folder, &params.package, true)?;
+            Ok(json!({ "done": true }))
+        }
+        "pause" => {
+            queue::pause();
+            Ok(json!({ "done": true }))
+        }
+        "resume" => {
+            queue::resume();
+            Ok(json!({ "done": true }))
+        }
+        "skip" | "prioritize" => {
+            let params: PackageParams = serde_json::from_value(request.params.clone())?;
+            if request.method == "skip" {
+                queue::skip(&params.package);
+            } else {
+                queue::prioritize(&params.package);
+            }
+            Ok(json!({ "done": true }))
+        }
+        method => anyhow::bail!("unknown method {method}"),
+    }
+}
+
+fn serve_client(
+    vam_folder: &Path,
+    protected: &Protected,
+    pipe: platform::Connection,
+) -> io::Result<()> {
+    let mut writer = pipe.try_clone()?;
+    for line in BufReader::new(pipe).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let response = match serde_json::from_str::<Request>(&line) {
+            Ok(request) => match handle(vam_folder, protected, &request) {
+                Ok(result) => json!({ "id": request.id, "result": result }),
+                Err(e) => json!({ "id": request.id, "error": e.to_string() }),
+            },
+            Err(e) => json!({ "id": null, "error": format!("invalid request: {e}") }),
+        };
+        writeln!(writer, "{response}")?;
+        writer.flush()?;
+    }
+    Ok(())
+}
+
+// Every client gets its own pipe instance and thread, so one can steer another's merge
+pub fn serve(vam_folder: &Path, pipe_name: &str, protected: &Protected) -> anyhow::Result<()> {
+    let listener = platform::listen(pipe_name)?;
+    info!("Listening on {pipe_name}");
+    std::thread::scope(|threads| loop {
+        let pipe = listener.accept()?;
+        threads.spawn(move || {
+            if let Err(e) = serve_client(vam_folder, protected, pipe) {
+                warning!("Client disconnected: {e}");
+            }
+        });
+    })
+}
@@ -0,0 +1,64 @@
+use crate::console::warning;
+use serde::Deserialize;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Decision {
+    Merge,
+    // Leave every copy where it is
+    Keep,
+}
+
+// Answers given for duplicate groups, kept in VarCleaner/decisions.json and applied again on
+// later runs without asking. A group is known by its package and the hashes of its copies, so
+// a copy that changes or a new copy makes it a new question.
+pub struct Decisions {
+    path: PathBuf,
+    entries: Mutex<BTreeMap<String, Decision>>,
+}
+
+pub fn key(package: &str, hashes: &[String]) -> String {
+    let mut hashes = hashes.to_vec();
+    hashes.sort();
+    format!("{}:{}", package.to_lowercase(), hashes.join(","))
+}
+
+impl Decisions {
+    pub fn load(vam_folder: &Path) -> Decisions {
+        let path = vam_folder.join("VarCleaner/decisions.json");
+        let entries = fs::read(&path)
+            .ok()
+            .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+            .unwrap_or_default();
+        Decisions {
+            path,
+            entries: Mutex::new(entries),
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<Decision> {
+        self.entries.lock().unwrap().get(key).copied()
+    }
+
+    pub fn remember(&self, key: String, decision: Decision) {
+        self.entries.lock().unwrap().insert(key, decision);
+        self.save();
+    }
+
+    // Saved after every answer, so a run stopped halfway still remembers what it was told
+    fn save(&self) {
+        let entries = self.entries.lock().unwrap();
+        let result = crate::readonly::check(&self.path)
+            .and_then(|_| fs::create_dir_all(self.path.parent().unwrap()))
+            .and_then(|_| fs::write(&self.path, serde_json::to_vec_pretty(&*entries).unwrap()));
+        if let Err(e) = result {
+            warning!("Failed to save {}: {e}", self.path.to_string_lossy());
+        }
+    }
+}
@@ -0,0 +1,145 @@
+use crate::budget::ChangeLimit;
+use crate::console::{error, info, summary, warning};
+use crate::hashing::HashCache;
+use crate::index;
+use crate::package::PackageId;
+use crate::reader::VarReader;
+use crate::stats::RunLog;
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+
+// Byte-identical vars under names of different packages, e.g. Creator.Pkg.1.var saved again
+// by a downloader as pkg_fixed.var. Clean groups copies by name, so these never meet. Only
+// vars of the same size are hashed; groups keep the order of `vars`.
+pub fn identical_groups(vars: &[PathBuf], hashes: &HashCache) -> Vec<Vec<PathBuf>> {
+    let mut by_size: HashMap<u64, Vec<&PathBuf>> = HashMap::new();
+    for var in vars {
+        if let Ok(metadata) = fs::metadata(var) {
+            by_size.entry(metadata.len()).or_default().push(var);
+        }
+    }
+    let candidates: Vec<&PathBuf> = by_size
+        .into_values()
+        .filter(|same_size| names(same_size.iter().copied()) > 1)
+        .flatten()
+        .collect();
+    let mut by_hash: HashMap<String, Vec<PathBuf>> = HashMap::new();
+    for (var, hash) in candidates
+        .iter()
+        .zip(hashes.hash_all(candidates.iter().copied()))
+    {
+        if let Ok(hash) = hash {
+            by_hash.entry(hash).or_default().push(var.to_path_buf());
+        }
+    }
+    let order: HashMap<&PathBuf, usize> = vars.iter().enumerate().map(|(i, v)| (v, i)).collect();
+    let mut groups: Vec<Vec<PathBuf>> = by_hash
+        .into_values()
+        .filter(|group| names(group.iter()) > 1)
+        .map(|mut group| {
+            group.sort_by_key(|var| order[var]);
+            group
+        })
+        .collect();
+    groups.sort_by_key(|group| order[&group[0]]);
+    groups
+}
+
+// Packages among `vars`, a " (1)" download copy being the package it was saved from
+fn names<'a>(vars: impl Iterator<Item = &'a PathBuf>) -> usize {
+    let mut names: Vec<String> = vars
+        .map(|var| {
+            index::download_base_name(&var.file_name().unwrap().to_string_lossy()).to_lowercase()
+        })
+        .collect();
+    names.sort();
+    names.dedup();
+    names.len()
+}
+
+// Whether the file name is the package meta.json says the var is; VaM only reads the version
+// from the name, so Creator.Pkg.1.var and Creator.Pkg.2.var both are
+fn named_after_meta(var: &Path) -> bool {
+    let Some(id) = PackageId::parse(&index::package_name(var)) else {
+        return false;
+    };
+    let Ok(mut reader) = VarReader::open(var) else {
+        return false;
+    };
+    let Some(meta) = reader.meta() else {
+        return false;
+    };
+    let same = |field: &str, name: &str| {
+        meta[field]
+            .as_str()
+            .is_some_and(|value| value.eq_ignore_ascii_case(name))
+    };
+    same("creatorName", &id.creator) && same("packageName", &id.name)
+}
+
+// Backs up the renamed copies of every group that has a copy named after its meta.json, that
+// copy and other versions of it stay; groups without one are only listed. Returns each var
+// moved to the backup with where it went.
+pub fn back_up_renamed(
+    groups: &[Vec<PathBuf>],
+    var_folder: &Path,
+    backup_folder: &Path,
+    limit: &ChangeLimit,
+    run_log: &RunLog,
+) -> Vec<(PathBuf, PathBuf)> {
+    let mut unnamed: BTreeMap<String, &Vec<PathBuf>> = BTreeMap::new();
+    let mut backed_up: Vec<(PathBuf, PathBuf)> = Vec::new();
+    for group in groups {
+        let (named, renamed): (Vec<&PathBuf>, Vec<&PathBuf>) =
+            group.iter().partition(|var| named_after_meta(var));
+        let Some(kept) = named.first() else {
+            unnamed.insert(index::package_name(&group[0]), group);
+            continue;
+        };
+        let kept = kept.file_name().unwrap().to_string_lossy().to_string();
+        let size: u64 = renamed
+            .iter()
+            .map(|var| fs::metadata(var).map_or(0, |m| m.len()))
+            .sum();
+        if renamed.is_empty() || !limit.allow(size) {
+            continue;
+        }
+        for var in renamed.iter() {
+            info!(
+                "{} is identical to {kept} / 与{kept}内容相同",
+                var.to_string_lossy()
+            );
+        }
+        let (moves, moved) =
+            crate::backup::move_all(renamed.iter().copied(), var_folder, backup_folder);
+        backed_up.extend(moves);
+        match moved {
+            Ok(()) => run_log.record(&kept, "renamed", size, 0),
+            Err(e) => {
+                error!("Failed to back up the renamed copies of {kept}: {e}");
+                run_log.record(&kept, "failed", size, size);
+            }
+        }
+    }
+    if !unnamed.is_empty() {
+        warning!("These vars are identical but none is named after its meta.json, left as they are / 以下Var内容相同，但没有一个与meta.json中的名称一致，未做处理:");
+        for group in unnamed.values() {
+            let names: Vec<String> = group
+                .iter()
+                .map(|var| var.file_name().unwrap().to_string_lossy().to_string())
+                .collect();
+            info!("  {}", names.join(", "));
+        }
+    }
+    if !backed_up.is_empty() {
+        summary!(
+            "Backed up {} renamed copies of other vars / 已备份{}个改名的重复Var",
+            backed_up.len(),
+            backed_up.len()
+        );
+    }
+    backed_up
+}
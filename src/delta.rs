@@ -0,0 +1,267 @@
+use crate::console::{error, info, success, summary};
+use crate::format_size;
+use crate::index;
+use crate::journal::Journal;
+use crate::journal::Operation;
+use crate::protect::Protected;
+use crate::reader::VarReader;
+use crate::versions;
+use path_slash::PathExt;
+use serde::Deserialize;
+use serde::Serialize;
+use std::collections::HashSet;
+use std::fs;
+use std::fs::File;
+use std::io::Read;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+use zip::write::SimpleFileOptions;
+use zip::ZipWriter;
+
+const MANIFEST_NAME: &str = "varcleaner-delta.json";
+
+#[derive(Serialize, Deserialize)]
+struct DeltaManifest {
+    package: String,
+    base: String,
+    // Original location relative to AddonPackages
+    original_path: PathBuf,
+    // Every entry of the original var in order, with the ones to take from the base marked
+    entries: Vec<String>,
+    from_base: Vec<String>,
+}
+
+fn delta_path(archive_folder: &Path, package: &str) -> PathBuf {
+    archive_folder.join(format!("{package}.delta.var"))
+}
+
+fn write_delta(
+    old: &Path,
+    base: &Path,
+    target: &Path,
+    manifest: &mut DeltaManifest,
+) -> anyhow::Result<()> {
+    let base_index = VarReader::open(base)?.index()?;
+    let base_entries: HashSet<(&str, u64, u32)> = base_index
+        .entries
+        .iter()
+        .map(|e| (&*e.name, e.size, e.crc32))
+        .collect();
+
+    let mut archive = index::open_var(old)?;
+    crate::readonly::check(target)?;
+    let mut zip = ZipWriter::new(File::create(target)?);
+    for i in 0..archive.len() {
+        let file = archive.by_index_raw(i)?;
+        let name = file.name().to_string();
+        manifest.entries.push(name.clone());
+        if !file.is_dir() && base_entries.contains(&(name.as_str(), file.size(), file.crc32())) {
+            manifest.from_base.push(name);
+        } else {
+            zip.raw_copy_file(file)?;
+        }
+    }
+    zip.start_file(MANIFEST_NAME, SimpleFileOptions::default())?;
+    zip.write_all(&serde_json::to_vec_pretty(manifest)?)?;
+    zip.finish()?;
+    Ok(())
+}
+
+fn read_manifest(delta: &Path) -> anyhow::Result<DeltaManifest> {
+    let mut archive = index::open_var(delta)?;
+    let mut buffer = Vec::new();
+    archive.by_name(MANIFEST_NAME)?.read_to_end(&mut buffer)?;
+    Ok(serde_json::from_slice(&buffer)?)
+}
+
+// Rebuild the original var at `target`; archived bases are rebuilt first into the archive folder
+fn rebuild(
+    var_folder: &Path,
+    archive_folder: &Path,
+    package: &str,
+    target: &Path,
+) -> anyhow::Result<()> {
+    let delta = delta_path(archive_folder, package);
+    let manifest = read_manifest(&delta)?;
+    let installed = index::installed_packages(var_folder);
+    let mut tmp_base = None;
+    let base = match installed.get(&manifest.base) {
+        Some(path) => path.clone(),
+        None => {
+            let path = archive_folder.join(format!("{}.rebuild.var", manifest.base));
+            rebuild(var_folder, archive_folder, &manifest.base, &path)?;
+            tmp_base = Some(path.clone());
+            path
+        }
+    };
+    let from_base: HashSet<&String> = manifest.from_base.iter().collect();
+    let mut base_archive = index::open_var(&base)?;
+    let mut delta_archive = index::open_var(&delta)?;
+    fs::create_dir_all(target.parent().unwrap())?;
+    crate::readonly::check(target)?;
+    let mut zip = ZipWriter::new(File::create(target)?);
+    for name in manifest.entries.iter() {
+        let source = if from_base.contains(name) {
+            &mut base_archive
+        } else {
+            &mut delta_archive
+        };
+        let i = match source.index_for_name(name) {
+            Some(i) => i,
+            None => anyhow::bail!("{name} is missing from the archive of {package}"),
+        };
+        zip.raw_copy_file(source.by_index_raw(i)?)?;
+    }
+    zip.finish()?;
+    if let Some(path) = tmp_base {
+        fs::remove_file(path)?;
+    }
+    Ok(())
+}
+
+// The original of `delta` written to `target` anywhere, the archive stays as it is
+pub fn rebuild_to(vam_folder: &Path, delta: &Path, target: &Path) -> anyhow::Result<()> {
+    let manifest = read_manifest(delta)?;
+    fs::create_dir_all(target.parent().unwrap())?;
+    rebuild(
+        &crate::root::addon_packages(vam_folder),
+        &vam_folder.join("VarCleaner/Archive"),
+        &manifest.package,
+        target,
+    )
+}
+
+pub fn archive_superseded(vam_folder: &Path, protected: &Protected) -> anyhow::Result<()> {
+    let var_folder = &crate::root::addon_packages(vam_folder);
+    let archive_folder = &vam_folder.join("VarCleaner/Archive");
+    crate::readonly::check(archive_folder)?;
+    fs::create_dir_all(archive_folder)?;
+    let installed = index::installed_packages(var_folder);
+    let mut journal = Journal::new("archive-versions");
+    let mut saved = 0;
+    for (base_name, versions) in versions::group_versions(&installed) {
+        let (latest, latest_path) = versions.last().unwrap();
+        for (version, path) in versions.iter().take(versions.len() - 1) {
+            if protected.skips(path) {
+                continue;
+            }
+            let package = format!("{base_name}.{version}");
+            let target = delta_path(archive_folder, &package);
+            let mut manifest = DeltaManifest {
+                package: package.clone(),
+                base: format!("{base_name}.{latest}"),
+                original_path: path.strip_prefix(var_folder).unwrap().to_path_buf(),
+                entries: Vec::new(),
+                from_base: Vec::new(),
+            };
+            if let Err(e) = write_delta(path, latest_path, &target, &mut manifest) {
+                error!("Failed to archive {package}: {e}");
+                let _ = fs::remove_file(&target);
+                continue;
+            }
+            // The old version may be open in VaM; the delta goes then, the var stays
+            let sizes = fs::metadata(path)
+                .and_then(|before| Ok((before.len(), fs::metadata(&target)?.len())));
+            let removed = sizes.and_then(|sizes| {
+                crate::readonly::check(path)?;
+                fs::remove_file(path)?;
+                Ok(sizes)
+            });
+            let (before, after) = match removed {
+                Ok(sizes) => sizes,
+                Err(e) => {
+                    error!("Failed to archive {package}, it stays installed: {e}");
+                    let _ = fs::remove_file(&target);
+                    continue;
+                }
+            };
+            saved += before.saturating_sub(after);
+            success!(
+                "Archived {package} as delta of {}: {} -> {}",
+                manifest.base,
+                format_size(before),
+                format_size(after)
+            );
+            journal.record(Operation::Delta {
+                from: path.clone(),
+                to: target,
+            });
+        }
+    }
+    if journal.operations.is_empty() {
+        summary!("No superseded versions found / 没有旧版本Var");
+        return Ok(());
+    }
+    summary!(
+        "Archived {} old versions, saved {}",
+        journal.operations.len(),
+        format_size(saved)
+    );
+    summary!(
+        "已归档{}个旧版本Var, 节省{}",
+        journal.operations.len(),
+        format_size(saved)
+    );
+    if let Err(e) = journal.save(&vam_folder.join("VarCleaner/Journal")) {
+        error!("Failed to write the journal: {e}");
+    }
+    Ok(())
+}
+
+// What a restore changes: the var added back, the delta removed, and the archived
+// bases rebuilt on the way when they are not installed
+fn plan(var_folder: &Path, archive_folder: &Path, package: &str) -> anyhow::Result<Vec<String>> {
+    let delta = delta_path(archive_folder, package);
+    let manifest = read_manifest(&delta)?;
+    let from_delta = manifest.entries.len() - manifest.from_base.len();
+    let mut lines = vec![
+        format!(
+            "+ AddonPackages/{} ({} entries from {}, {from_delta} from the delta)",
+            manifest.original_path.to_slash_lossy(),
+            manifest.from_base.len(),
+            manifest.base
+        ),
+        format!(
+            "- VarCleaner/Archive/{}",
+            delta.file_name().unwrap().to_string_lossy()
+        ),
+    ];
+    let installed = index::installed_packages(var_folder);
+    if !installed.contains_key(&manifest.base) {
+        lines.push(format!(
+            "  {} is archived too and is rebuilt temporarily",
+            manifest.base
+        ));
+        for line in plan(var_folder, archive_folder, &manifest.base)? {
+            if line.starts_with("  ") {
+                lines.push(line);
+            }
+        }
+    }
+    Ok(lines)
+}
+
+pub fn restore(vam_folder: &Path, package: &str, assume_yes: bool) -> anyhow::Result<()> {
+    let var_folder = &crate::root::addon_packages(vam_folder);
+    let archive_folder = &vam_folder.join("VarCleaner/Archive");
+    let delta = delta_path(archive_folder, package);
+    let manifest = read_manifest(&delta)?;
+    let target = var_folder.join(&manifest.original_path);
+    if target.exists() {
+        anyhow::bail!("{} already exists", target.to_string_lossy());
+    }
+    for line in plan(var_folder, archive_folder, package)? {
+        info!("{line}");
+    }
+    if !assume_yes && !crate::ui::confirm("Apply these changes? / 确认执行？") {
+        anyhow::bail!("Cancelled, pass --yes to restore without asking / 已取消");
+    }
+    if let Err(e) = rebuild(var_folder, archive_folder, package, &target) {
+        let _ = fs::remove_file(&target);
+        return Err(e);
+    }
+    fs::remove_file(&delta)?;
+    summary!("Restored {}", target.to_string_lossy());
+    Ok(())
+}
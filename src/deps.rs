@@ -0,0 +1,123 @@
+use crate::console::{info, summary};
+use crate::index;
+use crate::package::PackageId;
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use threadpool::ThreadPool;
+use threadpool_scope::scope_with;
+
+// Every installed package with the dependencies its meta.json lists, nested ones included,
+// each resolved to the installed package VaM would load for it (None when nothing matches)
+pub struct Graph {
+    pub packages: BTreeMap<String, Vec<(PackageId, Option<String>)>>,
+}
+
+impl Graph {
+    pub fn build(installed: &HashMap<String, PathBuf>) -> Graph {
+        let packages = Mutex::new(BTreeMap::new());
+        let pool = ThreadPool::new(crate::config::get().threads);
+        scope_with(&pool, |scope| {
+            for (package, path) in installed.iter() {
+                let packages = &packages;
+                scope.execute(move || {
+                    let meta = crate::jsoncache::index(path).ok().and_then(|i| i.meta);
+                    let deps: Vec<(PackageId, Option<String>)> = meta
+                        .as_ref()
+                        .map(index::dependencies)
+                        .unwrap_or_default()
+                        .into_iter()
+                        .map(|dep| {
+                            let resolved = index::resolve_dependency(&dep, installed)
+                                .map(|(resolved, _)| resolved.clone());
+                            (dep, resolved)
+                        })
+                        .collect();
+                    packages.lock().unwrap().insert(package.clone(), deps);
+                });
+            }
+        });
+        Graph {
+            packages: packages.into_inner().unwrap(),
+        }
+    }
+
+    // Installed packages another package depends on
+    pub fn depended_on(&self) -> HashSet<String> {
+        self.packages
+            .iter()
+            .flat_map(|(package, deps)| {
+                deps.iter()
+                    .filter_map(|(_, resolved)| resolved.as_ref())
+                    .filter(move |resolved| *resolved != package)
+            })
+            .cloned()
+            .collect()
+    }
+
+    // Dependencies nothing installed satisfies, each with the packages asking for it
+    pub fn missing(&self) -> BTreeMap<String, BTreeSet<String>> {
+        let mut result: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+        for (package, deps) in self.packages.iter() {
+            for (dep, _) in deps.iter().filter(|(_, resolved)| resolved.is_none()) {
+                result
+                    .entry(dep.to_string())
+                    .or_default()
+                    .insert(package.clone());
+            }
+        }
+        result
+    }
+
+    // Installed packages no other installed package depends on: scenes and looks people open
+    // themselves, or leftovers of something removed
+    pub fn orphans(&self) -> Vec<String> {
+        let depended_on = self.depended_on();
+        self.packages
+            .keys()
+            .filter(|package| !depended_on.contains(*package))
+            .cloned()
+            .collect()
+    }
+}
+
+// Installed packages the meta.json of another installed var depends on, with .latest and
+// .min references resolved the way VaM resolves them
+pub fn depended_on(installed: &HashMap<String, PathBuf>) -> HashSet<String> {
+    Graph::build(installed).depended_on()
+}
+
+pub fn report(var_folder: &Path, missing: bool, orphans: bool) {
+    let graph = Graph::build(&index::installed_packages(var_folder));
+    if missing {
+        let missing = graph.missing();
+        for (dep, packages) in missing.iter() {
+            info!("{dep}");
+            for package in packages {
+                info!("  needed by {package}");
+            }
+        }
+        summary!(
+            "{} dependencies are not installed / {}个依赖未安装",
+            missing.len(),
+            missing.len()
+        );
+    }
+    if orphans {
+        let orphans = graph.orphans();
+        for package in orphans.iter() {
+            info!("{package}");
+        }
+        summary!(
+            "{} of {} installed packages are not a dependency of any other / {}个包（共{}个）未被任何其他包依赖",
+            orphans.len(),
+            graph.packages.len(),
+            orphans.len(),
+            graph.packages.len()
+        );
+    }
+}
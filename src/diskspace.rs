@@ -0,0 +1,77 @@
+use crate::console::error;
+use crate::console::summary;
+use std::io;
+use std::sync::Condvar;
+use std::sync::Mutex;
+
+pub use crate::platform::free_space;
+
+static PAUSED: Mutex<bool> = Mutex::new(false);
+static RESUMED: Condvar = Condvar::new();
+
+pub fn is_disk_full(e: &anyhow::Error) -> bool {
+    e.chain().any(|cause| {
+        cause
+            .downcast_ref::<io::Error>()
+            .is_some_and(|io| io.kind() == io::ErrorKind::StorageFull)
+    })
+}
+
+// Packages whose merge fails as if the disk were full
+#[cfg(test)]
+pub static FULL_FOR: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+#[cfg(test)]
+pub fn simulate_full(package: &str) -> io::Result<()> {
+    match FULL_FOR.lock().unwrap().iter().any(|p| p == package) {
+        true => Err(io::ErrorKind::StorageFull.into()),
+        false => Ok(()),
+    }
+}
+
+// Block new packages from starting while the user frees space
+pub fn wait_if_paused() {
+    let mut paused = PAUSED.lock().unwrap();
+    while *paused {
+        paused = RESUMED.wait(paused).unwrap();
+    }
+}
+
+// The first worker to run out of space asks the user, the others just wait for the answer.
+// Returns false without waiting when nobody could answer, the package is given up instead.
+pub fn pause(package: &str) -> bool {
+    if !crate::ui::can_prompt() {
+        return false;
+    }
+    {
+        let mut paused = PAUSED.lock().unwrap();
+        if *paused {
+            while *paused {
+                paused = RESUMED.wait(paused).unwrap();
+            }
+            return true;
+        }
+        *paused = true;
+    }
+    error!("Disk is full while processing {package}, paused. Free some space to resume.");
+    error!("处理{package}时磁盘已满，已暂停。请释放磁盘空间后继续。");
+    if crate::queue::keyboard_active() {
+        // The control listener owns the console, resume goes through its r command
+        summary!("Type r and press Enter to resume / 输入r并回车继续");
+        crate::queue::pause();
+        crate::queue::wait_if_paused();
+    } else if crate::platform::MESSAGE_BOXES
+        && crate::config::get().pause_message_box
+        && crate::config::get().message_boxes
+    {
+        crate::ui::message_box(
+            "Disk full/磁盘已满",
+            "Disk is full. Free some space, then press OK to resume.\n磁盘已满，请释放空间后点击确定继续。",
+        );
+    } else {
+        crate::ui::wait_for_enter("Press Enter to resume / 按回车继续");
+    }
+    *PAUSED.lock().unwrap() = false;
+    RESUMED.notify_all();
+    true
+}
@@ -0,0 +1,37 @@
+use std::fs;
+use std::path::Component;
+use std::path::Path;
+use std::path::Prefix;
+
+// The drive `path` really lives on, following junctions: "D:", or "\\server\share" for a
+// network share, or the mount point on Linux and macOS. Subfolders of AddonPackages junctioned
+// to other disks get their own drive.
+pub fn of(path: &Path) -> String {
+    let resolved = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    match resolved.components().next() {
+        Some(Component::Prefix(prefix)) => match prefix.kind() {
+            Prefix::Disk(letter) | Prefix::VerbatimDisk(letter) => {
+                format!("{}:", letter.to_ascii_uppercase() as char)
+            }
+            Prefix::UNC(server, share) | Prefix::VerbatimUNC(server, share) => format!(
+                "\\\\{}\\{}",
+                server.to_string_lossy(),
+                share.to_string_lossy()
+            )
+            .to_lowercase(),
+            _ => prefix.as_os_str().to_string_lossy().to_string(),
+        },
+        _ => crate::platform::mount_point(&resolved),
+    }
+}
+
+// "d", "D:" and "D:\" all name drive D:
+pub fn normalize(drive: &str) -> String {
+    let drive = drive.trim_end_matches(['\\', '/']);
+    match drive.as_bytes() {
+        [letter] | [letter, b':'] if letter.is_ascii_alphabetic() => {
+            format!("{}:", letter.to_ascii_uppercase() as char)
+        }
+        _ => drive.to_lowercase(),
+    }
+}
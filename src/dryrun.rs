@@ -0,0 +1,77 @@
+use crate::console::{info, summary, warning};
+use crate::format_size;
+use crate::index;
+use crate::protect::Protected;
+use crate::CleanArgs;
+use std::collections::LinkedList;
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use threadpool::ThreadPool;
+use threadpool_scope::scope_with;
+
+// clean --dry-run: the groups clean would merge and about how much each merge takes out of
+// AddonPackages, from the central directories only. Nothing is extracted, moved or backed up,
+// and the checks that move files first (placeholders, foreign vars) are left out.
+pub fn run(scan_folder: &Path, args: &CleanArgs, protected: &Protected) -> anyhow::Result<()> {
+    let groups: Vec<LinkedList<PathBuf>> = crate::generate_duplicate_var_files(scan_folder)?
+        .into_values()
+        .filter(|filelist| filelist.len() > 1 && !filelist.iter().any(|p| protected.skips(p)))
+        .collect();
+    // Package, copies, bytes of every copy, estimated size of the merged var
+    let planned: Mutex<Vec<(String, usize, u64, u64)>> = Mutex::new(Vec::new());
+    let pool = ThreadPool::new(crate::config::get().threads);
+    scope_with(&pool, |scope| {
+        for filelist in groups.iter() {
+            let planned = &planned;
+            scope.execute(move || {
+                let filename = index::download_base_name(
+                    &filelist
+                        .front()
+                        .unwrap()
+                        .file_name()
+                        .unwrap()
+                        .to_string_lossy(),
+                );
+                let package = index::package_name(Path::new(&filename));
+                let copies: u64 = filelist
+                    .iter()
+                    .map(|p| fs::metadata(p).map_or(0, |m| m.len()))
+                    .sum();
+                match crate::streaming::plan(filelist, &package, args) {
+                    Ok(merged) => {
+                        planned
+                            .lock()
+                            .unwrap()
+                            .push((filename, filelist.len(), copies, merged));
+                    }
+                    Err(e) => warning!("Failed to read {filename}: {e}"),
+                }
+            });
+        }
+    });
+    let mut planned = planned.into_inner().unwrap();
+    let reclaimed =
+        |(_, _, copies, merged): &(String, usize, u64, u64)| copies.saturating_sub(*merged);
+    planned.sort_by_key(|group| std::cmp::Reverse(reclaimed(group)));
+    for group in planned.iter() {
+        info!(
+            "{:>10}  {} ({} copies, {} -> {})",
+            format_size(reclaimed(group)),
+            group.0,
+            group.1,
+            format_size(group.2),
+            format_size(group.3)
+        );
+    }
+    let total: u64 = planned.iter().map(reclaimed).sum();
+    summary!(
+        "Dry run: merging {} packages would take about {} out of AddonPackages, nothing was changed / 试运行：合并{}个包约可从AddonPackages移出{}，未做任何修改",
+        planned.len(),
+        format_size(total),
+        planned.len(),
+        format_size(total)
+    );
+    Ok(())
+}
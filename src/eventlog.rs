@@ -0,0 +1,23 @@
+use crate::console::Level;
+use crate::platform;
+
+// Event ids admins can filter on in the Application log
+const EVENT_START: u32 = 1;
+const EVENT_SUMMARY: u32 = 2;
+const EVENT_ERROR: u32 = 3;
+
+pub fn init(command: &str) {
+    if !platform::open_event_log() {
+        return;
+    }
+    platform::write_event(false, EVENT_START, &format!("VarCleaner {command} started"));
+}
+
+// Errors and summaries also go to the event log when it is enabled
+pub fn report(level: Level, message: &str) {
+    match level {
+        Level::Error => platform::write_event(true, EVENT_ERROR, message),
+        Level::Summary => platform::write_event(false, EVENT_SUMMARY, message),
+        _ => {}
+    }
+}
@@ -0,0 +1,107 @@
+use crate::console::warning;
+use serde::Deserialize;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::UNIX_EPOCH;
+use threadpool::ThreadPool;
+use threadpool_scope::scope_with;
+
+pub fn hash_file(path: &Path) -> io::Result<String> {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update_reader(File::open(path)?)?;
+    Ok(hasher.finalize().to_hex().to_string())
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+struct CachedHash {
+    size: u64,
+    mtime: u128,
+    hash: String,
+}
+
+// Whole-file hashes kept in VarCleaner/Cache/hashes.json, reused while size and mtime match
+pub struct HashCache {
+    path: PathBuf,
+    entries: Mutex<HashMap<String, CachedHash>>,
+}
+
+impl HashCache {
+    pub fn load(vam_folder: &Path) -> HashCache {
+        let path = vam_folder.join("VarCleaner/Cache/hashes.json");
+        let entries = fs::read(&path)
+            .ok()
+            .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+            .unwrap_or_default();
+        HashCache {
+            path,
+            entries: Mutex::new(entries),
+        }
+    }
+
+    pub fn hash(&self, path: &Path) -> io::Result<String> {
+        let metadata = fs::metadata(path)?;
+        let size = metadata.len();
+        let mtime = metadata
+            .modified()?
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos())
+            .unwrap_or(0);
+        let key = path.to_string_lossy().to_string();
+        if let Some(cached) = self.entries.lock().unwrap().get(&key) {
+            if cached.size == size && cached.mtime == mtime {
+                return Ok(cached.hash.clone());
+            }
+        }
+        let hash = hash_file(path)?;
+        self.entries.lock().unwrap().insert(
+            key,
+            CachedHash {
+                size,
+                mtime,
+                hash: hash.clone(),
+            },
+        );
+        Ok(hash)
+    }
+
+    // Hash several files at once, results in the same order as `paths`
+    pub fn hash_all<'a>(
+        &self,
+        paths: impl IntoIterator<Item = &'a PathBuf>,
+    ) -> Vec<io::Result<String>> {
+        let paths: Vec<&PathBuf> = paths.into_iter().collect();
+        let results: Vec<Mutex<Option<io::Result<String>>>> =
+            paths.iter().map(|_| Mutex::new(None)).collect();
+        let pool = ThreadPool::new(crate::config::get().threads.min(paths.len().max(1)));
+        scope_with(&pool, |scope| {
+            for (path, slot) in paths.iter().zip(results.iter()) {
+                scope.execute(move || *slot.lock().unwrap() = Some(self.hash(path)));
+            }
+        });
+        results
+            .into_iter()
+            .map(|slot| slot.into_inner().unwrap().unwrap())
+            .collect()
+    }
+
+    // Drop files that no longer exist so the cache does not grow forever
+    pub fn save(&self) {
+        // Hashes are recomputed next time, not worth a warning
+        if crate::readonly::is_enabled() {
+            return;
+        }
+        let mut entries = self.entries.lock().unwrap();
+        entries.retain(|path, _| Path::new(path).exists());
+        let result = fs::create_dir_all(self.path.parent().unwrap())
+            .and_then(|_| fs::write(&self.path, serde_json::to_vec(&*entries).unwrap()));
+        if let Err(e) = result {
+            warning!("Failed to save {}: {e}", self.path.to_string_lossy());
+        }
+    }
+}
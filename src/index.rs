@@ -0,0 +1,207 @@
+use crate::intern::Name;
+use crate::package::PackageId;
+use serde::Deserialize;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fs::File;
+use std::path::Path;
+use std::path::PathBuf;
+use walkdir::WalkDir;
+use zip::ZipArchive;
+
+#[derive(Serialize, Deserialize)]
+pub struct EntryInfo {
+    #[serde(deserialize_with = "crate::intern::deserialize")]
+    pub name: Name,
+    pub size: u64,
+    pub crc32: u32,
+}
+
+pub struct VarIndex {
+    pub package: String,
+    pub entries: Vec<EntryInfo>,
+    pub meta: Option<serde_json::Value>,
+}
+
+pub fn package_name(path: &Path) -> String {
+    path.file_stem().unwrap().to_string_lossy().to_string()
+}
+
+// The name of a var saved again by a browser or copied by Explorer: "Creator.Pkg.1 (1).var",
+// "Creator.Pkg.1 - Copy.var" and "Creator.Pkg.1 - Copy (2).var" are Creator.Pkg.1.var. VaM
+// cannot read a version out of those names, so they are always duplicates of the base name.
+pub fn download_base_name(file_name: &str) -> String {
+    let Some((stem, ext)) = file_name.rsplit_once('.') else {
+        return file_name.to_string();
+    };
+    let mut stem = stem;
+    loop {
+        let trimmed = stem
+            .strip_suffix(')')
+            .and_then(|s| s.rsplit_once(" ("))
+            .filter(|(_, n)| !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()))
+            .map(|(s, _)| s)
+            .or_else(|| stem.strip_suffix(" - Copy"))
+            .or_else(|| stem.strip_suffix(" - 副本"));
+        match trimmed {
+            Some(trimmed) if !trimmed.is_empty() => stem = trimmed,
+            _ => break,
+        }
+    }
+    format!("{stem}.{ext}")
+}
+
+pub fn is_var(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("var"))
+}
+
+// The order VaM registers packages in: each folder's files before its subfolders,
+// both sorted case-insensitively, and the first copy of a package wins
+pub fn vam_walk(var_folder: &Path) -> WalkDir {
+    WalkDir::new(var_folder).sort_by(|a, b| {
+        let key = |e: &walkdir::DirEntry| {
+            (
+                e.file_type().is_dir(),
+                e.file_name().to_string_lossy().to_lowercase(),
+            )
+        };
+        key(a).cmp(&key(b))
+    })
+}
+
+pub fn list_vars(var_folder: &Path) -> Vec<PathBuf> {
+    vam_walk(var_folder)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_file() && is_var(e.path()))
+        .map(|e| e.into_path())
+        .collect()
+}
+
+// One path per package name, the first one found wins like VaM does
+pub fn installed_packages(var_folder: &Path) -> HashMap<String, PathBuf> {
+    let mut result = HashMap::new();
+    for path in list_vars(var_folder) {
+        result.entry(package_name(&path)).or_insert(path);
+    }
+    result
+}
+
+// Copies of one package found so far, the one VaM loads first
+#[derive(Clone, Debug)]
+pub struct DuplicateGroup {
+    // Lowercased file name the copies share, NTFS ignores case
+    pub name: String,
+    pub copies: Vec<PathBuf>,
+}
+
+// Duplicate groups while AddonPackages is walked in VaM's order, so a UI can show them as they
+// turn up: a group is yielded once its second copy is found and again with every further
+// copy, so the last one yielded under a name is complete when the iterator ends. Unreadable
+// folders are skipped like list_vars does.
+pub fn scan_duplicates(root: &Path) -> impl Iterator<Item = DuplicateGroup> {
+    let mut found: HashMap<String, Vec<PathBuf>> = HashMap::new();
+    vam_walk(root)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_file() && is_var(e.path()))
+        .filter_map(move |e| {
+            let name = download_base_name(&e.file_name().to_string_lossy()).to_lowercase();
+            let copies = found.entry(name.clone()).or_default();
+            copies.push(e.into_path());
+            (copies.len() > 1).then(|| DuplicateGroup {
+                name,
+                copies: copies.clone(),
+            })
+        })
+}
+
+pub fn open_var(path: &Path) -> anyhow::Result<ZipArchive<File>> {
+    Ok(ZipArchive::new(File::open(path)?)?)
+}
+
+pub fn parse_json(bytes: &[u8]) -> serde_json::Result<serde_json::Value> {
+    // Some creators save their json with a BOM
+    let text = String::from_utf8_lossy(bytes);
+    serde_json::from_str(text.trim_start_matches('\u{feff}'))
+}
+
+// `value` written with every object's keys sorted and no whitespace: two JSON files that
+// only differ in formatting or key order come out the same
+pub fn canonical_json(value: &serde_json::Value) -> String {
+    fn sorted(value: &serde_json::Value) -> serde_json::Value {
+        match value {
+            serde_json::Value::Object(map) => {
+                let mut keys: Vec<&String> = map.keys().collect();
+                keys.sort();
+                serde_json::Value::Object(
+                    keys.into_iter()
+                        .map(|key| (key.clone(), sorted(&map[key])))
+                        .collect(),
+                )
+            }
+            serde_json::Value::Array(items) => {
+                serde_json::Value::Array(items.iter().map(sorted).collect())
+            }
+            other => other.clone(),
+        }
+    }
+    sorted(value).to_string()
+}
+
+// Content fingerprint from the central directory (FNV-1a over names, sizes and CRCs),
+// stable across runs and cheap enough to compute for a whole library
+pub fn fingerprint(var_index: &VarIndex) -> String {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    let mut feed = |bytes: &[u8]| {
+        for byte in bytes {
+            hash ^= *byte as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+    };
+    for entry in var_index.entries.iter() {
+        feed(entry.name.as_bytes());
+        feed(&entry.size.to_le_bytes());
+        feed(&entry.crc32.to_le_bytes());
+    }
+    format!("{:016x}", hash)
+}
+
+// All dependencies of a package, including the nested ones meta.json carries
+pub fn dependencies(meta: &serde_json::Value) -> Vec<PackageId> {
+    let mut result = Vec::new();
+    collect_dependencies(meta, &mut result);
+    result
+}
+
+fn collect_dependencies(node: &serde_json::Value, result: &mut Vec<PackageId>) {
+    if let Some(deps) = node.get("dependencies").and_then(|d| d.as_object()) {
+        for (name, child) in deps {
+            if let Some(id) = PackageId::parse(name) {
+                if !result.contains(&id) {
+                    result.push(id);
+                }
+            }
+            collect_dependencies(child, result);
+        }
+    }
+}
+
+// Resolve `Creator.Pkg.3`, `Creator.Pkg.latest` and `Creator.Pkg.min3` against installed packages
+pub fn resolve_dependency<'a>(
+    dep: &PackageId,
+    installed: &'a HashMap<String, PathBuf>,
+) -> Option<(&'a String, &'a PathBuf)> {
+    if let Some(found) = installed.get_key_value(&dep.to_string()) {
+        return Some(found);
+    }
+    installed
+        .iter()
+        .filter_map(|(pkg, path)| {
+            let id = PackageId::parse(pkg)?;
+            dep.accepts(&id).then_some((id.version, (pkg, path)))
+        })
+        .max_by_key(|(version, _)| *version)
+        .map(|(_, found)| found)
+}
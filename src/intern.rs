@@ -0,0 +1,46 @@
+use serde::Deserialize;
+use serde::Deserializer;
+use std::collections::HashSet;
+use std::hash::BuildHasher;
+use std::hash::RandomState;
+use std::sync::Arc;
+use std::sync::LazyLock;
+use std::sync::Mutex;
+
+// Entry names repeat across a library: every var has a meta.json, and the copies of a package
+// or the versions of a creator's packages list mostly the same paths. Scanning a large library
+// keeps millions of them, so each distinct name is stored once and shared; cloning one only
+// bumps a count, and the HashMaps keyed by them hash no longer copies.
+pub type Name = Arc<str>;
+
+// Split so the scan threads rarely wait on each other
+const SHARDS: usize = 32;
+
+struct Interner {
+    hasher: RandomState,
+    shards: [Mutex<HashSet<Name>>; SHARDS],
+}
+
+static INTERNER: LazyLock<Interner> = LazyLock::new(|| Interner {
+    hasher: RandomState::new(),
+    shards: std::array::from_fn(|_| Mutex::new(HashSet::new())),
+});
+
+// The shared copy of `name`. Names stay for the rest of the process, which holds no more than
+// the distinct names of the library.
+pub fn intern(name: &str) -> Name {
+    let shard = INTERNER.hasher.hash_one(name) as usize % SHARDS;
+    let mut names = INTERNER.shards[shard].lock().unwrap();
+    if let Some(found) = names.get(name) {
+        return found.clone();
+    }
+    let name: Name = Arc::from(name);
+    names.insert(name.clone());
+    name
+}
+
+// For serde, names read from the JSON cache are shared like freshly scanned ones
+pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Name, D::Error> {
+    let name = <std::borrow::Cow<'de, str>>::deserialize(deserializer)?;
+    Ok(intern(&name))
+}
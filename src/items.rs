@@ -0,0 +1,109 @@
+use crate::console::{info, summary, warning};
+use crate::content;
+use crate::index;
+use crate::package::PackageId;
+use crate::reader::VarReader;
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use threadpool::ThreadPool;
+use threadpool_scope::scope_with;
+
+// One clothing or hair item as its .vam file describes it
+pub struct Item {
+    pub uid: String,
+    pub display_name: String,
+    pub var: PathBuf,
+    pub entry: String,
+}
+
+// The uid VaM knows an item by stays the same when the item is repacked into another var
+// under another path, so it finds copies that the entry paths do not
+fn scan_var(path: &Path) -> anyhow::Result<Vec<Item>> {
+    let mut var = VarReader::open(path)?;
+    let mut items = Vec::new();
+    for entry in var.entries()? {
+        let category = content::category(&entry.name);
+        if (category != "clothing" && category != "hair")
+            || !entry.name.to_ascii_lowercase().ends_with(".vam")
+        {
+            continue;
+        }
+        let Ok(json) = var
+            .read_entry(&entry.name)
+            .and_then(|bytes| Ok(index::parse_json(&bytes)?))
+        else {
+            continue;
+        };
+        let Some(uid) = json.get("uid").and_then(|uid| uid.as_str()) else {
+            continue;
+        };
+        items.push(Item {
+            uid: uid.to_string(),
+            display_name: json
+                .get("displayName")
+                .and_then(|name| name.as_str())
+                .unwrap_or_default()
+                .to_string(),
+            var: path.to_path_buf(),
+            entry: entry.name.to_string(),
+        });
+    }
+    Ok(items)
+}
+
+// Clothing and hair items whose uid turns up in more than one package, by uid. Versions of
+// one package carry the same items and copies of one var are package duplicates, so only
+// items shared between different Creator.Pkg count.
+pub fn shared(var_folder: &Path) -> Vec<(String, Vec<Item>)> {
+    let items: Mutex<Vec<Item>> = Mutex::new(Vec::new());
+    let pool = ThreadPool::new(crate::config::get().threads);
+    let paths = index::list_vars(var_folder);
+    scope_with(&pool, |scope| {
+        for path in paths.iter() {
+            let items = &items;
+            scope.execute(move || match scan_var(path) {
+                Ok(found) => items.lock().unwrap().extend(found),
+                Err(_) => warning!("zipfile {} is invaild", path.to_string_lossy()),
+            });
+        }
+    });
+    let mut by_uid: BTreeMap<String, Vec<Item>> = BTreeMap::new();
+    for item in items.into_inner().unwrap() {
+        by_uid.entry(item.uid.clone()).or_default().push(item);
+    }
+    let base = |item: &Item| {
+        let package = index::package_name(&item.var);
+        PackageId::parse(&package).map_or(package, |id| id.base())
+    };
+    by_uid
+        .into_iter()
+        .filter(|(_, copies)| {
+            let mut packages: Vec<String> = copies.iter().map(base).collect();
+            packages.sort();
+            packages.dedup();
+            packages.len() > 1
+        })
+        .map(|(uid, mut copies)| {
+            copies.sort_by(|a, b| a.var.cmp(&b.var));
+            (uid, copies)
+        })
+        .collect()
+}
+
+pub fn report(var_folder: &Path) {
+    let shared = shared(var_folder);
+    for (uid, copies) in shared.iter() {
+        info!("{uid} ({})", copies[0].display_name);
+        for item in copies.iter() {
+            let relative = item.var.strip_prefix(var_folder).unwrap_or(&item.var);
+            info!("  {}:/{}", relative.to_string_lossy(), item.entry);
+        }
+    }
+    summary!(
+        "{} clothing and hair items are in more than one package / {}个服装和头发物品存在于多个包中",
+        shared.len(),
+        shared.len()
+    );
+}
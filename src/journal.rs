@@ -0,0 +1,64 @@
+use serde::Deserialize;
+use serde::Serialize;
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum Operation {
+    Move {
+        from: PathBuf,
+        to: PathBuf,
+    },
+    Create {
+        path: PathBuf,
+        // blake3 of the file as written, so undoing the run only deletes it while unchanged
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        hash: Option<String>,
+    },
+    // Var replaced by a delta against its newest version
+    Delta {
+        from: PathBuf,
+        to: PathBuf,
+    },
+    // Folder left empty by the run and removed, restore recreates it
+    RemoveDir {
+        path: PathBuf,
+    },
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Journal {
+    pub run_id: String,
+    pub command: String,
+    pub operations: Vec<Operation>,
+}
+
+impl Journal {
+    pub fn new(command: &str) -> Journal {
+        let secs = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        Journal {
+            run_id: format!("{command}-{secs}"),
+            command: command.to_string(),
+            operations: Vec::new(),
+        }
+    }
+
+    pub fn record(&mut self, operation: Operation) {
+        self.operations.push(operation);
+    }
+
+    pub fn save(&self, journal_folder: &Path) -> anyhow::Result<PathBuf> {
+        crate::readonly::check(journal_folder)?;
+        fs::create_dir_all(journal_folder)?;
+        let path = journal_folder.join(format!("{}.json", self.run_id));
+        fs::write(&path, serde_json::to_vec_pretty(self)?)?;
+        Ok(path)
+    }
+}
@@ -0,0 +1,110 @@
+use crate::index::EntryInfo;
+use crate::index::VarIndex;
+use crate::reader::VarReader;
+use serde::Deserialize;
+use serde::Serialize;
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+use std::time::UNIX_EPOCH;
+
+// Entry list, meta.json and scene JSON of every var read so far, one zstd compressed file per
+// var in VarCleaner/Cache/json, reused while the var's size and mtime match. Commands that
+// only look at JSON then skip opening thousands of zips, which dominates cold runs on HDDs.
+static FOLDER: OnceLock<PathBuf> = OnceLock::new();
+
+// Called once at startup; without it every read goes to the var itself
+pub fn init(vam_folder: &Path) {
+    if crate::config::get().json_cache {
+        let _ = FOLDER.set(vam_folder.join("VarCleaner/Cache/json"));
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct CachedVar {
+    size: u64,
+    mtime: u128,
+    entries: Vec<EntryInfo>,
+    meta: Option<serde_json::Value>,
+    // Saves/scene/*.json entries and their text
+    scenes: Vec<(String, String)>,
+}
+
+fn is_scene(name: &str) -> bool {
+    name.starts_with("Saves/scene/") && name.ends_with(".json")
+}
+
+fn stamp(path: &Path) -> Option<(u64, u128)> {
+    let metadata = fs::metadata(path).ok()?;
+    let mtime = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+    Some((metadata.len(), mtime.as_nanos()))
+}
+
+// The file is named after the var's path, a changed var overwrites its old entry
+fn cache_file(folder: &Path, path: &Path) -> PathBuf {
+    let key = blake3::hash(path.to_string_lossy().as_bytes());
+    folder.join(format!("{}.json.zst", key.to_hex()))
+}
+
+fn read_var(path: &Path) -> anyhow::Result<CachedVar> {
+    let mut var = VarReader::open(path)?;
+    let entries = var.entries()?;
+    let mut scenes = Vec::new();
+    for entry in entries.iter().filter(|e| is_scene(&e.name)) {
+        // An unreadable scene is left out, scene checks skip it as they always did
+        if let Ok(bytes) = var.read_entry(&entry.name) {
+            scenes.push((
+                entry.name.to_string(),
+                String::from_utf8_lossy(&bytes).to_string(),
+            ));
+        }
+    }
+    Ok(CachedVar {
+        size: 0,
+        mtime: 0,
+        meta: var.meta().cloned(),
+        entries,
+        scenes,
+    })
+}
+
+fn load(path: &Path) -> anyhow::Result<CachedVar> {
+    let (Some(folder), Some((size, mtime))) = (FOLDER.get(), stamp(path)) else {
+        return read_var(path);
+    };
+    let file = cache_file(folder, path);
+    let cached = fs::read(&file)
+        .ok()
+        .and_then(|bytes| zstd::decode_all(bytes.as_slice()).ok())
+        .and_then(|json| serde_json::from_slice::<CachedVar>(&json).ok())
+        .filter(|cached| cached.size == size && cached.mtime == mtime);
+    if let Some(cached) = cached {
+        return Ok(cached);
+    }
+    let mut var = read_var(path)?;
+    var.size = size;
+    var.mtime = mtime;
+    // A cache that cannot be written only costs speed
+    if crate::readonly::check(&file).is_ok() {
+        let _ = fs::create_dir_all(folder).and_then(|_| {
+            let bytes = zstd::encode_all(serde_json::to_vec(&var)?.as_slice(), 3)?;
+            fs::write(&file, bytes)
+        });
+    }
+    Ok(var)
+}
+
+pub fn index(path: &Path) -> anyhow::Result<VarIndex> {
+    let var = load(path)?;
+    Ok(VarIndex {
+        package: crate::index::package_name(path),
+        entries: var.entries,
+        meta: var.meta,
+    })
+}
+
+// Scene entries and their JSON text
+pub fn scenes(path: &Path) -> anyhow::Result<Vec<(String, String)>> {
+    Ok(load(path)?.scenes)
+}
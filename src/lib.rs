@@ -0,0 +1,7 @@
+// Var parsing shared by the VarCleaner binary and the fuzz targets in fuzz/. Everything
+// here reads archives only; nothing touches the VaM folder
+pub mod content;
+pub mod index;
+pub mod intern;
+pub mod package;
+pub mod reader;
@@ -0,0 +1,9 @@
+use crate::platform::is_lock_io;
+use std::io;
+
+pub use crate::platform::is_locked;
+
+pub fn is_lock_error(e: &anyhow::Error) -> bool {
+    e.chain()
+        .any(|cause| cause.downcast_ref::<io::Error>().is_some_and(is_lock_io))
+}
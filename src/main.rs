@@ -1,296 +1,1619 @@
-use glob::glob;
-use glob::GlobError;
-use glob::Pattern;
-use path_slash::PathExt;
+mod archive;
+mod backup;
+mod budget;
+mod builder;
+mod builtin;
+mod config;
+mod console;
+mod consolidate;
+mod daemon;
+mod decisions;
+mod dedupe;
+mod delta;
+mod deps;
+mod diskspace;
+mod drives;
+mod dryrun;
+mod eventlog;
+mod hashing;
+mod items;
+mod journal;
+mod jsoncache;
+mod locks;
+mod maintain;
+mod meta;
+mod morphs;
+mod nested;
+mod plan;
+mod platform;
+mod previews;
+mod protect;
+mod quarantine;
+mod queue;
+mod readonly;
+mod reconstruct;
+mod root;
+mod scenes;
+mod sidecars;
+mod sources;
+mod split;
+mod stamp;
+mod stats;
+mod status;
+mod streaming;
+mod strip;
+#[cfg(any(test, feature = "test-support"))]
+mod testkit;
+#[cfg(test)]
+mod tests;
+mod textures;
+mod ui;
+mod validate;
+mod vamcache;
+mod versions;
+
+// The archive layer lives in the library so fuzz targets can reach it
+use varcleaner::{content, index, intern, package, reader};
+
+use crate::console::{error, info, summary, verbose, warning};
+use crate::journal::Journal;
+use crate::journal::Operation;
+use clap::ArgAction;
+use clap::Args;
+use clap::CommandFactory;
+use clap::Parser;
+use clap::Subcommand;
+use clap::ValueEnum;
+use serde::Deserialize;
+use std::collections::BTreeMap;
 use std::collections::HashMap;
+use std::collections::HashSet;
 use std::collections::LinkedList;
 use std::env;
 use std::fs;
-use std::fs::create_dir_all;
-use std::fs::File;
 use std::io;
-use std::io::Read;
-use std::io::Seek;
-use std::io::Write;
-use std::os::windows::fs::MetadataExt;
 use std::path::Path;
 use std::path::PathBuf;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
 use threadpool::ThreadPool;
 use threadpool_scope::scope_with;
-use walkdir::{DirEntry, WalkDir};
-use zip::result::ZipError;
-use zip::write::SimpleFileOptions;
-
-use std::ffi::OsStr;
-use std::os::windows::ffi::OsStrExt;
-use std::ptr::null_mut;
-use winapi::um::winuser::{MessageBoxW, MB_OK, MB_SYSTEMMODAL};
-
-fn to_wide_string(s: &str) -> Vec<u16> {
-    OsStr::new(s)
-        .encode_wide()
-        .chain(std::iter::once(0))
-        .collect()
+
+#[derive(Parser)]
+#[command(
+    name = "VarCleaner",
+    version,
+    about = "Merge duplicated VaM var packages"
+)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+    /// Only print errors and final summaries
+    #[arg(short, long, global = true)]
+    quiet: bool,
+    /// Print more details, repeat for even more
+    #[arg(short, long, action = ArgAction::Count, global = true)]
+    verbose: u8,
+    /// Use two workers and stream entries instead of buffering them, for 8 GB machines
+    #[arg(long, global = true)]
+    low_memory: bool,
+    /// VaM folder to work on, found automatically when omitted
+    #[arg(long, global = true, visible_alias = "vam-dir")]
+    vam_root: Option<PathBuf>,
+    /// Do not require VaM.exe, work on the var folder given by --addon-path
+    #[arg(
+        long,
+        global = true,
+        requires = "addon_path",
+        conflicts_with = "vam_root"
+    )]
+    no_vam_check: bool,
+    /// Var folder to clean when there is no VaM install
+    #[arg(
+        long,
+        global = true,
+        requires = "no_vam_check",
+        visible_alias = "addon-dir"
+    )]
+    addon_path: Option<PathBuf>,
+    /// Folder for the originals of merged vars instead of VarCleaner/Backup
+    #[arg(long, global = true)]
+    backup_dir: Option<PathBuf>,
+    /// Folder older versions extracted copies into; merging no longer extracts anything
+    #[arg(long, global = true, hide = true)]
+    tmp_dir: Option<PathBuf>,
+    /// Worker threads, overriding the threads setting
+    #[arg(long, global = true)]
+    threads: Option<usize>,
+    /// Also write start, summary and error events to the Windows Application event log
+    #[arg(long, global = true)]
+    event_log: bool,
+    /// Refuse every command and file operation that would change the VaM folder
+    #[arg(long, global = true)]
+    read_only: bool,
 }
 
-fn show_message_box(title: &str, message: &str) {
-    let title_wide = to_wide_string(title);
-    let message_wide = to_wide_string(message);
+#[derive(Subcommand)]
+enum Command {
+    /// Merge duplicated vars in AddonPackages (default)
+    Clean(CleanArgs),
+    /// Estimate decoded texture memory per var and per scene
+    Textures {
+        /// How many vars and scenes to list
+        #[arg(long, default_value_t = 20)]
+        top: usize,
+    },
+    /// Extract package and scene thumbnails into VarCleaner/Cache/previews
+    Previews,
+    /// Report vars that force-preload their morphs into every session
+    Morphs {
+        /// Turn preloadMorphs off and repack, backing up the originals
+        #[arg(long)]
+        fix: bool,
+    },
+    /// Combine a creator's small single-type asset vars into one var per content type
+    Consolidate {
+        /// Creator name as it appears in the var file names
+        #[arg(long)]
+        creator: String,
+        /// Only consolidate vars up to this size in MB
+        #[arg(long, default_value_t = 100)]
+        max_size_mb: u64,
+    },
+    /// Split a large var into separate texture, audio and scene vars
+    Split {
+        /// The var to split
+        var: PathBuf,
+    },
+    /// Store superseded versions as deltas against the newest version
+    ArchiveVersions {
+        /// Rebuild an archived package (Creator.Package.Version) back into AddonPackages
+        #[arg(long)]
+        restore: Option<String>,
+        /// Restore without showing the changes and asking first
+        #[arg(long, requires = "restore")]
+        yes: bool,
+    },
+    /// Move all but the newest versions of every package to the backup
+    PruneVersions {
+        /// How many of the newest versions of each package stay
+        #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u64).range(1..))]
+        keep_latest: u64,
+        /// Also keep versions another var's meta.json depends on
+        #[arg(long)]
+        keep_referenced: bool,
+    },
+    /// Report dependencies no installed var satisfies and vars nothing depends on, both
+    /// without options
+    Deps {
+        /// List dependencies that are not installed with the vars that need them
+        #[arg(long)]
+        missing: bool,
+        /// List installed vars no other var depends on
+        #[arg(long)]
+        orphans: bool,
+    },
+    /// Report scene references that do not resolve to an installed var entry
+    CheckScenes,
+    /// List duplicated packages and which copy VaM actually loads
+    #[command(visible_alias = "scan")]
+    Duplicates {
+        /// Group duplicates by the folder they sit in instead
+        #[arg(long)]
+        by_folder: bool,
+        /// Back up every var in this AddonPackages subfolder that also exists elsewhere
+        #[arg(long, conflicts_with = "by_folder")]
+        drop_folder: Option<PathBuf>,
+        /// List clothing and hair items whose uid is in more than one package, even when
+        /// repacked under another path
+        #[arg(long, conflicts_with_all = ["by_folder", "drop_folder"])]
+        items: bool,
+    },
+    /// Report backup files identical to live vars and optionally reclaim their space
+    BackupDedupe {
+        /// Turn identical backup files into hardlinks to the live var
+        #[arg(long, conflicts_with = "drop")]
+        hardlink: bool,
+        /// Delete identical backup files
+        #[arg(long)]
+        drop: bool,
+    },
+    /// CRC-check every entry of the given vars without changing anything
+    Verify {
+        /// Check every var in AddonPackages
+        #[arg(long, conflicts_with = "vars")]
+        all: bool,
+        /// Vars to check
+        #[arg(required_unless_present = "all")]
+        vars: Vec<PathBuf>,
+    },
+    /// Re-derive which copy every entry of a merged var should come from and compare; exits
+    /// with status 1 on any problem
+    VerifyMerge {
+        /// The merged var
+        merged: PathBuf,
+        /// Its sources, the copy VaM loads first
+        #[arg(required = true)]
+        sources: Vec<PathBuf>,
+    },
+    /// Serve scan, merge and restore requests as newline-delimited JSON over a named pipe, a Unix
+    /// socket on Linux and macOS
+    Daemon {
+        #[arg(long, default_value = platform::DEFAULT_PIPE)]
+        pipe: String,
+    },
+    /// Save the duplicate groups clean would merge, or check a saved plan is still valid
+    Plan {
+        /// Where to save the plan
+        #[arg(long, default_value = "plan.json")]
+        output: PathBuf,
+        /// Plan saved earlier: report what changed in the library since, saving nothing
+        #[arg(long)]
+        compare: Option<PathBuf>,
+    },
+    /// Show which copy every entry of one duplicated package would come from, without merging
+    Preview {
+        /// Package file name, e.g. Creator.Pkg.1.var
+        var: String,
+        /// Compare scene, preset and item JSON by content, ignoring whitespace and key order
+        #[arg(long)]
+        canonical_json: bool,
+    },
+    /// Run scan, identical-copy merge, version archive, backup pruning and a report in one
+    /// go without asking anything, for Task Scheduler; exits with status 1 when a pass fails
+    Maintain {
+        /// Passes to run, in order, e.g. scan,merge; defaults to the maintenance setting
+        #[arg(long, value_enum, value_delimiter = ',')]
+        passes: Vec<maintain::Pass>,
+        /// Instead run only as many space-freeing passes as it takes to get this much free
+        /// space on the AddonPackages drive, e.g. 200GB, cheapest first
+        #[arg(long, value_parser = parse_size, conflicts_with = "passes")]
+        target_free: Option<u64>,
+    },
+    /// Put every original in the backup back into AddonPackages, moving merged vars whose
+    /// originals came back to VarCleaner/Unmerged
+    Restore {
+        /// Undo only this clean run, e.g. clean-1760000000: its originals go back where they
+        /// were and the merged vars it wrote are deleted
+        #[arg(long)]
+        run_id: Option<String>,
+        /// Restore without asking first
+        #[arg(long)]
+        yes: bool,
+    },
+    /// Rebuild AddonPackages as it was before VarCleaner changed it, from the backup and the
+    /// journals, into another folder; the VaM folder itself is left as it is
+    Reconstruct {
+        /// Empty folder outside the VaM folder to rebuild into
+        target: PathBuf,
+    },
+    /// Print a shell completion script
+    Completions {
+        #[arg(value_enum)]
+        shell: clap_complete::Shell,
+    },
+}
 
-    unsafe {
-        MessageBoxW(
-            null_mut(),
-            message_wide.as_ptr(),
-            title_wide.as_ptr(),
-            MB_OK | MB_SYSTEMMODAL,
-        );
+impl Command {
+    // Whether the command can change files, refused in read-only mode
+    fn writes(&self) -> bool {
+        match self {
+            Command::Textures { .. }
+            | Command::CheckScenes
+            | Command::Deps { .. }
+            | Command::Verify { .. }
+            | Command::VerifyMerge { .. }
+            | Command::Preview { .. }
+            | Command::Completions { .. } => false,
+            Command::Clean(args) => !args.dry_run,
+            Command::Morphs { fix } => *fix,
+            Command::Plan { compare, .. } => compare.is_none(),
+            Command::Duplicates { drop_folder, .. } => drop_folder.is_some(),
+            Command::BackupDedupe { hardlink, drop } => *hardlink || *drop,
+            _ => true,
+        }
+    }
+}
+
+#[derive(Args, Clone, Default, Deserialize)]
+#[serde(default)]
+struct CleanArgs {
+    /// Drop promo screenshots from merged vars, keeping only package and content thumbnails
+    #[arg(long)]
+    strip_promo_images: bool,
+    /// How entries of merged vars are compressed
+    #[arg(long, value_enum, default_value_t = Compression::Stored)]
+    compression: Compression,
+    /// Only merge duplicates found inside this subfolder of AddonPackages
+    #[arg(long)]
+    scope: Option<PathBuf>,
+    /// Write why each entry's copy was kept to VarCleaner/Logs/<package>.log
+    #[arg(long)]
+    explain: bool,
+    /// Merging always streams zip to zip now, still accepted from older scripts
+    #[arg(long, hide = true)]
+    streaming: bool,
+    /// Nothing is extracted to VarCleaner/Tmp any more, still accepted from older scripts
+    #[arg(long, hide = true, value_parser = parse_size)]
+    max_temp: Option<u64>,
+    /// Try packages that were in use once more at the end of the run
+    #[arg(long)]
+    retry_locked: bool,
+    /// Copy vars shipped inside other vars out to AddonPackages/nested before merging
+    #[arg(long)]
+    extract_nested: bool,
+    /// Read p (pause), r (resume), s NAME (skip) and f NAME (first) from the console while running
+    #[arg(long)]
+    controls: bool,
+    /// Quarantine vars with absurd entry counts or folder nesting; off for genuinely giant packages
+    #[arg(long, value_enum, default_value_t = Limits::On)]
+    limits: Limits,
+    /// Leave folders emptied by moving originals out in AddonPackages
+    #[arg(long)]
+    keep_empty_folders: bool,
+    /// Name merged vars after the original (same), Creator.Pkg.1-merged1 (suffix) or the next
+    /// free version (bump); references to the exact old version then resolve to other versions
+    #[arg(long, value_enum, default_value_t = Naming::Same)]
+    naming: Naming,
+    /// Only merge entries of these content categories, e.g. textures,audio; entries of other
+    /// categories are copied unchanged from the copy VaM loads
+    #[arg(long, value_delimiter = ',', value_parser = content::parse_category)]
+    only_content: Vec<String>,
+    /// Whether copies VaM has disabled (a .disabled marker, or a folder named disabled) give
+    /// entries to the merged var; excluded copies are still backed up
+    #[arg(long, value_enum, default_value_t = DisabledCopies::Include)]
+    disabled_copies: DisabledCopies,
+    /// Whether the merge of a group holding a disabled copy is enabled, disabled, or follows
+    /// the copy VaM loads (live)
+    #[arg(long, value_enum, default_value_t = MergedState::Enabled)]
+    merged_state: MergedState,
+    /// Leave out textures and morphs identical to files in VaM's own Custom folder (also the
+    /// strip_builtin setting)
+    #[arg(long)]
+    strip_builtin: bool,
+    /// Never leave out entries VaM might look for inside the var, whatever the settings say
+    #[arg(long)]
+    strict_compat: bool,
+    /// Stop after changing this many packages, 0 for no limit; 50 on a first run
+    #[arg(long)]
+    max_changes: Option<usize>,
+    /// Stop after moving this much of the originals, e.g. 200GB, 0 for no limit; 20GB on a
+    /// first run
+    #[arg(long, value_parser = parse_size)]
+    max_bytes: Option<u64>,
+    /// Ask before merging each group of differing copies; answers are remembered in
+    /// VarCleaner/decisions.json and reused on later runs until a copy changes
+    #[arg(long)]
+    ask: bool,
+    /// Rewrite this file every second with the package being merged, percent done and time
+    /// left, as JSON or as one line of text for a .txt file (for stream overlays)
+    #[arg(long)]
+    status_file: Option<PathBuf>,
+    /// Keep the originals when a merged var comes out more than this many percent larger
+    /// than the largest copy (default 10)
+    #[arg(long)]
+    max_growth: Option<u64>,
+    /// Clear VaM's package cache at the end, so its next launch shows the cleaned library
+    /// instead of stale entries
+    #[arg(long)]
+    refresh_vam_cache: bool,
+    /// overnight: fewer workers and a rest after every package to keep a laptop cool and
+    /// quiet, statistics saved after every package, Ctrl+C stops between packages
+    #[arg(long, value_enum, default_value_t = Pace::Normal)]
+    pace: Pace,
+    /// Write merged vars to this folder (another drive, a NAS) instead of AddonPackages/merged
+    /// and leave every original where it is
+    #[arg(long)]
+    output: Option<PathBuf>,
+    /// Also copy every var without duplicates to --output, making it a complete clean library
+    #[arg(long, requires = "output")]
+    export: bool,
+    /// Only merge groups whose copies are byte-identical, leaving groups of differing copies
+    #[arg(long)]
+    identical_only: bool,
+    /// Also hash vars of the same size and back up byte-identical copies saved under another
+    /// package's name, keeping the copy named after its meta.json
+    #[arg(long)]
+    by_content: bool,
+    /// Put the blake3 of every original into merged vars (VarCleaner.stamp.json), so a shared
+    /// merge can be checked against the originals with verify-merge
+    #[arg(long)]
+    stamp: bool,
+    /// Also copy every merged var into merged/_latest-run, emptied when each run starts, to
+    /// try out only what this run changed in VaM
+    #[arg(long)]
+    stage_latest: bool,
+    /// Only list the groups that would be merged and about how much space each merge frees,
+    /// reading central directories and changing nothing
+    #[arg(long)]
+    dry_run: bool,
+}
+
+#[derive(ValueEnum, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum DisabledCopies {
+    #[default]
+    Include,
+    Exclude,
+}
+
+#[derive(ValueEnum, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum MergedState {
+    #[default]
+    Enabled,
+    Disabled,
+    Live,
+}
+
+impl CleanArgs {
+    // Whether entries named `name` are merged across copies, otherwise only the live copy's
+    // entry is kept, byte for byte
+    fn merges(&self, name: &str) -> bool {
+        self.only_content.is_empty()
+            || self
+                .only_content
+                .iter()
+                .any(|c| c == content::category(name))
     }
 }
 
-fn file_op(is_copy: bool, src: &PathBuf, dst: &PathBuf) {
-    fs::create_dir_all(dst.parent().unwrap()).unwrap();
-    if is_copy {
-        let mut file = fs::File::open(src).unwrap();
-        let mut outfile = fs::File::create(&dst).unwrap();
-        io::copy(&mut file, &mut outfile).unwrap();
+#[derive(ValueEnum, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Naming {
+    #[default]
+    Same,
+    Suffix,
+    Bump,
+}
+
+#[derive(ValueEnum, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Limits {
+    #[default]
+    On,
+    Off,
+}
+
+#[derive(ValueEnum, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Pace {
+    #[default]
+    Normal,
+    // Half the workers, a rest after every package, progress saved after every package and
+    // Ctrl+C stopping between packages
+    Overnight,
+}
+
+#[derive(ValueEnum, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Compression {
+    /// No compression, fastest to load in VaM
+    #[default]
+    Stored,
+    Deflate,
+    Zstd,
+}
+
+impl Compression {
+    fn method(self) -> zip::CompressionMethod {
+        match self {
+            Compression::Stored => zip::CompressionMethod::Stored,
+            Compression::Deflate => zip::CompressionMethod::Deflated,
+            Compression::Zstd => zip::CompressionMethod::Zstd,
+        }
+    }
+}
+
+// Times one package is tried again after freeing space, it fails after that
+const DISK_FULL_RETRIES: usize = 3;
+// Longest rest between packages with --pace overnight
+const OVERNIGHT_REST: Duration = Duration::from_secs(30);
+// Limits of a first clean of a library, unless --max-changes/--max-bytes say otherwise
+const FIRST_RUN_CHANGES: usize = 50;
+const FIRST_RUN_BYTES: u64 = 20 << 30;
+// A merge this much larger than its largest copy keeps the originals, unless --max-growth
+// says otherwise; growth below GROWTH_SLACK is alignment padding on small vars
+const MAX_GROWTH_PERCENT: u64 = 10;
+const GROWTH_SLACK: u64 = 1 << 20;
+// Subfolder of the merged folder holding copies of this run's merges with --stage-latest. VaM
+// loads the var in merged itself first, files come before subfolders.
+const LATEST_RUN: &str = "_latest-run";
+
+// "50GB", "512 MB" or plain bytes
+fn parse_size(text: &str) -> Result<u64, String> {
+    let text = text.trim().to_ascii_uppercase();
+    let digits = text.trim_end_matches(|c: char| c.is_ascii_alphabetic() || c == ' ');
+    let multiplier: u64 = match text[digits.len()..].trim() {
+        "" | "B" => 1,
+        "K" | "KB" => 1 << 10,
+        "M" | "MB" => 1 << 20,
+        "G" | "GB" => 1 << 30,
+        "T" | "TB" => 1 << 40,
+        unit => return Err(format!("unknown unit {unit}")),
+    };
+    let value: f64 = digits
+        .trim()
+        .parse()
+        .map_err(|_| format!("invalid size {text}"))?;
+    Ok((value * multiplier as f64) as u64)
+}
+
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KB", "MB", "GB", "TB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} {}", bytes, UNITS[0])
     } else {
-        fs::rename(src, dst).unwrap();
+        format!("{:.2} {}", size, UNITS[unit])
     }
 }
 
+fn file_size(path: &Path) -> u64 {
+    fs::metadata(path).map(|m| m.len()).unwrap_or(0)
+}
+
 fn generate_duplicate_var_files(
-    src_folder: &String,
-) -> Result<HashMap<String, LinkedList<PathBuf>>, GlobError> {
+    src_folder: &Path,
+) -> Result<HashMap<String, LinkedList<PathBuf>>, walkdir::Error> {
     let mut result: HashMap<String, LinkedList<PathBuf>> = HashMap::new();
-    for entry in glob(format!("{}/**/*.var", Pattern::escape(src_folder)).as_str())
-        .expect("Failed to read glob pattern")
-    {
-        match entry {
-            Ok(path) => {
-                if !path.is_file() {
-                    continue;
-                }
-                let filename = String::from(path.file_name().unwrap().to_str().unwrap());
-                if !result.contains_key(&filename) {
-                    result.insert(filename.clone(), LinkedList::new());
-                }
-                result.get_mut(&filename).unwrap().push_back(path);
-            }
-            Err(e) => return Err(e),
+    // Walk in VaM's order so the first copy of every group is the one VaM loads
+    for entry in index::vam_walk(src_folder) {
+        let entry = entry?;
+        if !entry.file_type().is_file() || !index::is_var(entry.path()) {
+            continue;
         }
+        // NTFS ignores case, so Creator.Pkg.1.var and creator.pkg.1.var are the same package
+        let filename =
+            index::download_base_name(entry.file_name().to_str().unwrap()).to_lowercase();
+        result
+            .entry(filename)
+            .or_default()
+            .push_back(entry.into_path());
+    }
+    // "Pkg.1 (1).var" sorts before "Pkg.1.var" but VaM only loads the properly named copy
+    for copies in result.values_mut() {
+        let (named, downloads): (LinkedList<PathBuf>, LinkedList<PathBuf>) =
+            std::mem::take(copies).into_iter().partition(|path| {
+                let name = path.file_name().unwrap().to_string_lossy();
+                index::download_base_name(&name) == name
+            });
+        *copies = named;
+        copies.extend(downloads);
     }
     Ok(result)
 }
 
-fn get_short_path(path: &PathBuf, base: &PathBuf) -> PathBuf {
-    return PathBuf::from_iter(path.strip_prefix(base).unwrap().components().skip(1));
+fn report_duplicates(var_folder: &Path) {
+    let groups = generate_duplicate_var_files(var_folder).unwrap();
+    let mut duplicates: Vec<&LinkedList<PathBuf>> =
+        groups.values().filter(|list| list.len() > 1).collect();
+    duplicates.sort_by_key(|list| list.front().unwrap().file_name().unwrap().to_os_string());
+    for list in duplicates.iter() {
+        let mut copies = list.iter();
+        let live = copies.next().unwrap();
+        info!("{}", live.file_name().unwrap().to_string_lossy());
+        info!(
+            "  live    {}",
+            live.strip_prefix(var_folder).unwrap().to_string_lossy()
+        );
+        for ignored in copies {
+            info!(
+                "  ignored {}",
+                ignored.strip_prefix(var_folder).unwrap().to_string_lossy()
+            );
+        }
+    }
+    summary!(
+        "{} packages have duplicates / {}个包存在重复",
+        duplicates.len(),
+        duplicates.len()
+    );
 }
 
-fn zip_dir<T>(
-    it: &mut dyn Iterator<Item = DirEntry>,
-    prefix: &Path,
-    writer: T,
-    method: zip::CompressionMethod,
-) -> anyhow::Result<()>
-where
-    T: Write + Seek,
-{
-    let mut zip = zip::ZipWriter::new(writer);
-    let options = SimpleFileOptions::default()
-        .compression_method(method)
-        .unix_permissions(0o755)
-        .with_alignment(4096);
-
-    let prefix = Path::new(prefix);
-    let mut buffer = Vec::new();
-    for entry in it {
-        let path = entry.path();
-        let name = path.strip_prefix(prefix).unwrap();
-        let path_as_string = name.to_slash().unwrap();
-
-        // Write file or directory explicitly
-        // Some unzip tools unzip files with directory paths correctly, some do not!
-        if path.is_file() {
-            //println!("adding file {path:?} as {name:?} ...");
-            zip.start_file(path_as_string, options)?;
-            let mut f = File::open(path)?;
-
-            f.read_to_end(&mut buffer)?;
-            zip.write_all(&buffer)?;
-            buffer.clear();
-        } else if !name.as_os_str().is_empty() {
-            // Only if not root! Avoids path spec / warning
-            // and mapname conversion failed error on unzip
-            //println!("adding dir {path_as_string:?} as {name:?} ...");
-            zip.add_directory(path_as_string, options)?;
-        }
-    }
-    zip.finish()?;
-    Ok(())
+// Entries of the live copy win, otherwise the largest copy of an entry is kept
+fn prefer(kept: (usize, u64), copy: usize, size: u64) -> bool {
+    kept.0 != 0 && (copy == 0 || kept.1 < size)
 }
 
-fn zip_one_file(
-    src_dir: &Path,
-    dst_file: &Path,
-    method: zip::CompressionMethod,
-) -> anyhow::Result<()> {
-    if !Path::new(src_dir).is_dir() {
-        println!(
-            "Path {} is not directory, error.",
-            src_dir.as_os_str().to_str().unwrap()
-        );
-        return Err(ZipError::FileNotFound.into());
+// Date and CRC of an entry in one copy, read while its archive is open for the sizes
+type EntryStamp = (Option<zip::DateTime>, u32);
+
+// `prefer`, with a size tie between two copies other than the live one settled by what the
+// entries hold: equal CRCs keep the earlier copy, otherwise the entry written last wins, then
+// the higher CRC, so every run picks the same copy whatever order they were read in
+fn prefer_copy(
+    stamps: &HashMap<(usize, String), EntryStamp>,
+    name: &str,
+    kept: (usize, u64),
+    copy: usize,
+    size: u64,
+) -> bool {
+    if kept.0 == 0 || copy == 0 || kept.1 != size {
+        return prefer(kept, copy, size);
+    }
+    let stamp = |copy: usize| stamps.get(&(copy, name.to_string()));
+    let (Some(old), Some(new)) = (stamp(kept.0), stamp(copy)) else {
+        return copy < kept.0;
+    };
+    if old.1 == new.1 {
+        return copy < kept.0;
     }
-    fs::create_dir_all(dst_file.parent().unwrap()).unwrap();
+    let wins = new > old;
+    let (winner, loser) = if wins { (copy, kept.0) } else { (kept.0, copy) };
+    warning!(
+        "{name}: copies {winner} and {loser} differ at the same size, kept copy {winner} / 同样大小但内容不同，保留副本{winner}"
+    );
+    wins
+}
 
-    let path = Path::new(dst_file);
-    let file = File::create(path).unwrap();
+// Names that differ only in case are one file on NTFS, extracting both would silently keep
+// whichever came last. Of each such set the entry `prefer` picks stays, ties going to the
+// first name in sort order; returns the names to leave out
+fn case_collisions(package: &str, winners: &HashMap<String, (usize, u64)>) -> Vec<String> {
+    let mut names: Vec<&String> = winners.keys().collect();
+    names.sort();
+    let mut kept: HashMap<String, &String> = HashMap::new();
+    let mut dropped = Vec::new();
+    for name in names {
+        let (copy, size) = winners[name];
+        match kept.get(&name.to_lowercase()) {
+            Some(&other) => {
+                let loser = if prefer(winners[other], copy, size) {
+                    kept.insert(name.to_lowercase(), name);
+                    other
+                } else {
+                    name
+                };
+                let winner = kept[&name.to_lowercase()];
+                warning!(
+                    "{package}: {loser} and {winner} differ only in case, kept {winner} (copy {})",
+                    winners[winner].0
+                );
+                dropped.push(loser.clone());
+            }
+            None => {
+                kept.insert(name.to_lowercase(), name);
+            }
+        }
+    }
+    dropped
+}
 
-    let walkdir = WalkDir::new(src_dir);
-    let it = walkdir.into_iter();
+fn preview(var_folder: &Path, var: &str, canonical_json: bool) -> anyhow::Result<()> {
+    let mut name = var.to_lowercase();
+    if !name.ends_with(".var") {
+        name += ".var";
+    }
+    let file_dicts = generate_duplicate_var_files(var_folder)?;
+    match file_dicts.get(&name) {
+        Some(filelist) if filelist.len() > 1 => streaming::preview(filelist, canonical_json),
+        Some(_) => anyhow::bail!("{var} has no duplicates / {var}没有重复的副本"),
+        None => anyhow::bail!("{var} is not installed / 未找到{var}"),
+    }
+}
 
-    zip_dir(&mut it.filter_map(|e| e.ok()), src_dir, file, method)?;
+// One line per entry: the kept copy and why, then the copies that lost
+fn write_explanation(
+    log: &Path,
+    sources: &LinkedList<PathBuf>,
+    candidates: &BTreeMap<String, Vec<(usize, u64)>>,
+    kept: &HashMap<String, usize>,
+) -> anyhow::Result<()> {
+    readonly::check(log)?;
+    let sources: Vec<&PathBuf> = sources.iter().collect();
+    let mut text = String::new();
+    for (pos, source) in sources.iter().enumerate() {
+        text += &format!("copy {pos}: {}\n", source.to_string_lossy());
+    }
+    text += "copy 0 is the one VaM loads; missing copies of an entry were corrupt or absent\n\n";
+    for (name, copies) in candidates.iter() {
+        let kept = kept.get(name).copied();
+        let reason = match kept {
+            None => "dropped as promo image".to_string(),
+            Some(_) if copies.len() == 1 => "only copy".to_string(),
+            Some(0) => "live copy".to_string(),
+            Some(_) => "largest, live copy lacks it".to_string(),
+        };
+        text += &format!("{name}: {reason}\n");
+        for (copy, size) in copies.iter() {
+            let mark = if Some(*copy) == kept { "kept" } else { "lost" };
+            text += &format!("  {mark} copy {copy} {}\n", format_size(*size));
+        }
+    }
+    fs::create_dir_all(log.parent().unwrap())?;
+    fs::write(log, text)?;
     Ok(())
 }
 
-fn rezip_one_file(src: &PathBuf, target: &PathBuf) {
-    let mut result: HashMap<String, (PathBuf, u64)> = HashMap::new();
-    let pattern = format!(
-        "{}/**/*",
-        Pattern::escape(src.as_os_str().to_str().unwrap())
-    );
-    for entry in glob(&pattern).expect("Failed to read glob pattern") {
-        match entry {
-            Ok(path) => {
-                if !path.is_file() {
-                    continue;
+fn main() {
+    let cli = Cli::parse();
+    console::init(cli.quiet, cli.verbose, platform::enable_ansi_colors());
+    config::init(cli.low_memory, cli.threads);
+    if let Some((major, minor)) = platform::legacy_windows() {
+        verbose!(
+            "Windows {major}.{minor}, console colours are off / 旧版Windows，已关闭命令行颜色"
+        );
+    }
+    if cli.event_log {
+        eventlog::init(&env::args().skip(1).collect::<Vec<_>>().join(" "));
+    }
+    if cli.read_only {
+        readonly::enable();
+    }
+    let command = cli.command.unwrap_or(Command::Clean(CleanArgs::default()));
+    if readonly::is_enabled() && command.writes() {
+        error!("This command changes the VaM folder and is refused in read-only mode / 只读模式下不允许执行会修改VaM文件夹的命令");
+        return;
+    }
+    if let Command::Completions { shell } = command {
+        clap_complete::generate(shell, &mut Cli::command(), "VarCleaner", &mut io::stdout());
+        return;
+    }
+    // Needs no VaM folder, so it also runs in CI against recorded groups
+    if let Command::VerifyMerge { merged, sources } = &command {
+        match validate::verify_merge(merged, sources) {
+            Ok(true) => return,
+            Ok(false) => std::process::exit(1),
+            Err(e) => {
+                error!("{e}");
+                std::process::exit(1);
+            }
+        }
+    }
+    let found = match &cli.addon_path {
+        Some(addon_path) => root::standalone(addon_path),
+        None => root::find(cli.vam_root.as_deref()),
+    };
+    let found = found.and_then(|folder| {
+        root::set_folders(cli.backup_dir.as_deref(), cli.tmp_dir.as_deref())?;
+        Ok(folder)
+    });
+    let vam_folder = match found {
+        Ok(folder) => folder,
+        Err(e) => {
+            error!("{e}");
+            ui::message_box("Error/错误", &e.to_string());
+            return;
+        }
+    };
+    stats::show_savings(&vam_folder);
+    jsoncache::init(&vam_folder);
+    let protected = &protect::load(&vam_folder);
+    match command {
+        Command::Clean(args) => match clean(&vam_folder, &args, protected) {
+            Ok(()) => {
+                let logs = vam_folder.join("VarCleaner/Logs");
+                let report = stats::latest_report(&vam_folder)
+                    .unwrap_or_else(|| vam_folder.join("VarCleaner/reports"));
+                ui::completion(
+                    "Done/完成清理",
+                    &report,
+                    &root::addon_packages(&vam_folder).join("merged"),
+                    logs.is_dir().then_some(logs),
+                )
+            }
+            Err(e) => error!("{e}"),
+        },
+        Command::Textures { top } => textures::report(&root::addon_packages(&vam_folder), top),
+        Command::Previews => previews::cache_all(
+            &root::addon_packages(&vam_folder),
+            &vam_folder.join("VarCleaner/Cache/previews"),
+        ),
+        Command::Morphs { fix } => morphs::audit(
+            &root::addon_packages(&vam_folder),
+            &root::backup_folder(&vam_folder),
+            fix,
+            protected,
+        ),
+        Command::Consolidate {
+            creator,
+            max_size_mb,
+        } => consolidate::run(&vam_folder, &creator, max_size_mb * 1024 * 1024, protected),
+        Command::Split { var } => {
+            if let Err(e) = split::run(&vam_folder, &std::path::absolute(var).unwrap(), protected) {
+                error!("Split failed: {e}");
+            }
+        }
+        Command::ArchiveVersions { restore: None, .. } => {
+            if let Err(e) = delta::archive_superseded(&vam_folder, protected) {
+                error!("Archiving failed: {e}");
+            }
+        }
+        Command::ArchiveVersions {
+            restore: Some(package),
+            yes,
+        } => {
+            if let Err(e) = delta::restore(&vam_folder, &package, yes) {
+                error!("Restore failed: {e}");
+            }
+        }
+        Command::PruneVersions {
+            keep_latest,
+            keep_referenced,
+        } => versions::keep_latest(
+            &vam_folder,
+            keep_latest as usize,
+            keep_referenced,
+            protected,
+        ),
+        Command::Deps { missing, orphans } => {
+            let both = !missing && !orphans;
+            deps::report(
+                &root::addon_packages(&vam_folder),
+                missing || both,
+                orphans || both,
+            )
+        }
+        Command::CheckScenes => scenes::check_all(&vam_folder),
+        Command::BackupDedupe { hardlink, drop } => backup::dedupe(
+            &vam_folder,
+            match (hardlink, drop) {
+                (true, _) => backup::DedupeAction::Hardlink,
+                (_, true) => backup::DedupeAction::Drop,
+                _ => backup::DedupeAction::Report,
+            },
+        ),
+        Command::Duplicates { items: true, .. } => {
+            items::report(&root::addon_packages(&vam_folder))
+        }
+        Command::Duplicates {
+            by_folder: false,
+            drop_folder: None,
+            ..
+        } => report_duplicates(&root::addon_packages(&vam_folder)),
+        Command::Duplicates {
+            drop_folder: None, ..
+        } => {
+            if let Err(e) = sources::report(&root::addon_packages(&vam_folder)) {
+                error!("{e}");
+            }
+        }
+        Command::Duplicates {
+            drop_folder: Some(folder),
+            ..
+        } => {
+            if let Err(e) = sources::drop_folder(&vam_folder, &folder, protected) {
+                error!("{e}");
+            }
+        }
+        Command::Verify { all: true, .. } => {
+            validate::verify(&index::list_vars(&root::addon_packages(&vam_folder)))
+        }
+        Command::Verify { vars, .. } => validate::verify(
+            &vars
+                .iter()
+                .map(|var| std::path::absolute(var).unwrap())
+                .collect::<Vec<_>>(),
+        ),
+        Command::Daemon { pipe } => {
+            if let Err(e) = daemon::serve(&vam_folder, &pipe, protected) {
+                error!("Daemon stopped: {e}");
+            }
+        }
+        Command::Plan {
+            compare: Some(old), ..
+        } => {
+            if let Err(e) = plan::compare(&root::addon_packages(&vam_folder), protected, &old) {
+                error!("{e}");
+            }
+        }
+        Command::Plan { output, .. } => {
+            if let Err(e) = plan::write(&root::addon_packages(&vam_folder), protected, &output) {
+                error!("{e}");
+            }
+        }
+        Command::Preview {
+            var,
+            canonical_json,
+        } => {
+            if let Err(e) = preview(&root::addon_packages(&vam_folder), &var, canonical_json) {
+                error!("{e}");
+            }
+        }
+        Command::Maintain {
+            target_free: Some(target),
+            ..
+        } => {
+            if !maintain::free_up(&vam_folder, target, protected) {
+                std::process::exit(1);
+            }
+        }
+        Command::Maintain { passes, .. } => {
+            let passes = match passes.is_empty() {
+                true => config::get().maintenance.clone(),
+                false => passes,
+            };
+            if !maintain::run(&vam_folder, &passes, protected) {
+                std::process::exit(1);
+            }
+        }
+        Command::Restore { run_id, yes } => {
+            let restored = match run_id {
+                Some(run_id) => backup::restore_run(&vam_folder, &run_id, yes),
+                None => backup::restore(&vam_folder, yes),
+            };
+            if let Err(e) = restored {
+                error!("Restore failed: {e}");
+            }
+        }
+        Command::Reconstruct { target } => {
+            if let Err(e) = std::path::absolute(&target)
+                .map_err(anyhow::Error::from)
+                .and_then(|target| reconstruct::run(&vam_folder, &target))
+            {
+                error!("{e}");
+            }
+        }
+        Command::Completions { .. } | Command::VerifyMerge { .. } => unreachable!(),
+    }
+}
+
+struct CleanRun<'a> {
+    var_folder: &'a Path,
+    var_merged_folder: &'a Path,
+    var_backup_folder: &'a Path,
+    log_folder: &'a Path,
+    limit: &'a budget::ChangeLimit,
+    hashes: &'a hashing::HashCache,
+    bumper: &'a versions::VersionBumper,
+    builtin: Option<&'a builtin::BuiltIn>,
+    run_log: &'a stats::RunLog,
+    decisions: &'a decisions::Decisions,
+    // Packages whose merge came out too large, listed again at the end of the run
+    grown: Mutex<Vec<String>>,
+    // Packages whose copies differ, merged entry by entry and listed again at the end
+    differing: Mutex<Vec<String>>,
+    // Originals backed up and files written, so `restore --run-id` can undo the run
+    journal: Mutex<Journal>,
+    args: &'a CleanArgs,
+}
+
+impl CleanRun<'_> {
+    // Returns false when a copy is in use by another program, so the group can be retried
+    fn merge_group(&self, filelist: &LinkedList<PathBuf>) -> bool {
+        let args = self.args;
+        // Keep the spelling of the first copy found for the merged var, without a " (1)"
+        let live_name = filelist
+            .front()
+            .unwrap()
+            .file_name()
+            .unwrap()
+            .to_string_lossy()
+            .to_string();
+        let filename = index::download_base_name(&live_name);
+        let before: u64 = filelist.iter().map(|p| file_size(p)).sum();
+        let log =
+            |outcome: &str, after: u64| self.run_log.record(&filename, outcome, before, after);
+        if queue::is_skipped(&filename) {
+            info!("Skip {filename} as requested");
+            log("skipped", before);
+            return true;
+        }
+        if let Some(path) = filelist.iter().find(|p| locks::is_locked(p)) {
+            warning!("Skip {filename}, {} is in use", path.to_string_lossy());
+            log("in-use", before);
+            return false;
+        }
+        let hashes = self.hashes.hash_all(filelist);
+        let identical = hashes
+            .iter()
+            .all(|h| h.is_ok() && h.as_ref().ok() == hashes[0].as_ref().ok());
+        if !identical && args.identical_only {
+            info!("Skip {filename}, its copies differ");
+            log("skipped", before);
+            return true;
+        }
+        // Groups of identical copies are never asked about, there is nothing to choose
+        if !identical && self.keep_by_decision(&filename, &hashes) {
+            log("skipped", before);
+            return true;
+        }
+        if !self.limit.allow(before) {
+            log("limit", before);
+            return true;
+        }
+        // Byte-identical copies need no merge, VaM keeps loading the live one
+        if identical && args.output.is_some() {
+            info!("{} copies of {filename} are identical", filelist.len());
+            let live = filelist.front().unwrap();
+            let target = self.var_merged_folder.join(&filename);
+            if let Err(e) = copy_var(live, &target) {
+                error!("Failed to copy {filename} to the output: {e}");
+                log("failed", before);
+                return true;
+            }
+            self.journal_created(&target);
+            log("identical", file_size(live));
+            return true;
+        }
+        // Only download copies, merging gives the one kept the name VaM reads
+        if identical && live_name == filename {
+            info!("{} copies of {filename} are identical", filelist.len());
+            let others = filelist.iter().skip(1);
+            let (moves, moved) = backup::move_all(others, self.var_folder, self.var_backup_folder);
+            self.journal_backups(&moves);
+            if let Err(e) = moved {
+                error!("Failed to back up {filename}, copies not in the backup stay in place: {e}");
+                log("failed", before);
+                return true;
+            }
+            let live = filelist.front().unwrap();
+            log("identical", file_size(live));
+            if let Ok(var_index) = reader::VarReader::open(live).and_then(|mut var| var.index()) {
+                let copies = filelist.len() as u64;
+                stats::record(
+                    var_index
+                        .entries
+                        .iter()
+                        .map(|e| (&*e.name, e.size * copies)),
+                    |name| {
+                        var_index
+                            .entries
+                            .iter()
+                            .find(|e| &*e.name == name)
+                            .map(|e| e.size)
+                    },
+                );
+            }
+            return true;
+        }
+        if !identical {
+            self.differing.lock().unwrap().push(filename.clone());
+        }
+        info!("Process file {} Count {}", filename, filelist.len());
+        verbose!(
+            "VaM currently loads {}",
+            filelist.front().unwrap().to_string_lossy()
+        );
+        let disabled: Vec<bool> = filelist
+            .iter()
+            .map(|p| sidecars::is_disabled(p, self.var_folder))
+            .collect();
+        let sources: &LinkedList<PathBuf> = &match args.disabled_copies {
+            DisabledCopies::Exclude if disabled.contains(&false) => filelist
+                .iter()
+                .zip(disabled.iter())
+                .filter(|(_, disabled)| !**disabled)
+                .map(|(path, _)| path.clone())
+                .collect(),
+            _ => filelist.clone(),
+        };
+        let target = &self.var_merged_folder.join(self.merged_name(&filename));
+        let mut disk_full = 0;
+        let merged = loop {
+            diskspace::wait_if_paused();
+            match streaming::merge(sources, target, self.log_folder, args, self.builtin) {
+                Ok(merged) => break merged,
+                Err(_) if queue::is_aborted() => {
+                    info!("Stopped {filename}, originals are untouched");
+                    log("stopped", before);
+                    return true;
                 }
-                let short_name = get_short_path(&path, src);
-                let short_name_str = short_name.as_os_str().to_str().unwrap().to_string();
-                if !result.contains_key(short_name.as_os_str().to_str().unwrap()) {
-                    result.insert(
-                        short_name_str,
-                        (path.clone(), fs::metadata(&path).unwrap().file_size()),
-                    );
-                } else {
-                    let size = fs::metadata(&path).unwrap().file_size();
-                    if result.get(&short_name_str).unwrap().1 < size {
-                        *result.get_mut(&short_name_str).unwrap() = (path.clone(), size);
+                Err(e) if diskspace::is_disk_full(&e) => {
+                    disk_full += 1;
+                    if disk_full > DISK_FULL_RETRIES || !diskspace::pause(&filename) {
+                        error!("Failed to merge {filename}, the disk is full, originals are untouched: {e}");
+                        log("failed", before);
+                        return true;
                     }
                 }
+                Err(e) if locks::is_lock_error(&e) => {
+                    warning!("Skip {filename}, a copy is in use: {e}");
+                    log("in-use", before);
+                    return false;
+                }
+                Err(e) => {
+                    error!("Failed to merge {filename}, originals are untouched: {e}");
+                    log("failed", before);
+                    return true;
+                }
             }
-            Err(_) => panic!(),
+        };
+        let partial = merged.as_ref().map(|merged| merged.partial.clone());
+        if queue::is_skipped(&filename) {
+            info!("Skip {filename} as requested, originals are untouched");
+            if let Some(partial) = partial {
+                let _ = fs::remove_file(partial);
+            }
+            log("skipped", before);
+            return true;
         }
-    }
-    // If all duplicated var files are invalid, no file can be compress, just leave it
-    if result.len() == 0 {
-        return;
+        if let Some(partial) = &partial {
+            let largest = filelist.iter().map(|p| file_size(p)).max().unwrap_or(0);
+            let merged = file_size(partial);
+            let allowed = largest
+                + (largest * args.max_growth.unwrap_or(MAX_GROWTH_PERCENT) / 100).max(GROWTH_SLACK);
+            if merged > allowed {
+                warning!(
+                    "Merged {filename} is {} against {} for its largest copy, originals are kept / 合并后的{filename}比最大的副本还大，保留原始文件",
+                    format_size(merged),
+                    format_size(largest)
+                );
+                let _ = fs::remove_file(partial);
+                self.grown.lock().unwrap().push(filename.clone());
+                log("grew", before);
+                return true;
+            }
+        }
+        // Originals only move once the merged copy is safely written, and never when the
+        // merge goes to a separate output
+        let (moves, moved) = match args.output {
+            Some(_) => (Vec::new(), Ok(())),
+            None => backup::move_all(filelist, self.var_folder, self.var_backup_folder),
+        };
+        self.journal_backups(&moves);
+        if let Err(e) = moved {
+            error!("Failed to back up {filename}, copies not in the backup stay in place: {e}");
+            if let Some(partial) = partial {
+                let _ = fs::remove_file(partial);
+            }
+            log("failed", before);
+            return true;
+        }
+        if let Some(partial) = partial {
+            if args.naming != Naming::Same {
+                if let Err(e) = stamp_provenance(&partial, filelist, self.var_folder) {
+                    warning!("Failed to record the sources of {filename} in meta.json: {e}");
+                }
+            }
+            // Last, every change to the entries before it would break the stamp
+            if args.stamp {
+                if let Err(e) = stamp::write(
+                    &partial,
+                    &index::package_name(Path::new(&filename)),
+                    filelist,
+                    &hashes,
+                ) {
+                    warning!("Failed to stamp {filename} with its sources: {e}");
+                }
+            }
+            if let Err(e) = fs::rename(&partial, target) {
+                error!("Failed to put merged {filename} in place, originals are moved back: {e}");
+                let _ = fs::remove_file(&partial);
+                self.unback(&moves);
+                log("failed", before);
+                return true;
+            }
+            self.journal_created(target);
+            if let Some(merged) = &merged {
+                merged.record_savings();
+            }
+            let disable = disabled.contains(&true)
+                && match args.merged_state {
+                    MergedState::Enabled => false,
+                    MergedState::Disabled => true,
+                    MergedState::Live => disabled[0],
+                };
+            if disable {
+                match sidecars::disable(target) {
+                    Ok(marker) => self.journal_created(&marker),
+                    Err(e) => warning!("Failed to disable merged {filename}: {e}"),
+                }
+            }
+            // A disabled merge is nothing to try out
+            if args.stage_latest && !disable {
+                let staged = self
+                    .var_merged_folder
+                    .join(LATEST_RUN)
+                    .join(target.file_name().unwrap());
+                let copied = fs::create_dir_all(staged.parent().unwrap())
+                    .and_then(|_| fs::copy(target, &staged));
+                match copied {
+                    Ok(_) => self.journal_created(&staged),
+                    Err(e) => warning!("Failed to copy {filename} to {LATEST_RUN}: {e}"),
+                }
+            }
+            log("merged", file_size(target));
+        } else {
+            // No copy had a readable entry, the originals went to the backup all the same
+            log("empty", 0);
+        }
+        true
     }
 
-    let workdir = src.join("working");
-    for (short_name, (path, _)) in result.iter() {
-        let filepath = workdir.join(short_name);
-        file_op(false, path, &filepath);
+    // Moves backed-up copies back where they were and drops them from the journal
+    fn unback(&self, moves: &[(PathBuf, PathBuf)]) {
+        let mut journal = self.journal.lock().unwrap();
+        for (copy, backup) in moves {
+            match backup::move_verified(backup, copy) {
+                Ok(()) => journal
+                    .operations
+                    .retain(|op| !matches!(op, Operation::Move { from, .. } if from == copy)),
+                Err(e) => error!(
+                    "Failed to move {} back, it stays in the backup: {e}",
+                    copy.to_string_lossy()
+                ),
+            }
+        }
     }
-    zip_one_file(&workdir, target, zip::CompressionMethod::Stored).unwrap();
-}
 
-fn unzip_one_file(path: &PathBuf, base: &PathBuf, idx: usize) {
-    let mut archive = match zip::ZipArchive::new(
-        fs::File::open(path)
-            .expect(format!("Could not open file {}", path.as_os_str().to_str().unwrap()).as_str()),
-    ) {
-        Ok(ret) => ret,
-        Err(_) => {
-            println!("zipfile {} is invaild", path.as_os_str().to_str().unwrap());
-            return;
+    // Journals each copy that reached the backup; after a failed move_all only some did
+    fn journal_backups(&self, moves: &[(PathBuf, PathBuf)]) {
+        let mut journal = self.journal.lock().unwrap();
+        for (from, to) in moves {
+            journal.record(Operation::Move {
+                from: from.clone(),
+                to: to.clone(),
+            });
         }
-    };
+    }
 
-    for i in 0..archive.len() {
-        let mut file = match archive.by_index(i) {
-            Ok(tfile) => tfile,
-            Err(_) => {
-                println!("file error, ignore");
-                continue;
+    fn journal_created(&self, path: &Path) {
+        self.journal.lock().unwrap().record(Operation::Create {
+            path: path.to_path_buf(),
+            hash: hashing::hash_file(path).ok(),
+        });
+    }
+
+    // Whether to leave a group as it is, from an earlier answer or by asking with --ask
+    fn keep_by_decision(&self, filename: &str, hashes: &[std::io::Result<String>]) -> bool {
+        let hashes: Vec<String> = hashes
+            .iter()
+            .map(|h| h.as_ref().map_or(String::new(), |h| h.clone()))
+            .collect();
+        let key = decisions::key(filename, &hashes);
+        let decision = match self.decisions.get(&key) {
+            Some(decision) => decision,
+            None if self.args.ask => {
+                let copies = hashes.len();
+                let question = format!(
+                    "Merge the {copies} differing copies of {filename}? / 合并{filename}的{copies}个不同副本？"
+                );
+                let decision = if ui::confirm(&question) {
+                    decisions::Decision::Merge
+                } else {
+                    decisions::Decision::Keep
+                };
+                self.decisions.remember(key, decision);
+                decision
             }
+            None => return false,
         };
-        let outpath = match file.enclosed_name() {
-            Some(path) => path,
-            None => continue,
+        if decision == decisions::Decision::Keep {
+            info!("Keep the copies of {filename} as decided / 按已有决定保留{filename}的副本");
+        }
+        decision == decisions::Decision::Keep
+    }
+
+    fn merged_name(&self, filename: &str) -> String {
+        let package = filename.strip_suffix(".var").unwrap_or(filename);
+        let renamed = match self.args.naming {
+            Naming::Same => None,
+            Naming::Suffix => (1..)
+                .map(|n| format!("{package}-merged{n}"))
+                .find(|name| !self.var_merged_folder.join(format!("{name}.var")).exists()),
+            Naming::Bump => self.bumper.next(package),
         };
+        match renamed {
+            Some(name) => {
+                info!("Merged {filename} will be named {name}.var");
+                format!("{name}.var")
+            }
+            None => filename.to_string(),
+        }
+    }
+}
 
-        let realoutpath = base.join(idx.to_string()).join(outpath);
+// A copy in the output, verified like a backup; an existing file of the same name is kept
+fn copy_var(src: &Path, dst: &Path) -> anyhow::Result<()> {
+    if dst.exists() {
+        return Ok(());
+    }
+    readonly::check(dst)?;
+    fs::create_dir_all(dst.parent().unwrap())?;
+    let tmp = dst.with_extension("var.tmp");
+    fs::copy(src, &tmp)?;
+    if hashing::hash_file(src)? != hashing::hash_file(&tmp)? {
+        let _ = fs::remove_file(&tmp);
+        anyhow::bail!(
+            "the copy of {} differs from the original",
+            src.to_string_lossy()
+        );
+    }
+    fs::rename(tmp, dst)?;
+    Ok(())
+}
 
-        if file.is_dir() {
-            fs::create_dir_all(&realoutpath).unwrap();
-        } else {
-            if let Some(p) = realoutpath.parent() {
-                if !p.exists() {
-                    fs::create_dir_all(p).unwrap();
-                }
-            }
-            let mut outfile = fs::File::create(&realoutpath).unwrap();
-            io::copy(&mut file, &mut outfile).unwrap();
+// --export: every var that was not part of a duplicate group joins the output at the same
+// path it has in AddonPackages
+fn export_rest(
+    var_folder: &Path,
+    scan_folder: &Path,
+    output: &Path,
+    grouped: &[PathBuf],
+    foreign: &HashSet<PathBuf>,
+) {
+    let grouped: HashSet<&PathBuf> = grouped.iter().collect();
+    let rest: Vec<PathBuf> = index::list_vars(scan_folder)
+        .into_iter()
+        .filter(|path| !grouped.contains(path) && !foreign.contains(path))
+        .collect();
+    let mut copied = 0;
+    for path in rest.iter() {
+        let target = output.join(path.strip_prefix(var_folder).unwrap());
+        match copy_var(path, &target) {
+            Ok(()) => copied += 1,
+            Err(e) => error!("Failed to export {}: {e}", path.to_string_lossy()),
         }
     }
+    summary!(
+        "Exported {copied} vars without duplicates to {} / 已将{copied}个无重复的Var导出",
+        output.to_string_lossy()
+    );
 }
 
-fn main() {
-    if !fs::exists("VaM.exe").unwrap() {
-        println!("Please put VarCleaner.exe under VaM folder which includes VaM.exe \n 请将VarCleaner.exe放在VaM.exe同级目录下");
-        show_message_box("Error/错误", "Please put VarCleaner.exe under VaM folder which includes VaM.exe \n 请将VarCleaner.exe放在VaM.exe同级目录下");
-        return;
+// A renamed merge no longer says where it came from, so meta.json lists its sources
+fn stamp_provenance(
+    partial: &Path,
+    filelist: &LinkedList<PathBuf>,
+    var_folder: &Path,
+) -> anyhow::Result<()> {
+    let mut meta = reader::VarReader::open(partial)?
+        .meta()
+        .cloned()
+        .filter(|meta| meta.is_object())
+        .unwrap_or_else(|| serde_json::json!({}));
+    meta["mergedFrom"] = filelist
+        .iter()
+        .map(|p| p.strip_prefix(var_folder).unwrap_or(p).to_string_lossy())
+        .collect();
+    let replacements =
+        HashMap::from([("meta.json".to_string(), serde_json::to_vec_pretty(&meta)?)]);
+    let stamped = partial.with_extension("stamped");
+    if let Err(e) = archive::rewrite_var(partial, &stamped, &replacements) {
+        let _ = fs::remove_file(&stamped);
+        return Err(e);
+    }
+    fs::rename(stamped, partial)?;
+    Ok(())
+}
+
+fn clean(
+    vam_folder: &Path,
+    args: &CleanArgs,
+    protected: &protect::Protected,
+) -> anyhow::Result<()> {
+    let var_folder = &root::addon_packages(vam_folder);
+    let var_merged_folder = &match &args.output {
+        Some(output) => std::path::absolute(output)?,
+        None => PathBuf::from(&var_folder).join("merged"),
+    };
+    // VaM would load the merge next to the originals it came from
+    if args.output.is_some() && var_merged_folder.starts_with(var_folder) {
+        anyhow::bail!(
+            "--output {} is inside AddonPackages / --output 不能位于AddonPackages中",
+            var_merged_folder.to_string_lossy()
+        );
     }
-    let vam_folder = env::current_dir().unwrap();
-    let var_folder = &vam_folder.join("AddonPackages");
-    let var_merged_folder = &PathBuf::from(&var_folder).join("merged");
-    let var_backup_folder = &PathBuf::from(&vam_folder).join("VarCleaner/Backup");
-    let dst_tmp_folder = &PathBuf::from(&vam_folder).join("VarCleaner/Tmp");
-    let var_folder_str = var_folder.to_string_lossy();
+    let var_backup_folder = &root::backup_folder(vam_folder);
+    let dst_tmp_folder = &root::tmp_folder(vam_folder);
+    let log_folder = &vam_folder.join("VarCleaner/Logs");
     let var_merged_folder_str = var_merged_folder.to_string_lossy();
     let var_backup_folder_str = var_backup_folder.to_string_lossy();
-    println!("VarCleaner will put merged duplicated var to {var_merged_folder_str}, and backup original var at {var_backup_folder_str}");
-    println!("VarCleaner 将清理过的重复Var放在{var_merged_folder_str}, 并将原始Var备份在{var_backup_folder_str}");
+    info!("VarCleaner will put merged duplicated var to {var_merged_folder_str}, and backup original var at {var_backup_folder_str}");
+    info!("VarCleaner 将清理过的重复Var放在{var_merged_folder_str}, 并将原始Var备份在{var_backup_folder_str}");
+
+    let scan_folder = &match &args.scope {
+        Some(scope) => var_folder.join(scope),
+        None => var_folder.clone(),
+    };
+    if !scan_folder.is_dir()
+        || !scan_folder.starts_with(var_folder)
+        || scan_folder
+            .components()
+            .any(|c| c == std::path::Component::ParentDir)
+    {
+        anyhow::bail!(
+            "Scope {} is not a folder inside AddonPackages / 范围必须是AddonPackages下的文件夹",
+            scan_folder.to_string_lossy()
+        );
+    }
+
+    if args.dry_run {
+        return dryrun::run(scan_folder, args, protected);
+    }
+    archive::remove_partials(var_merged_folder);
+    // Cleared with or without --stage-latest, the last run's copies would be duplicates
+    let staged = var_merged_folder.join(LATEST_RUN);
+    if staged.is_dir() {
+        readonly::check(&staged)?;
+        fs::remove_dir_all(&staged)?;
+    }
+    let deferred = quarantine::deferred_vars(vam_folder, scan_folder);
+    quarantine::placeholders(
+        vam_folder,
+        scan_folder,
+        protected,
+        args.limits == Limits::On,
+        &deferred,
+    );
+    if args.extract_nested {
+        nested::extract_all(vam_folder);
+    }
+    let foreign = quarantine::foreign_vars(vam_folder, scan_folder, &deferred);
 
-    let hpool = ThreadPool::new(12);
-    let file_dicts = generate_duplicate_var_files(&var_folder_str.to_string()).unwrap();
+    // A library VarCleaner never backed anything up from gets a cautious first run; an
+    // output elsewhere leaves the library as it is
+    let first_run = !var_backup_folder.exists() && args.output.is_none();
+    let limit = &budget::ChangeLimit::new(
+        args.max_changes
+            .unwrap_or(if first_run { FIRST_RUN_CHANGES } else { 0 }),
+        args.max_bytes
+            .unwrap_or(if first_run { FIRST_RUN_BYTES } else { 0 }),
+    );
+    let builtin = ((args.strip_builtin || config::get().strip_builtin) && !args.strict_compat)
+        .then(|| builtin::load(vam_folder));
+    let run = &CleanRun {
+        var_folder,
+        var_merged_folder,
+        var_backup_folder,
+        log_folder,
+        limit,
+        hashes: &hashing::HashCache::load(vam_folder),
+        bumper: &versions::VersionBumper::new(&match args.naming {
+            Naming::Bump => index::installed_packages(var_folder),
+            _ => HashMap::new(),
+        }),
+        builtin: builtin.as_ref(),
+        run_log: &stats::RunLog::create(vam_folder),
+        decisions: &decisions::Decisions::load(vam_folder),
+        grown: Mutex::new(Vec::new()),
+        differing: Mutex::new(Vec::new()),
+        journal: Mutex::new(Journal::new("clean")),
+        args,
+    };
+    let locked: Mutex<Vec<LinkedList<PathBuf>>> = Mutex::new(Vec::new());
+    stats::reset();
+    stats::recover_checkpoint(vam_folder);
+    let overnight = args.pace == Pace::Overnight;
+    let threads = if overnight {
+        config::get().threads.div_ceil(2)
+    } else {
+        config::get().threads
+    };
+    let hpool = ThreadPool::new(threads);
+    let file_dicts = generate_duplicate_var_files(scan_folder).unwrap();
+    let groups: Vec<LinkedList<PathBuf>> = file_dicts
+        .into_values()
+        .map(|filelist| {
+            filelist
+                .into_iter()
+                .filter(|p| !foreign.contains(p))
+                .collect::<LinkedList<PathBuf>>()
+        })
+        .filter(|filelist| {
+            filelist.len() > 1
+                && !filelist
+                    .iter()
+                    .any(|p| protected.skips(p) || deferred.contains(p))
+        })
+        .collect();
+    let mut moved: Vec<PathBuf> = groups.iter().flatten().cloned().collect();
+    if let Some(file) = &args.status_file {
+        status::start(file, groups.len());
+    }
+    let queue = &queue::Queue::new(groups);
+    if args.controls {
+        queue::listen_keyboard();
+    }
+    queue::stop_on_ctrl_c();
     scope_with(&hpool, |hscope| {
-        for (filename, filelist) in file_dicts.iter() {
-            let filename_clone = filename.clone();
-            let filelist_clone = filelist.clone();
+        for _ in 0..threads {
+            let locked = &locked;
             hscope.execute(move || {
-                if filelist_clone.len() > 1 {
-                    println!(
-                        "Process file {} Count {}",
-                        filename_clone,
-                        filelist_clone.len()
-                    );
-                    let pool = ThreadPool::new(filelist_clone.len());
-                    let var_tmp_folder = &dst_tmp_folder.join(PathBuf::from(&filename_clone));
-                    scope_with(&pool, |scope| {
-                        for (pos, item) in filelist_clone.iter().enumerate() {
-                            let item_clone = item.clone();
-                            scope.execute(move || {
-                                let relative_path = item_clone.strip_prefix(var_folder).unwrap();
-                                let backup_var_path = var_backup_folder.join(relative_path);
-                                unzip_one_file(&item_clone, &var_tmp_folder, pos);
-                                create_dir_all(backup_var_path.parent().unwrap()).unwrap();
-                                file_op(false, &item_clone, &backup_var_path);
-                            });
-                        }
-                    });
-                    if fs::exists(var_tmp_folder).unwrap() {
-                        rezip_one_file(&var_tmp_folder, &var_merged_folder.join(&filename_clone));
-                        fs::remove_dir_all(&var_tmp_folder).unwrap();
+                let _guard = queue::AbortOnPanic;
+                while let Some(filelist) = queue.pop() {
+                    let name = filelist.front().unwrap().file_name().unwrap();
+                    let name = name.to_string_lossy().to_string();
+                    status::begin(&name);
+                    let started = Instant::now();
+                    let merged = run.merge_group(&filelist);
+                    queue.done(&filelist);
+                    if !merged {
+                        locked.lock().unwrap().push(filelist);
+                    }
+                    status::end(&name);
+                    if overnight {
+                        stats::checkpoint(vam_folder);
+                        // Rest as long as the package took, so the machine idles half the time
+                        thread::sleep(started.elapsed().min(OVERNIGHT_REST));
                     }
                 }
             });
         }
     });
-    if fs::exists(&dst_tmp_folder).unwrap() {
-        fs::remove_dir_all(&dst_tmp_folder).unwrap();
+    let mut locked = locked.into_inner().unwrap();
+    if args.retry_locked && !locked.is_empty() && !queue::is_stopping() {
+        info!("Retrying {} packages that were in use", locked.len());
+        locked.retain(|filelist| !run.merge_group(filelist));
+    }
+    if !locked.is_empty() {
+        warning!("Retry these, they were in use / 以下Var被占用，请稍后重试:");
+        for filelist in locked.iter() {
+            info!(
+                "  {}",
+                filelist
+                    .front()
+                    .unwrap()
+                    .file_name()
+                    .unwrap()
+                    .to_string_lossy()
+            );
+        }
+    }
+    let grown = std::mem::take(&mut *run.grown.lock().unwrap());
+    if !grown.is_empty() {
+        warning!("These merges came out larger than their largest copy, originals are kept / 以下包合并后变大，已保留原始文件:");
+        for filename in grown.iter() {
+            info!("  {filename}");
+        }
+    }
+    let mut differing = std::mem::take(&mut *run.differing.lock().unwrap());
+    if !differing.is_empty() {
+        differing.sort();
+        warning!("The copies of these packages differ, their entries were merged; check them in VaM / 以下包的副本内容不同，已按条目合并，请在VaM中检查:");
+        for filename in differing.iter() {
+            info!("  {filename}");
+        }
+    }
+    // After the merges, so only what is left of each name group is compared
+    if args.by_content && args.output.is_none() && !queue::is_stopping() {
+        let vars: Vec<PathBuf> = index::list_vars(scan_folder)
+            .into_iter()
+            .filter(|p| !foreign.contains(p) && !deferred.contains(p) && !protected.skips(p))
+            .collect();
+        let groups = dedupe::identical_groups(&vars, run.hashes);
+        let renamed =
+            dedupe::back_up_renamed(&groups, var_folder, var_backup_folder, limit, run.run_log);
+        run.journal_backups(&renamed);
+        moved.extend(renamed.into_iter().map(|(from, _)| from));
+    }
+    let journal = run.journal.lock().unwrap();
+    if !journal.operations.is_empty() {
+        match journal.save(&vam_folder.join("VarCleaner/Journal")) {
+            Ok(_) => info!(
+                "restore --run-id {} undoes this run / 使用 restore --run-id {} 可撤销本次清理",
+                journal.run_id, journal.run_id
+            ),
+            Err(e) => error!("Failed to write the journal: {e}"),
+        }
+    }
+    run.hashes.save();
+    if limit.reached() {
+        let (changes, bytes) = limit.used();
+        warning!(
+            "Stopped after {changes} packages ({}) as limited by --max-changes/--max-bytes, run again to continue / 已达到本次修改上限（{changes}个包），请再次运行以继续",
+            format_size(bytes)
+        );
+    }
+    if args.export {
+        export_rest(var_folder, scan_folder, var_merged_folder, &moved, &foreign);
+    }
+    if !args.keep_empty_folders && args.output.is_none() {
+        backup::remove_emptied_folders(&moved, var_folder, &vam_folder.join("VarCleaner/Journal"));
+    }
+    sidecars::cleanup(vam_folder);
+    if args.refresh_vam_cache {
+        vamcache::refresh(vam_folder);
     }
-    println!("Done/完成清理");
-    show_message_box("Success/成功", "Done/完成清理");
+    stats::report();
+    stats::save(vam_folder);
+    stats::clear_checkpoint(vam_folder);
+    status::finish();
+    // Only an older version extracted copies here, an interrupted run of it left them behind
+    if dst_tmp_folder.is_dir() {
+        if let Err(e) = fs::remove_dir_all(dst_tmp_folder) {
+            warning!("Failed to remove {}: {e}", dst_tmp_folder.to_string_lossy());
+        }
+    }
+    summary!("Done/完成清理");
+    Ok(())
 }
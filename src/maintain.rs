@@ -0,0 +1,240 @@
+use crate::console::{error, info, summary, warning};
+use crate::format_size;
+use crate::index;
+use crate::protect::Protected;
+use crate::CleanArgs;
+use clap::ValueEnum;
+use serde::Deserialize;
+use serde::Serialize;
+use std::fs;
+use std::path::Path;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::time::Instant;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+use threadpool::ThreadPool;
+use threadpool_scope::scope_with;
+
+// Passes of `maintain`, run in the order given
+#[derive(ValueEnum, Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Pass {
+    /// Read every var into the JSON cache, only new and changed vars are opened
+    Scan,
+    /// Merge duplicate groups whose copies are byte-identical
+    Merge,
+    /// Store superseded versions as deltas against the newest version
+    Archive,
+    /// Delete backup files identical to a live var
+    Prune,
+    /// Save what the passes did to VarCleaner/reports/maintain-<time>.json
+    Report,
+}
+
+pub const ALL_PASSES: [Pass; 5] = [
+    Pass::Scan,
+    Pass::Merge,
+    Pass::Archive,
+    Pass::Prune,
+    Pass::Report,
+];
+
+#[derive(Serialize)]
+struct PassResult {
+    pass: Pass,
+    ok: bool,
+    seconds: u64,
+}
+
+#[derive(Serialize)]
+struct Report<'a> {
+    finished: u64,
+    passes: &'a [PassResult],
+    // The run report of the merge pass
+    merge_report: Option<String>,
+}
+
+// Runs `passes` one after the other without asking anything, for Task Scheduler. A failed
+// pass is reported and the next one still runs; returns whether every pass succeeded.
+pub fn run(vam_folder: &Path, passes: &[Pass], protected: &Protected) -> bool {
+    crate::ui::set_headless();
+    let mut results: Vec<PassResult> = Vec::new();
+    for &pass in passes {
+        run_pass(vam_folder, pass, passes, &mut results, protected);
+    }
+    finish(&results)
+}
+
+// What frees space for how much work, cheapest first: deleting backups identical to live vars
+// only deletes, an identical merge hashes and moves copies into the backup (pruned right
+// after), archiving rewrites every superseded version as a delta
+const FREEING_STEPS: [&[Pass]; 3] = [
+    &[Pass::Prune],
+    &[Pass::Merge, Pass::Prune],
+    &[Pass::Archive],
+];
+
+// Runs the freeing steps until the drive holding AddonPackages has `target` bytes free, then
+// stops; returns whether the target was reached
+pub fn free_up(vam_folder: &Path, target: u64, protected: &Protected) -> bool {
+    crate::ui::set_headless();
+    let var_folder = &crate::root::addon_packages(vam_folder);
+    let free = || crate::diskspace::free_space(var_folder).unwrap_or(0);
+    let mut results: Vec<PassResult> = Vec::new();
+    let mut done: Vec<Pass> = Vec::new();
+    for step in FREEING_STEPS {
+        let free = free();
+        if free >= target {
+            break;
+        }
+        info!(
+            "{} free, {} to go / 可用空间{}，还差{}",
+            format_size(free),
+            format_size(target - free),
+            format_size(free),
+            format_size(target - free)
+        );
+        for &pass in step {
+            done.push(pass);
+            run_pass(vam_folder, pass, &done, &mut results, protected);
+        }
+    }
+    done.push(Pass::Report);
+    run_pass(vam_folder, Pass::Report, &done, &mut results, protected);
+    finish(&results);
+    let free = free();
+    if free < target {
+        error!(
+            "Only {} free after every step, {} short of the target / 所有步骤完成后可用空间仅{}，距目标还差{}",
+            format_size(free),
+            format_size(target - free),
+            format_size(free),
+            format_size(target - free)
+        );
+        return false;
+    }
+    summary!(
+        "{} free, the target of {} is reached / 可用空间{}，已达到目标",
+        format_size(free),
+        format_size(target),
+        format_size(free)
+    );
+    true
+}
+
+fn run_pass(
+    vam_folder: &Path,
+    pass: Pass,
+    passes: &[Pass],
+    results: &mut Vec<PassResult>,
+    protected: &Protected,
+) {
+    info!("Maintenance pass {pass:?} / 维护步骤 {pass:?}");
+    let started = Instant::now();
+    let ok = match pass {
+        Pass::Scan => scan(&crate::root::addon_packages(vam_folder)),
+        Pass::Merge => {
+            // Only copies with nothing to choose between; groups of differing copies
+            // wait for a run someone watches. The first-run limits still apply.
+            let args = CleanArgs {
+                identical_only: true,
+                ..CleanArgs::default()
+            };
+            match crate::clean(vam_folder, &args, protected) {
+                Ok(()) => true,
+                Err(e) => {
+                    error!("{e}");
+                    false
+                }
+            }
+        }
+        Pass::Archive => match crate::delta::archive_superseded(vam_folder, protected) {
+            Ok(()) => true,
+            Err(e) => {
+                error!("{e}");
+                false
+            }
+        },
+        Pass::Prune => {
+            crate::backup::dedupe(vam_folder, crate::backup::DedupeAction::Drop);
+            true
+        }
+        Pass::Report => save_report(vam_folder, passes, results),
+    };
+    results.push(PassResult {
+        pass,
+        ok,
+        seconds: started.elapsed().as_secs(),
+    });
+}
+
+fn finish(results: &[PassResult]) -> bool {
+    let failed = results.iter().filter(|r| !r.ok).count();
+    if failed == 0 {
+        summary!(
+            "Maintenance finished, {} passes / 维护完成，共{}步",
+            results.len(),
+            results.len()
+        );
+    } else {
+        error!("Maintenance finished, {failed} passes failed / 维护完成，{failed}步失败");
+    }
+    failed == 0
+}
+
+// Reading every var once fills VarCleaner/Cache/json; vars whose size and mtime did not
+// change since are taken from the cache without opening them
+fn scan(var_folder: &Path) -> bool {
+    let vars = index::list_vars(var_folder);
+    let unreadable = AtomicUsize::new(0);
+    let pool = ThreadPool::new(crate::config::get().threads);
+    scope_with(&pool, |scope| {
+        for path in vars.iter() {
+            let unreadable = &unreadable;
+            scope.execute(move || {
+                if let Err(e) = crate::jsoncache::index(path) {
+                    warning!("Failed to read {}: {e}", path.to_string_lossy());
+                    unreadable.fetch_add(1, Ordering::Relaxed);
+                }
+            });
+        }
+    });
+    let unreadable = unreadable.into_inner();
+    summary!(
+        "Scanned {} vars, {unreadable} unreadable / 已扫描{}个Var，{unreadable}个无法读取",
+        vars.len(),
+        vars.len()
+    );
+    true
+}
+
+fn save_report(vam_folder: &Path, passes: &[Pass], results: &[PassResult]) -> bool {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    let report = Report {
+        finished: secs,
+        passes: results,
+        merge_report: passes
+            .contains(&Pass::Merge)
+            .then(|| crate::stats::latest_report(vam_folder))
+            .flatten()
+            .map(|p| p.to_string_lossy().to_string()),
+    };
+    let path = vam_folder.join(format!("VarCleaner/reports/maintain-{secs}.json"));
+    let written = crate::readonly::check(&path)
+        .and_then(|_| fs::create_dir_all(path.parent().unwrap()))
+        .and_then(|_| fs::write(&path, serde_json::to_vec_pretty(&report).unwrap()));
+    match written {
+        Ok(()) => {
+            info!("Saved {}", path.to_string_lossy());
+            true
+        }
+        Err(e) => {
+            error!("Failed to write {}: {e}", path.to_string_lossy());
+            false
+        }
+    }
+}
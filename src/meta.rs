@@ -0,0 +1,105 @@
+use crate::console::{info, warning};
+use crate::package::PackageId;
+use serde::Deserialize;
+use serde::Serialize;
+use serde_json::Map;
+use serde_json::Value;
+
+// The meta.json of a var. Only what VarCleaner checks is typed, everything else (license,
+// dependencies, custom options) is carried along as it was.
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Meta {
+    #[serde(default)]
+    pub creator_name: String,
+    #[serde(default)]
+    pub package_name: String,
+    // Files and folders of the package, shown by VaM's package manager; an item without an
+    // entry makes VaM report the package as broken
+    #[serde(default)]
+    pub content_list: Vec<String>,
+    #[serde(flatten)]
+    pub other: Map<String, Value>,
+}
+
+impl Meta {
+    pub fn parse(bytes: &[u8]) -> anyhow::Result<Meta> {
+        Ok(serde_json::from_value(crate::index::parse_json(bytes)?)?)
+    }
+
+    // The meta.json VaM's packager would write for `package` holding `content`
+    pub fn generate(package: &str, content: Vec<String>) -> Meta {
+        let id = PackageId::parse(package);
+        let mut other = Map::new();
+        other.insert("licenseType".to_string(), Value::from("FC"));
+        other.insert("dependencies".to_string(), Value::Object(Map::new()));
+        Meta {
+            creator_name: id.as_ref().map_or(String::new(), |id| id.creator.clone()),
+            package_name: id.map_or(package.to_string(), |id| id.name),
+            content_list: content,
+            other,
+        }
+    }
+
+    pub fn to_vec(&self) -> Vec<u8> {
+        serde_json::to_vec_pretty(self).unwrap()
+    }
+
+    // Brings contentList in line with the entries of a merged var: items naming no entry are
+    // dropped, files no item covers (taken from another copy) are added. An empty list is left
+    // alone, the package never listed its content. Returns whether anything changed.
+    pub fn reconcile(&mut self, package: &str, names: &[String]) -> bool {
+        if self.content_list.is_empty() {
+            return false;
+        }
+        let covers = |item: &str, name: &str| {
+            name == item
+                || name
+                    .strip_prefix(item)
+                    .is_some_and(|rest| rest.starts_with('/'))
+        };
+        let missing: Vec<String> = self
+            .content_list
+            .iter()
+            .filter(|item| !names.iter().any(|name| covers(item, name)))
+            .cloned()
+            .collect();
+        let unlisted: Vec<String> = names
+            .iter()
+            .filter(|name| !self.content_list.iter().any(|item| covers(item, name)))
+            .cloned()
+            .collect();
+        if !missing.is_empty() {
+            warning!(
+                "{package}: contentList names {} missing entries, dropped: {}",
+                missing.len(),
+                summarize(&missing)
+            );
+            self.content_list.retain(|item| !missing.contains(item));
+        }
+        if !unlisted.is_empty() {
+            info!(
+                "{package}: added {} entries missing from contentList: {}",
+                unlisted.len(),
+                summarize(&unlisted)
+            );
+            self.content_list.extend(unlisted.iter().cloned());
+        }
+        !missing.is_empty() || !unlisted.is_empty()
+    }
+}
+
+// The first few names, the rest only counted
+fn summarize(names: &[String]) -> String {
+    const SHOWN: usize = 3;
+    let mut text = names
+        .iter()
+        .take(SHOWN)
+        .cloned()
+        .collect::<Vec<_>>()
+        .join(", ");
+    if names.len() > SHOWN {
+        text.push_str(&format!(" and {} more", names.len() - SHOWN));
+    }
+    text
+}
@@ -0,0 +1,102 @@
+use crate::archive;
+use crate::console::{error, info, summary, warning};
+use crate::index;
+use crate::protect::Protected;
+use std::collections::HashMap;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use threadpool::ThreadPool;
+use threadpool_scope::scope_with;
+
+struct PreloadInfo {
+    path: PathBuf,
+    morph_count: usize,
+    meta: serde_json::Value,
+}
+
+fn preloads_morphs(meta: &serde_json::Value) -> bool {
+    match meta.pointer("/customOptions/preloadMorphs") {
+        Some(serde_json::Value::String(s)) => s.eq_ignore_ascii_case("true"),
+        Some(serde_json::Value::Bool(b)) => *b,
+        _ => false,
+    }
+}
+
+fn scan_var(path: &Path) -> anyhow::Result<Option<PreloadInfo>> {
+    let var_index = crate::jsoncache::index(path)?;
+    let meta = match var_index.meta {
+        Some(meta) if preloads_morphs(&meta) => meta,
+        _ => return Ok(None),
+    };
+    let morph_count = var_index
+        .entries
+        .iter()
+        .filter(|e| e.name.contains("/Morphs/") && e.name.ends_with(".vmi"))
+        .count();
+    Ok(Some(PreloadInfo {
+        path: path.to_path_buf(),
+        morph_count,
+        meta,
+    }))
+}
+
+fn fix_var(info: &PreloadInfo, var_folder: &Path, backup_folder: &Path) -> anyhow::Result<()> {
+    let mut meta = info.meta.clone();
+    meta["customOptions"]["preloadMorphs"] = serde_json::Value::String("false".to_string());
+    let mut replacements = HashMap::new();
+    replacements.insert("meta.json".to_string(), serde_json::to_vec_pretty(&meta)?);
+    let backup_path =
+        crate::backup::free_path(backup_folder.join(info.path.strip_prefix(var_folder)?));
+    archive::replace_var(&info.path, &backup_path, &replacements)
+}
+
+pub fn audit(var_folder: &Path, backup_folder: &Path, fix: bool, protected: &Protected) {
+    let found: Mutex<Vec<PreloadInfo>> = Mutex::new(Vec::new());
+    let pool = ThreadPool::new(crate::config::get().threads);
+    let paths = index::list_vars(var_folder);
+    scope_with(&pool, |scope| {
+        for path in paths.iter() {
+            let found = &found;
+            scope.execute(move || match scan_var(path) {
+                Ok(Some(info)) => found.lock().unwrap().push(info),
+                Ok(None) => {}
+                Err(_) => warning!("zipfile {} is invaild", path.to_string_lossy()),
+            });
+        }
+    });
+    let mut found = found.into_inner().unwrap();
+    found.sort_by(|a, b| a.path.cmp(&b.path));
+
+    summary!(
+        "{} vars force-preload their morphs into every session / {}个Var强制预加载变形",
+        found.len(),
+        found.len()
+    );
+    for info in found.iter() {
+        let relative = info.path.strip_prefix(var_folder).unwrap_or(&info.path);
+        info!(
+            "  {:>6} morphs  {}",
+            info.morph_count,
+            relative.to_string_lossy()
+        );
+    }
+    if !fix || found.is_empty() {
+        return;
+    }
+    let mut fixed = 0;
+    for info in found.iter().filter(|info| !protected.skips(&info.path)) {
+        match fix_var(info, var_folder, backup_folder) {
+            Ok(()) => fixed += 1,
+            Err(e) => error!("Failed to fix {}: {e}", info.path.to_string_lossy()),
+        }
+    }
+    summary!(
+        "Disabled morph preload in {fixed} vars, originals are in {}",
+        backup_folder.to_string_lossy()
+    );
+    summary!(
+        "已关闭{fixed}个Var的变形预加载, 原始Var备份在{}",
+        backup_folder.to_string_lossy()
+    );
+}
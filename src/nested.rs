@@ -0,0 +1,109 @@
+use crate::archive;
+use crate::console::{error, success, summary, verbose, warning};
+use crate::index;
+use crate::journal::Journal;
+use crate::journal::Operation;
+use std::collections::HashMap;
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use threadpool::ThreadPool;
+use threadpool_scope::scope_with;
+
+// A package shipped inside another package; VaM never loads these
+pub fn is_nested(name: &str) -> bool {
+    index::is_var(Path::new(name))
+}
+
+fn nested_entries(path: &Path) -> anyhow::Result<Vec<String>> {
+    let archive = index::open_var(path)?;
+    Ok(archive
+        .file_names()
+        .filter(|n| is_nested(n))
+        .map(|n| n.to_string())
+        .collect())
+}
+
+fn extract(path: &Path, name: &str, target: &Path) -> anyhow::Result<()> {
+    let mut archive = index::open_var(path)?;
+    let mut file = archive.by_name(name)?;
+    crate::readonly::check(target)?;
+    fs::create_dir_all(target.parent().unwrap())?;
+    let partial = archive::partial_path(target);
+    let result = File::create(&partial)
+        .and_then(|mut out| io::copy(&mut file, &mut out))
+        .map_err(anyhow::Error::from)
+        .and_then(|_| index::open_var(&partial).map(|_| ()));
+    if let Err(e) = result {
+        let _ = fs::remove_file(&partial);
+        return Err(e);
+    }
+    fs::rename(&partial, target)?;
+    Ok(())
+}
+
+// Copy nested vars that are not installed yet out to AddonPackages/nested
+pub fn extract_all(vam_folder: &Path) {
+    let var_folder = &crate::root::addon_packages(vam_folder);
+    let nested_folder = &var_folder.join("nested");
+    let paths = index::list_vars(var_folder);
+    let found: Mutex<Vec<(PathBuf, String)>> = Mutex::new(Vec::new());
+    let pool = ThreadPool::new(crate::config::get().threads);
+    scope_with(&pool, |scope| {
+        for path in paths.iter() {
+            let found = &found;
+            scope.execute(move || {
+                for name in nested_entries(path).unwrap_or_default() {
+                    found.lock().unwrap().push((path.clone(), name));
+                }
+            });
+        }
+    });
+    let mut found = found.into_inner().unwrap();
+    found.sort();
+
+    let mut installed: HashMap<String, PathBuf> = index::installed_packages(var_folder);
+    let mut journal = Journal::new("extract-nested");
+    for (path, name) in found.iter() {
+        let file_name = Path::new(name).file_name().unwrap();
+        let package = index::package_name(Path::new(file_name));
+        if installed.contains_key(&package) {
+            verbose!(
+                "{package} in {} is already installed",
+                path.to_string_lossy()
+            );
+            continue;
+        }
+        let target = nested_folder.join(file_name);
+        match extract(path, name, &target) {
+            Ok(()) => {
+                success!("Extracted {package} from {}", path.to_string_lossy());
+                journal.record(Operation::Create {
+                    path: target.clone(),
+                    hash: None,
+                });
+                installed.insert(package, target);
+            }
+            Err(e) => warning!(
+                "Failed to extract {name} from {}: {e}",
+                path.to_string_lossy()
+            ),
+        }
+    }
+    summary!(
+        "Found {} nested vars, extracted {} / 发现{}个内嵌Var，已提取{}个",
+        found.len(),
+        journal.operations.len(),
+        found.len(),
+        journal.operations.len()
+    );
+    if journal.operations.is_empty() {
+        return;
+    }
+    if let Err(e) = journal.save(&vam_folder.join("VarCleaner/Journal")) {
+        error!("Failed to write the journal: {e}");
+    }
+}
@@ -0,0 +1,127 @@
+use std::cmp::Ordering;
+use std::fmt;
+
+// The version part of a package name or a reference to one
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Version {
+    Exact(u64),
+    // `.latest`: the newest installed version
+    Latest,
+    // `.min3`: version 3 or newer, the newest installed one is used
+    Min(u64),
+}
+
+impl Version {
+    fn parse(text: &str) -> Option<Version> {
+        if text.eq_ignore_ascii_case("latest") {
+            return Some(Version::Latest);
+        }
+        match text.strip_prefix("min") {
+            Some(min) => Some(Version::Min(min.parse().ok()?)),
+            None => Some(Version::Exact(text.parse().ok()?)),
+        }
+    }
+
+    // Whether the installed version `version` satisfies this reference
+    pub fn accepts(self, version: u64) -> bool {
+        match self {
+            Version::Exact(exact) => version == exact,
+            Version::Latest => true,
+            Version::Min(min) => version >= min,
+        }
+    }
+
+    // `min3` sorts just before 3, `latest` after every number
+    fn sort_key(self) -> (u64, u8) {
+        match self {
+            Version::Min(min) => (min, 0),
+            Version::Exact(exact) => (exact, 1),
+            Version::Latest => (u64::MAX, 2),
+        }
+    }
+}
+
+impl Ord for Version {
+    fn cmp(&self, other: &Version) -> Ordering {
+        self.sort_key().cmp(&other.sort_key())
+    }
+}
+
+impl PartialOrd for Version {
+    fn partial_cmp(&self, other: &Version) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Version::Exact(exact) => write!(f, "{exact}"),
+            Version::Latest => write!(f, "latest"),
+            Version::Min(min) => write!(f, "min{min}"),
+        }
+    }
+}
+
+// `Creator.Name.Version`, as in var file names and dependency references
+#[derive(Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Debug)]
+pub struct PackageId {
+    pub creator: String,
+    pub name: String,
+    pub version: Version,
+}
+
+impl PackageId {
+    // `Creator.Pkg.3`, `Creator.Pkg.3.var`, `Creator.Pkg.latest` or `Creator.Pkg.min3`
+    pub fn parse(text: &str) -> Option<PackageId> {
+        let end = text.len().saturating_sub(4);
+        let text = match text.get(end..) {
+            Some(extension) if extension.eq_ignore_ascii_case(".var") => &text[..end],
+            _ => text,
+        };
+        let (base, version) = text.rsplit_once('.')?;
+        let (creator, name) = base.split_once('.')?;
+        if creator.is_empty() || name.is_empty() {
+            return None;
+        }
+        Some(PackageId {
+            creator: creator.to_string(),
+            name: name.to_string(),
+            version: Version::parse(version)?,
+        })
+    }
+
+    // `Creator.Pkg`, shared by every version
+    pub fn base(&self) -> String {
+        format!("{}.{}", self.creator, self.name)
+    }
+
+    pub fn exact_version(&self) -> Option<u64> {
+        match self.version {
+            Version::Exact(version) => Some(version),
+            _ => None,
+        }
+    }
+
+    pub fn with_version(&self, version: u64) -> PackageId {
+        PackageId {
+            version: Version::Exact(version),
+            ..self.clone()
+        }
+    }
+
+    // Whether the installed package `installed` satisfies this reference
+    pub fn accepts(&self, installed: &PackageId) -> bool {
+        self.creator == installed.creator
+            && self.name == installed.name
+            && installed
+                .exact_version()
+                .is_some_and(|version| self.version.accepts(version))
+    }
+}
+
+impl fmt::Display for PackageId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.creator, self.name, self.version)
+    }
+}
@@ -0,0 +1,130 @@
+use crate::console::{info, success, summary, warning};
+use crate::format_size;
+use crate::protect::Protected;
+use path_slash::PathExt;
+use serde::Deserialize;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::Path;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+// The duplicate groups clean would merge, with enough about every copy to notice when the
+// library changed since: written by `plan`, checked again by `plan --compare`
+#[derive(Serialize, Deserialize)]
+struct Plan {
+    created: u64,
+    // Package file name -> its copies, in the order VaM finds them
+    groups: BTreeMap<String, Vec<PlannedCopy>>,
+}
+
+#[derive(Serialize, Deserialize, PartialEq)]
+struct PlannedCopy {
+    // Relative to AddonPackages, with slashes
+    path: String,
+    size: u64,
+    mtime: u128,
+}
+
+fn build(var_folder: &Path, protected: &Protected) -> anyhow::Result<Plan> {
+    let mut groups = BTreeMap::new();
+    for (name, copies) in crate::generate_duplicate_var_files(var_folder)? {
+        if copies.len() < 2 || copies.iter().any(|p| protected.skips(p)) {
+            continue;
+        }
+        let copies = copies
+            .iter()
+            .map(|path| {
+                let metadata = fs::metadata(path).ok();
+                PlannedCopy {
+                    path: path
+                        .strip_prefix(var_folder)
+                        .unwrap()
+                        .to_slash_lossy()
+                        .to_string(),
+                    size: metadata.as_ref().map_or(0, |m| m.len()),
+                    mtime: metadata
+                        .and_then(|m| m.modified().ok())
+                        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                        .map_or(0, |d| d.as_nanos()),
+                }
+            })
+            .collect();
+        groups.insert(name, copies);
+    }
+    Ok(Plan {
+        created: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs(),
+        groups,
+    })
+}
+
+pub fn write(var_folder: &Path, protected: &Protected, output: &Path) -> anyhow::Result<()> {
+    let plan = build(var_folder, protected)?;
+    let copies: usize = plan.groups.values().map(|c| c.len()).sum();
+    let size: u64 = plan.groups.values().flatten().map(|c| c.size).sum();
+    for (name, group) in plan.groups.iter() {
+        info!("{name}");
+        for copy in group {
+            info!("  {} {}", copy.path, format_size(copy.size));
+        }
+    }
+    crate::readonly::check(output)?;
+    fs::write(output, serde_json::to_vec_pretty(&plan)?)?;
+    summary!(
+        "{} packages with {copies} copies ({}) would be merged, plan saved to {} / {}个包将被合并，计划已保存",
+        plan.groups.len(),
+        format_size(size),
+        output.to_string_lossy(),
+        plan.groups.len()
+    );
+    Ok(())
+}
+
+// What changed in the library since `old` was written; the plan is still valid when nothing did
+pub fn compare(var_folder: &Path, protected: &Protected, old: &Path) -> anyhow::Result<()> {
+    let old: Plan = serde_json::from_slice(&fs::read(old)?)?;
+    let new = build(var_folder, protected)?;
+    let names: BTreeSet<&String> = old.groups.keys().chain(new.groups.keys()).collect();
+    let mut changes = 0;
+    for name in names {
+        match (old.groups.get(name), new.groups.get(name)) {
+            (None, Some(copies)) => {
+                changes += 1;
+                warning!("{name}: new duplicate, {} copies", copies.len());
+            }
+            (Some(_), None) => {
+                changes += 1;
+                warning!("{name}: no longer duplicated");
+            }
+            (Some(before), Some(after)) if before != after => {
+                changes += 1;
+                warning!("{name}: copies changed");
+                for copy in before.iter().filter(|c| !after.contains(c)) {
+                    info!("  - {} {}", copy.path, format_size(copy.size));
+                }
+                for copy in after.iter().filter(|c| !before.contains(c)) {
+                    info!("  + {} {}", copy.path, format_size(copy.size));
+                }
+            }
+            _ => {}
+        }
+    }
+    let age = new.created.saturating_sub(old.created);
+    if changes == 0 {
+        success!(
+            "Nothing changed since the plan was made {}h ago, it is still valid / 计划生成后库未发生变化，计划仍然有效",
+            age / 3600
+        );
+    } else {
+        summary!(
+            "{changes} packages changed since the plan was made {}h ago, make a new plan / 计划生成后有{changes}个包发生变化，请重新生成计划",
+            age / 3600
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,12 @@
+// Everything that differs between the Windows build and the Linux and macOS builds, for VaM
+// run through Proton or Wine and libraries kept on a NAS. The rest of the crate only calls
+// what is exported here.
+#[cfg(unix)]
+mod unix;
+#[cfg(windows)]
+mod windows;
+
+#[cfg(unix)]
+pub use unix::*;
+#[cfg(windows)]
+pub use windows::*;
@@ -0,0 +1,218 @@
+use crate::console;
+use crate::console::warning;
+use crate::console::Level;
+use std::ffi::CString;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::fs::MetadataExt;
+use std::os::unix::net::UnixListener;
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::sync::OnceLock;
+use std::thread;
+use std::time::Duration;
+
+// Only Windows before 10 needs the legacy console handling
+pub fn legacy_windows() -> Option<(u32, u32)> {
+    None
+}
+
+// Terminals understand the color codes as they are
+pub fn enable_ansi_colors() -> bool {
+    true
+}
+
+// Bytes free for this user on the file system holding `path`
+pub fn free_space(path: &Path) -> io::Result<u64> {
+    let path = CString::new(path.as_os_str().as_bytes())?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(path.as_ptr(), &mut stat) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(stat.f_bavail as u64 * stat.f_frsize as u64)
+}
+
+// Open files are not locked here, VaM under Proton or Wine does not keep anyone else out
+pub fn is_lock_io(_: &io::Error) -> bool {
+    false
+}
+
+pub fn is_locked(_: &Path) -> bool {
+    false
+}
+
+// A rename onto another file system, which has to be a copy instead
+pub fn is_cross_device(e: &io::Error) -> bool {
+    e.raw_os_error() == Some(libc::EXDEV)
+}
+
+static CTRL_C: OnceLock<fn()> = OnceLock::new();
+static INTERRUPTS: AtomicUsize = AtomicUsize::new(0);
+
+extern "C" fn count_interrupt(_: libc::c_int) {
+    INTERRUPTS.fetch_add(1, Ordering::Relaxed);
+}
+
+// The signal handler only counts, `handler` runs on a watcher thread where it may lock and print
+pub fn on_ctrl_c(handler: fn()) {
+    if CTRL_C.set(handler).is_err() {
+        return;
+    }
+    let count: extern "C" fn(libc::c_int) = count_interrupt;
+    unsafe {
+        libc::signal(libc::SIGINT, count as libc::sighandler_t);
+    }
+    thread::spawn(|| {
+        let mut handled = 0;
+        loop {
+            thread::sleep(Duration::from_millis(100));
+            while handled < INTERRUPTS.load(Ordering::Relaxed) {
+                handled += 1;
+                (CTRL_C.get().unwrap())();
+            }
+        }
+    });
+}
+
+fn home() -> Option<PathBuf> {
+    std::env::var_os("HOME").map(PathBuf::from)
+}
+
+// Steam's own folders on Linux and macOS, their libraryfolders.vdf lists the other libraries
+pub fn steam_folders() -> Vec<PathBuf> {
+    let Some(home) = home() else {
+        return Vec::new();
+    };
+    [
+        ".steam/steam",
+        ".local/share/Steam",
+        "Library/Application Support/Steam",
+    ]
+    .iter()
+    .map(|folder| home.join(folder))
+    .filter(|folder| folder.is_dir())
+    .collect()
+}
+
+// Folders VaM is often installed in without Steam, including the C: drive of Wine's default
+// prefix
+pub fn install_roots() -> Vec<PathBuf> {
+    let Some(home) = home() else {
+        return Vec::new();
+    };
+    vec![home.clone(), home.join("Games"), home.join(".wine/drive_c")]
+}
+
+// Per user settings, $XDG_CONFIG_HOME or ~/.config
+pub fn config_folder() -> Option<PathBuf> {
+    match std::env::var_os("XDG_CONFIG_HOME") {
+        Some(folder) if !folder.is_empty() => Some(PathBuf::from(folder)),
+        _ => home().map(|home| home.join(".config")),
+    }
+}
+
+// The mount point of the file system holding `path`: the last ancestor on the same device
+pub fn mount_point(path: &Path) -> String {
+    let Ok(device) = path.metadata().map(|m| m.dev()) else {
+        return String::new();
+    };
+    let mut mount = path;
+    for ancestor in path.ancestors().skip(1) {
+        match ancestor.metadata() {
+            Ok(metadata) if metadata.dev() == device => mount = ancestor,
+            _ => break,
+        }
+    }
+    mount.to_string_lossy().to_string()
+}
+
+// Message boxes go to the console, nothing waits for them to be closed
+pub const MESSAGE_BOXES: bool = false;
+
+pub fn message_box(title: &str, message: &str) {
+    console::print(Level::Summary, &format!("{title}: {message}"));
+}
+
+pub fn show_completion(message: &str, report: &Path, merged: &Path, logs: Option<PathBuf>) {
+    console::print(Level::Summary, message);
+    console::print(
+        Level::Summary,
+        &format!("Report / 报告: {}", report.to_string_lossy()),
+    );
+    console::print(
+        Level::Summary,
+        &format!("Merged folder / 合并文件夹: {}", merged.to_string_lossy()),
+    );
+    if let Some(logs) = logs {
+        console::print(
+            Level::Summary,
+            &format!("Logs / 日志: {}", logs.to_string_lossy()),
+        );
+    }
+}
+
+static SYSLOG: AtomicBool = AtomicBool::new(false);
+
+// The event log here is syslog, or the journal on systemd systems
+pub fn open_event_log() -> bool {
+    unsafe {
+        libc::openlog(c"VarCleaner".as_ptr(), libc::LOG_PID, libc::LOG_USER);
+    }
+    SYSLOG.store(true, Ordering::Relaxed);
+    true
+}
+
+pub fn write_event(error: bool, _: u32, message: &str) {
+    if !SYSLOG.load(Ordering::Relaxed) {
+        return;
+    }
+    let Ok(message) = CString::new(message) else {
+        return;
+    };
+    let priority = if error { libc::LOG_ERR } else { libc::LOG_INFO };
+    unsafe {
+        libc::syslog(priority, c"%s".as_ptr(), message.as_ptr());
+    }
+}
+
+pub const DEFAULT_PIPE: &str = "/tmp/varcleaner.sock";
+
+// A client of the daemon
+pub type Connection = UnixStream;
+
+pub struct Listener(UnixListener);
+
+pub fn listen(name: &str) -> io::Result<Listener> {
+    // A socket left behind by a daemon that was killed refuses the bind, anything else at
+    // that path is not ours to remove
+    if let Ok(metadata) = std::fs::symlink_metadata(name) {
+        if !metadata.file_type().is_socket() {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{name} exists and is no socket"),
+            ));
+        }
+        if UnixStream::connect(name).is_err() {
+            std::fs::remove_file(name)?;
+        }
+    }
+    UnixListener::bind(name).map(Listener)
+}
+
+impl Listener {
+    // Waits for the next client
+    pub fn accept(&self) -> io::Result<Connection> {
+        loop {
+            match self.0.accept() {
+                Ok((stream, _)) => return Ok(stream),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => warning!("Client failed to connect: {e}"),
+            }
+        }
+    }
+}
@@ -0,0 +1,350 @@
+use crate::console::warning;
+use std::ffi::OsStr;
+use std::fs::File;
+use std::fs::OpenOptions;
+use std::io;
+use std::mem::size_of;
+use std::os::windows::ffi::OsStrExt;
+use std::os::windows::fs::OpenOptionsExt;
+use std::os::windows::io::FromRawHandle;
+use std::path::Path;
+use std::path::PathBuf;
+use std::ptr::null;
+use std::ptr::null_mut;
+use std::sync::Mutex;
+use std::sync::OnceLock;
+use winapi::shared::minwindef::{LPARAM, WPARAM};
+use winapi::shared::ntdef::ULARGE_INTEGER;
+use winapi::shared::windef::{HHOOK, HWND};
+use winapi::shared::winerror::ERROR_PIPE_CONNECTED;
+use winapi::um::consoleapi::{GetConsoleMode, SetConsoleCtrlHandler, SetConsoleMode};
+use winapi::um::fileapi::GetDiskFreeSpaceExW;
+use winapi::um::handleapi::INVALID_HANDLE_VALUE;
+use winapi::um::namedpipeapi::{ConnectNamedPipe, CreateNamedPipeW};
+use winapi::um::processenv::GetStdHandle;
+use winapi::um::processthreadsapi::GetCurrentThreadId;
+use winapi::um::shellapi::ShellExecuteW;
+use winapi::um::winbase::{
+    RegisterEventSourceW, ReportEventW, PIPE_ACCESS_DUPLEX, PIPE_READMODE_BYTE, PIPE_TYPE_BYTE,
+    PIPE_UNLIMITED_INSTANCES, PIPE_WAIT, STD_OUTPUT_HANDLE,
+};
+use winapi::um::wincon::ENABLE_VIRTUAL_TERMINAL_PROCESSING;
+use winapi::um::winnt::{EVENTLOG_ERROR_TYPE, EVENTLOG_INFORMATION_TYPE, OSVERSIONINFOW};
+use winapi::um::winuser::{
+    CallNextHookEx, MessageBoxIndirectW, MessageBoxW, SetDlgItemTextW, SetWindowsHookExW,
+    UnhookWindowsHookEx, HCBT_ACTIVATE, HELPINFO, IDCANCEL, IDHELP, IDNO, IDYES, MB_HELP,
+    MB_ICONINFORMATION, MB_OK, MB_SYSTEMMODAL, MB_YESNOCANCEL, MSGBOXPARAMSW, SW_SHOWNORMAL,
+    WH_CBT,
+};
+
+fn to_wide_string(s: &str) -> Vec<u16> {
+    OsStr::new(s)
+        .encode_wide()
+        .chain(std::iter::once(0))
+        .collect()
+}
+
+#[link(name = "ntdll")]
+extern "system" {
+    // GetVersionExW answers 6.2 to every exe without a manifest, this one does not lie
+    fn RtlGetVersion(info: *mut OSVERSIONINFOW) -> i32;
+}
+
+// Major and minor Windows version: 6.1 is Windows 7, 6.2 and 6.3 are 8 and 8.1
+fn windows_version() -> (u32, u32) {
+    static VERSION: OnceLock<(u32, u32)> = OnceLock::new();
+    *VERSION.get_or_init(|| {
+        let mut info: OSVERSIONINFOW = unsafe { std::mem::zeroed() };
+        info.dwOSVersionInfoSize = size_of::<OSVERSIONINFOW>() as u32;
+        if unsafe { RtlGetVersion(&mut info) } != 0 {
+            return (10, 0);
+        }
+        (info.dwMajorVersion, info.dwMinorVersion)
+    })
+}
+
+// The version of Windows 7 and 8, which still run VaM; their console has no ANSI colours and
+// Rust's std from 1.78 on needs Windows 10, so those builds come from the win7 targets (see
+// README.txt)
+pub fn legacy_windows() -> Option<(u32, u32)> {
+    let version = windows_version();
+    (version.0 < 10).then_some(version)
+}
+
+// Older consoles only understand color codes once virtual terminal processing is on
+pub fn enable_ansi_colors() -> bool {
+    // Consoles before Windows 10 print the escape codes as text
+    if legacy_windows().is_some() {
+        return false;
+    }
+    unsafe {
+        let handle = GetStdHandle(STD_OUTPUT_HANDLE);
+        let mut mode = 0;
+        if GetConsoleMode(handle, &mut mode) == 0 {
+            return false;
+        }
+        SetConsoleMode(handle, mode | ENABLE_VIRTUAL_TERMINAL_PROCESSING) != 0
+    }
+}
+
+// Bytes free for this user on the drive holding `path`
+pub fn free_space(path: &Path) -> io::Result<u64> {
+    let wide = to_wide_string(&path.to_string_lossy());
+    let mut free: ULARGE_INTEGER = unsafe { std::mem::zeroed() };
+    if unsafe { GetDiskFreeSpaceExW(wide.as_ptr(), &mut free, null_mut(), null_mut()) } == 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(unsafe { *free.QuadPart() })
+}
+
+const ERROR_SHARING_VIOLATION: i32 = 32;
+const ERROR_LOCK_VIOLATION: i32 = 33;
+const ERROR_NOT_SAME_DEVICE: i32 = 17;
+
+pub fn is_lock_io(e: &io::Error) -> bool {
+    matches!(
+        e.raw_os_error(),
+        Some(ERROR_SHARING_VIOLATION | ERROR_LOCK_VIOLATION)
+    )
+}
+
+// Open without sharing: fails while VaM, a cloud sync client or a virus scanner holds the file
+pub fn is_locked(path: &Path) -> bool {
+    match OpenOptions::new().read(true).share_mode(0).open(path) {
+        Ok(_) => false,
+        Err(e) => is_lock_io(&e),
+    }
+}
+
+// A rename onto another volume, which has to be a copy instead
+pub fn is_cross_device(e: &io::Error) -> bool {
+    e.raw_os_error() == Some(ERROR_NOT_SAME_DEVICE)
+}
+
+static CTRL_C: OnceLock<fn()> = OnceLock::new();
+
+unsafe extern "system" fn call_ctrl_c(_: u32) -> i32 {
+    if let Some(handler) = CTRL_C.get() {
+        handler();
+    }
+    1
+}
+
+// `handler` runs on the thread Windows starts for every Ctrl+C
+pub fn on_ctrl_c(handler: fn()) {
+    if CTRL_C.set(handler).is_ok() {
+        unsafe {
+            SetConsoleCtrlHandler(Some(call_ctrl_c), 1);
+        }
+    }
+}
+
+// Steam's own folder, its libraryfolders.vdf lists the other libraries
+pub fn steam_folders() -> Vec<PathBuf> {
+    match std::env::var_os("ProgramFiles(x86)") {
+        Some(folder) => vec![PathBuf::from(folder).join("Steam")],
+        None => Vec::new(),
+    }
+}
+
+// Folders VaM is often installed in, without Steam
+pub fn install_roots() -> Vec<PathBuf> {
+    ('C'..='Z')
+        .map(|drive| PathBuf::from(format!("{drive}:/")))
+        .collect()
+}
+
+// Per user settings, %APPDATA%
+pub fn config_folder() -> Option<PathBuf> {
+    std::env::var_os("APPDATA").map(PathBuf::from)
+}
+
+// Paths without a drive letter or share have no volume of their own
+pub fn mount_point(_: &Path) -> String {
+    String::new()
+}
+
+// Whether message_box really shows a box the user has to close
+pub const MESSAGE_BOXES: bool = true;
+
+pub fn message_box(title: &str, message: &str) {
+    let title_wide = to_wide_string(title);
+    let message_wide = to_wide_string(message);
+    unsafe {
+        MessageBoxW(
+            null_mut(),
+            message_wide.as_ptr(),
+            title_wide.as_ptr(),
+            MB_OK | MB_SYSTEMMODAL,
+        );
+    }
+}
+
+fn open(path: &Path) {
+    let operation = to_wide_string("open");
+    let file = to_wide_string(&path.to_string_lossy());
+    unsafe {
+        ShellExecuteW(
+            null_mut(),
+            operation.as_ptr(),
+            file.as_ptr(),
+            null_mut(),
+            null_mut(),
+            SW_SHOWNORMAL,
+        );
+    }
+}
+
+// Button captions of the completion dialog, set by the hook once the box exists; the Help
+// button opens the logs without closing the box
+static CAPTIONS: Mutex<Vec<(i32, Vec<u16>)>> = Mutex::new(Vec::new());
+static LOGS: Mutex<Option<PathBuf>> = Mutex::new(None);
+
+unsafe extern "system" fn rename_buttons(code: i32, wparam: WPARAM, lparam: LPARAM) -> isize {
+    if code == HCBT_ACTIVATE {
+        for (id, caption) in CAPTIONS.lock().unwrap().iter() {
+            SetDlgItemTextW(wparam as HWND, *id, caption.as_ptr());
+        }
+    }
+    CallNextHookEx(null_mut(), code, wparam, lparam)
+}
+
+unsafe extern "system" fn open_logs(_: *mut HELPINFO) {
+    if let Some(logs) = LOGS.lock().unwrap().as_ref() {
+        open(logs);
+    }
+}
+
+pub fn show_completion(message: &str, report: &Path, merged: &Path, logs: Option<PathBuf>) {
+    let mut captions = vec![
+        (IDYES, "Open report / 打开报告"),
+        (IDNO, "Open merged folder / 打开合并文件夹"),
+        (IDCANCEL, "Close / 关闭"),
+    ];
+    let mut style = MB_YESNOCANCEL | MB_ICONINFORMATION | MB_SYSTEMMODAL;
+    if logs.is_some() {
+        captions.push((IDHELP, "Open logs / 打开日志"));
+        style |= MB_HELP;
+    }
+    *CAPTIONS.lock().unwrap() = captions
+        .into_iter()
+        .map(|(id, caption)| (id, to_wide_string(caption)))
+        .collect();
+    *LOGS.lock().unwrap() = logs;
+    let title = to_wide_string("Success/成功");
+    let text = to_wide_string(message);
+    let params = MSGBOXPARAMSW {
+        cbSize: size_of::<MSGBOXPARAMSW>() as u32,
+        hwndOwner: null_mut(),
+        hInstance: null_mut(),
+        lpszText: text.as_ptr(),
+        lpszCaption: title.as_ptr(),
+        dwStyle: style,
+        lpszIcon: null(),
+        dwContextHelpId: 0,
+        lpfnMsgBoxCallback: Some(open_logs),
+        dwLanguageId: 0,
+    };
+    let answer = unsafe {
+        let hook: HHOOK = SetWindowsHookExW(
+            WH_CBT,
+            Some(rename_buttons),
+            null_mut(),
+            GetCurrentThreadId(),
+        );
+        let answer = MessageBoxIndirectW(&params);
+        UnhookWindowsHookEx(hook);
+        answer
+    };
+    match answer {
+        IDYES => open(report),
+        IDNO => open(merged),
+        _ => {}
+    }
+}
+
+// The event source handle, kept as an address so it can live in a static
+static EVENT_SOURCE: OnceLock<usize> = OnceLock::new();
+
+// Registers VarCleaner as a source of the Application log; false when that failed
+pub fn open_event_log() -> bool {
+    let name = to_wide_string("VarCleaner");
+    let handle = unsafe { RegisterEventSourceW(null(), name.as_ptr()) };
+    if handle.is_null() {
+        return false;
+    }
+    EVENT_SOURCE.get_or_init(|| handle as usize);
+    true
+}
+
+pub fn write_event(error: bool, id: u32, message: &str) {
+    let handle = match EVENT_SOURCE.get() {
+        Some(handle) => *handle,
+        None => return,
+    };
+    let kind = if error {
+        EVENTLOG_ERROR_TYPE
+    } else {
+        EVENTLOG_INFORMATION_TYPE
+    };
+    let message = to_wide_string(message);
+    let mut strings = [message.as_ptr()];
+    unsafe {
+        ReportEventW(
+            handle as _,
+            kind,
+            0,
+            id,
+            null_mut(),
+            1,
+            0,
+            strings.as_mut_ptr(),
+            null_mut(),
+        );
+    }
+}
+
+pub const DEFAULT_PIPE: &str = r"\\.\pipe\varcleaner";
+
+// A client of the daemon; every client gets its own pipe instance
+pub type Connection = File;
+
+pub struct Listener {
+    name: Vec<u16>,
+}
+
+pub fn listen(name: &str) -> io::Result<Listener> {
+    Ok(Listener {
+        name: to_wide_string(name),
+    })
+}
+
+impl Listener {
+    // Waits for the next client
+    pub fn accept(&self) -> io::Result<Connection> {
+        loop {
+            let handle = unsafe {
+                CreateNamedPipeW(
+                    self.name.as_ptr(),
+                    PIPE_ACCESS_DUPLEX,
+                    PIPE_TYPE_BYTE | PIPE_READMODE_BYTE | PIPE_WAIT,
+                    PIPE_UNLIMITED_INSTANCES,
+                    64 * 1024,
+                    64 * 1024,
+                    0,
+                    null_mut(),
+                )
+            };
+            if handle == INVALID_HANDLE_VALUE {
+                return Err(io::Error::last_os_error());
+            }
+            let pipe = unsafe { File::from_raw_handle(handle as _) };
+            let connected = unsafe { ConnectNamedPipe(handle, null_mut()) } != 0
+                || io::Error::last_os_error().raw_os_error() == Some(ERROR_PIPE_CONNECTED as i32);
+            if connected {
+                return Ok(pipe);
+            }
+            warning!("Client failed to connect: {}", io::Error::last_os_error());
+        }
+    }
+}
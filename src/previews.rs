@@ -0,0 +1,150 @@
+use crate::console::{summary, warning};
+use crate::index;
+use crate::reader::VarReader;
+use crate::textures::is_image;
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use threadpool::ThreadPool;
+use threadpool_scope::scope_with;
+
+// Extensions of VaM content files that get a same-named .jpg thumbnail next to them
+const CONTENT_EXTENSIONS: [&str; 9] = [
+    "json",
+    "vap",
+    "vam",
+    "vaj",
+    "vmi",
+    "vab",
+    "assetbundle",
+    "cs",
+    "cslist",
+];
+
+pub fn is_content_thumbnail(name: &str, names: &HashSet<&str>) -> bool {
+    let stem = match name.rsplit_once('.') {
+        Some((stem, _)) => stem,
+        None => return false,
+    };
+    is_image(name)
+        && CONTENT_EXTENSIONS
+            .iter()
+            .any(|ext| names.contains(format!("{stem}.{ext}").as_str()))
+}
+
+// Files that belong to the content file with the same stem: its thumbnail and the data
+// stored next to a clothing/hair .vam or a morph .vmi
+const ITEM_PARTS: [&str; 3] = ["vaj", "vab", "vmb"];
+
+// The content file `name` is part of, among `names`
+fn owner<'a>(name: &str, names: &HashSet<&'a str>) -> Option<&'a str> {
+    let (stem, ext) = name.rsplit_once('.')?;
+    if !is_image(name) && !ITEM_PARTS.contains(&ext.to_ascii_lowercase().as_str()) {
+        return None;
+    }
+    CONTENT_EXTENSIONS
+        .iter()
+        .filter(|ext| !ITEM_PARTS.contains(ext))
+        .find_map(|ext| names.get(format!("{stem}.{ext}").as_str()).copied())
+}
+
+// Parts follow the decision made for their content file: taken from the same copy when that
+// copy has them, left out when the content file is. `candidates` lists the copies (and sizes)
+// holding each entry, `winners` the copy each kept entry comes from; returns the entries
+// whose decision changes, None meaning left out
+pub fn pair_parts(
+    candidates: &BTreeMap<String, Vec<(usize, u64)>>,
+    winners: &HashMap<String, (usize, u64)>,
+) -> Vec<(String, Option<(usize, u64)>)> {
+    let names: HashSet<&str> = candidates.keys().map(|n| n.as_str()).collect();
+    let mut result = Vec::new();
+    for (name, copies) in candidates.iter() {
+        let Some(content) = owner(name, &names) else {
+            continue;
+        };
+        let current = winners.get(name).copied();
+        let wanted = match winners.get(content) {
+            Some((copy, _)) => copies.iter().find(|(c, _)| c == copy).copied().or(current),
+            None => None,
+        };
+        if wanted != current {
+            result.push((name.clone(), wanted));
+        }
+    }
+    result
+}
+
+// Package thumbnails sit at the archive root, content thumbnails share the stem of a content file
+pub fn is_preview(name: &str, names: &HashSet<&str>) -> bool {
+    is_image(name) && (!name.contains('/') || is_content_thumbnail(name, names))
+}
+
+pub fn preview_entries(var_index: &index::VarIndex) -> Vec<&index::EntryInfo> {
+    let names: HashSet<&str> = var_index.entries.iter().map(|e| &*e.name).collect();
+    var_index
+        .entries
+        .iter()
+        .filter(|e| is_preview(&e.name, &names))
+        .collect()
+}
+
+pub fn cached_previews(var_path: &Path, cache_folder: &Path) -> anyhow::Result<Vec<PathBuf>> {
+    let mut var = VarReader::open(var_path)?;
+    let var_index = var.index()?;
+    let dir = cache_folder.join(index::fingerprint(&var_index));
+    let mut result = Vec::new();
+    for entry in preview_entries(&var_index) {
+        let mut file = var.open_entry(&entry.name)?;
+        let outpath = match file.enclosed_name() {
+            Some(path) => dir.join(path),
+            None => continue,
+        };
+        if !outpath.exists() {
+            crate::readonly::check(&outpath)?;
+            fs::create_dir_all(outpath.parent().unwrap())?;
+            let mut outfile = File::create(&outpath)?;
+            if let Err(e) = io::copy(&mut file, &mut outfile) {
+                drop(outfile);
+                fs::remove_file(&outpath)?;
+                return Err(e.into());
+            }
+        }
+        result.push(outpath);
+    }
+    Ok(result)
+}
+
+pub fn cache_all(var_folder: &Path, cache_folder: &Path) {
+    let installed = index::installed_packages(var_folder);
+    let image_count = AtomicUsize::new(0);
+    let pool = ThreadPool::new(crate::config::get().threads);
+    scope_with(&pool, |scope| {
+        for path in installed.values() {
+            let image_count = &image_count;
+            scope.execute(move || match cached_previews(path, cache_folder) {
+                Ok(images) => {
+                    image_count.fetch_add(images.len(), Ordering::Relaxed);
+                }
+                Err(_) => warning!("zipfile {} is invaild", path.to_string_lossy()),
+            });
+        }
+    });
+    let cache_folder_str = cache_folder.to_string_lossy();
+    let image_count = image_count.into_inner();
+    summary!(
+        "Cached {} preview images of {} vars in {cache_folder_str}",
+        image_count,
+        installed.len()
+    );
+    summary!(
+        "已将{}个Var的{image_count}张预览图缓存至{cache_folder_str}",
+        installed.len()
+    );
+}
@@ -0,0 +1,54 @@
+use crate::console::{verbose, warning};
+use glob::MatchOptions;
+use glob::Pattern;
+use std::fs;
+use std::path::Path;
+
+// Package names or globs from VarCleaner/protected.txt that no command may modify
+pub struct Protected {
+    patterns: Vec<Pattern>,
+}
+
+const MATCH_OPTIONS: MatchOptions = MatchOptions {
+    case_sensitive: false,
+    require_literal_separator: false,
+    require_literal_leading_dot: false,
+};
+
+// Content bundled with VaM itself, protected even without a protected.txt
+const BUILT_IN: &[&str] = &["MeshedVR.*", "AshAuryn.*"];
+
+pub fn load(vam_folder: &Path) -> Protected {
+    let path = vam_folder.join("VarCleaner/protected.txt");
+    let text = fs::read_to_string(&path).unwrap_or_default();
+    let mut patterns: Vec<Pattern> = BUILT_IN.iter().map(|p| Pattern::new(p).unwrap()).collect();
+    for line in text.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = line.strip_suffix(".var").unwrap_or(line);
+        match Pattern::new(line) {
+            Ok(pattern) => patterns.push(pattern),
+            Err(e) => warning!("Ignore invalid protected pattern {line}: {e}"),
+        }
+    }
+    Protected { patterns }
+}
+
+impl Protected {
+    pub fn contains(&self, package: &str) -> bool {
+        self.patterns
+            .iter()
+            .any(|p| p.matches_with(package, MATCH_OPTIONS))
+    }
+
+    // Same check for a var on disk, logging why it is left alone
+    pub fn skips(&self, path: &Path) -> bool {
+        let package = crate::index::package_name(path);
+        let protected = self.contains(&package);
+        if protected {
+            verbose!("Skip protected {package}");
+        }
+        protected
+    }
+}
@@ -0,0 +1,287 @@
+use crate::backup;
+use crate::console::{error, info, summary, warning};
+use crate::index;
+use crate::journal::Journal;
+use crate::journal::Operation;
+use crate::protect::Protected;
+use serde::Deserialize;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::collections::HashSet;
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::Duration;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+const PLACEHOLDER_SIZE: u64 = 1024;
+// Far beyond any real package, merging such archives only burns time and memory
+const MAX_ENTRIES: usize = 200_000;
+const MAX_DEPTH: usize = 32;
+// Top level folders VaM loads content from
+const CONTENT_ROOTS: [&str; 2] = ["custom", "saves"];
+
+// Why `path` should not take part in a merge: empty files or tiny non-zips left behind
+// by failed downloads, and with `limits` archives too large or too deep to be content
+fn problem(path: &Path, limits: bool) -> Option<String> {
+    let size = fs::metadata(path).ok()?.len();
+    if size == 0 {
+        return Some("is empty".to_string());
+    }
+    if size < PLACEHOLDER_SIZE && index::open_var(path).is_err() {
+        return Some(format!("is broken ({size} bytes)"));
+    }
+    if !limits {
+        return None;
+    }
+    let archive = index::open_var(path).ok()?;
+    if archive.len() > MAX_ENTRIES {
+        return Some(format!(
+            "has {} entries, more than {MAX_ENTRIES} (pass --limits off to merge it)",
+            archive.len()
+        ));
+    }
+    let depth = archive
+        .file_names()
+        .map(|name| name.split('/').count())
+        .max()
+        .unwrap_or(0);
+    if depth > MAX_DEPTH {
+        return Some(format!(
+            "nests folders {depth} levels deep, more than {MAX_DEPTH} (pass --limits off to merge it)"
+        ));
+    }
+    None
+}
+
+// Move placeholders and, with `limits`, pathological archives under `scan_folder` to
+// VarCleaner/Quarantine before they join a duplicate group
+pub fn placeholders(
+    vam_folder: &Path,
+    scan_folder: &Path,
+    protected: &Protected,
+    limits: bool,
+    deferred: &HashSet<PathBuf>,
+) {
+    let var_folder = &crate::root::addon_packages(vam_folder);
+    let quarantine_folder = &vam_folder.join("VarCleaner/Quarantine");
+    // Vars already known not to be packages stay out of merging without being opened again
+    let known = load_known(vam_folder);
+    let found: Vec<_> = index::list_vars(scan_folder)
+        .into_iter()
+        .filter(|path| !deferred.contains(path))
+        .filter(|path| {
+            let known = stamp(path).zip(known.get(&*path.to_string_lossy()));
+            !known.is_some_and(|((size, mtime), k)| k.size == size && k.mtime == mtime)
+        })
+        .filter_map(|path| Some((problem(&path, limits)?, path)))
+        .filter(|(_, path)| !protected.skips(path))
+        .collect();
+    if found.is_empty() {
+        return;
+    }
+    let mut journal = Journal::new("quarantine");
+    for (problem, path) in found.iter() {
+        let target =
+            backup::free_path(quarantine_folder.join(path.strip_prefix(var_folder).unwrap()));
+        match backup::move_verified(path, &target) {
+            Ok(()) => {
+                warning!(
+                    "{} {problem}",
+                    path.strip_prefix(var_folder).unwrap().to_string_lossy()
+                );
+                journal.record(Operation::Move {
+                    from: path.clone(),
+                    to: target,
+                });
+            }
+            Err(e) => error!("Failed to quarantine {}: {e}", path.to_string_lossy()),
+        }
+    }
+    summary!(
+        "Moved {} placeholder or oversized vars to {} / 已将{}个空Var、损坏的下载或异常巨大的Var移至隔离目录",
+        journal.operations.len(),
+        quarantine_folder.to_string_lossy(),
+        journal.operations.len()
+    );
+    if let Err(e) = journal.save(&vam_folder.join("VarCleaner/Journal")) {
+        error!("Failed to write the journal: {e}");
+    }
+}
+
+// Vars written to in the last defer_minutes, likely still downloading or syncing: a half
+// written var would be quarantined as broken or merged as corrupt. They are listed and left
+// alone for this run, the next run picks them up.
+pub fn deferred_vars(vam_folder: &Path, scan_folder: &Path) -> HashSet<PathBuf> {
+    let var_folder = &crate::root::addon_packages(vam_folder);
+    let settle = Duration::from_secs(crate::config::get().defer_minutes * 60);
+    let now = SystemTime::now();
+    let found: HashSet<PathBuf> = index::list_vars(scan_folder)
+        .into_iter()
+        .filter(|path| {
+            fs::metadata(path)
+                .and_then(|m| m.modified())
+                // A clock set back makes the file look like it comes from the future
+                .map(|modified| {
+                    now.duration_since(modified)
+                        .map_or(true, |age| age < settle)
+                })
+                .unwrap_or(false)
+        })
+        .collect();
+    if !found.is_empty() {
+        warning!(
+            "{} vars changed in the last {} minutes and are deferred to the next run / 以下{}个Var最近{}分钟内有改动（可能仍在下载或同步），留待下次运行:",
+            found.len(),
+            crate::config::get().defer_minutes,
+            found.len(),
+            crate::config::get().defer_minutes
+        );
+        for path in found.iter() {
+            info!(
+                "  {}",
+                path.strip_prefix(var_folder)
+                    .unwrap_or(path)
+                    .to_string_lossy()
+            );
+        }
+    }
+    found
+}
+
+// Why `path` is not a VaM package at all: another archive format renamed to .var, or a zip
+// with neither meta.json nor anything under Custom/ or Saves/
+fn foreign(path: &Path) -> Option<String> {
+    let Ok(var_index) = crate::jsoncache::index(path) else {
+        let mut magic = [0u8; 6];
+        fs::File::open(path).ok()?.read_exact(&mut magic).ok()?;
+        return Some(match &magic {
+            b"Rar!\x1a\x07" => "is a RAR archive renamed to .var".to_string(),
+            [b'7', b'z', 0xbc, 0xaf, 0x27, 0x1c] => "is a 7z archive renamed to .var".to_string(),
+            _ => "is not a zip archive".to_string(),
+        });
+    };
+    let vam = var_index.entries.iter().any(|e| {
+        let lower = e.name.to_ascii_lowercase();
+        lower == "meta.json"
+            || lower
+                .split_once('/')
+                .is_some_and(|(root, _)| CONTENT_ROOTS.contains(&root))
+    });
+    (!vam).then(|| "is a zip without meta.json or VaM content".to_string())
+}
+
+const FOREIGN_MEMORY: &str = "VarCleaner/Cache/foreign.json";
+
+// A var found not to be a VaM package, kept in FOREIGN_MEMORY so later runs know it by size
+// and mtime without opening it again; when only the mtime changed the hash tells a touched
+// file from a re-download, which is checked afresh
+#[derive(Serialize, Deserialize)]
+struct KnownForeign {
+    size: u64,
+    mtime: u128,
+    hash: String,
+    problem: String,
+    // Seconds since 1970 it was first found
+    since: u64,
+}
+
+fn load_known(vam_folder: &Path) -> BTreeMap<String, KnownForeign> {
+    fs::read(vam_folder.join(FOREIGN_MEMORY))
+        .ok()
+        .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+        .unwrap_or_default()
+}
+
+fn stamp(path: &Path) -> Option<(u64, u128)> {
+    let metadata = fs::metadata(path).ok()?;
+    let mtime = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+    Some((metadata.len(), mtime.as_nanos()))
+}
+
+// Vars under `scan_folder` that are not VaM packages, listed so the user can delete or fix
+// them; clean leaves them where they are and out of every merge
+pub fn foreign_vars(
+    vam_folder: &Path,
+    scan_folder: &Path,
+    deferred: &HashSet<PathBuf>,
+) -> HashSet<PathBuf> {
+    let var_folder = &crate::root::addon_packages(vam_folder);
+    let memory_path = vam_folder.join(FOREIGN_MEMORY);
+    let mut known = load_known(vam_folder);
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    let mut found: Vec<(String, PathBuf, u64)> = Vec::new();
+    for path in index::list_vars(scan_folder) {
+        if deferred.contains(&path) {
+            continue;
+        }
+        let Some((size, mtime)) = stamp(&path) else {
+            continue;
+        };
+        let key = path.to_string_lossy().to_string();
+        let hash = match known.get_mut(&key).filter(|k| k.size == size) {
+            Some(entry) if entry.mtime == mtime => {
+                found.push((entry.problem.clone(), path, entry.since));
+                continue;
+            }
+            // Touched but the same bytes, e.g. copied back from a backup
+            Some(entry) => match crate::hashing::hash_file(&path) {
+                Ok(hash) if hash == entry.hash => {
+                    entry.mtime = mtime;
+                    found.push((entry.problem.clone(), path, entry.since));
+                    continue;
+                }
+                hash => Some(hash),
+            },
+            None => None,
+        };
+        known.remove(&key);
+        let Some(problem) = foreign(&path) else {
+            continue;
+        };
+        if let Ok(hash) = hash.unwrap_or_else(|| crate::hashing::hash_file(&path)) {
+            known.insert(
+                key,
+                KnownForeign {
+                    size,
+                    mtime,
+                    hash,
+                    problem: problem.clone(),
+                    since: now,
+                },
+            );
+        }
+        found.push((problem, path, now));
+    }
+    known.retain(|path, _| Path::new(path).exists());
+    if crate::readonly::check(&memory_path).is_ok() {
+        let _ = fs::create_dir_all(memory_path.parent().unwrap())
+            .and_then(|_| fs::write(&memory_path, serde_json::to_vec_pretty(&known).unwrap()));
+    }
+    if !found.is_empty() {
+        warning!(
+            "{} files are not VaM packages and are left out of merging, re-download, delete or fix them / 以下{}个文件不是VaM包，不参与合并，请重新下载、删除或修复:",
+            found.len(),
+            found.len()
+        );
+        for (problem, path, since) in found.iter() {
+            let days = now.saturating_sub(*since) / 86400;
+            let age = match days {
+                0 => String::new(),
+                days => format!(", for {days} days"),
+            };
+            info!(
+                "  {} {problem}{age}",
+                path.strip_prefix(var_folder)
+                    .unwrap_or(path)
+                    .to_string_lossy()
+            );
+        }
+    }
+    found.into_iter().map(|(_, path, _)| path).collect()
+}
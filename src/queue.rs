@@ -0,0 +1,269 @@
+use crate::console::{error, info, summary, warning};
+use crate::drives;
+use std::collections::HashMap;
+use std::collections::LinkedList;
+use std::collections::VecDeque;
+use std::io;
+use std::io::BufRead;
+use std::path::PathBuf;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::sync::Condvar;
+use std::sync::Mutex;
+
+// Runtime controls shared by the keyboard listener, the daemon and the clean workers
+struct Controls {
+    paused: bool,
+    skipped: Vec<String>,
+    prioritized: Vec<String>,
+}
+
+static CONTROLS: Mutex<Controls> = Mutex::new(Controls {
+    paused: false,
+    skipped: Vec::new(),
+    prioritized: Vec::new(),
+});
+static RESUMED: Condvar = Condvar::new();
+static KEYBOARD: AtomicBool = AtomicBool::new(false);
+static STOPPING: AtomicBool = AtomicBool::new(false);
+static ABORTED: AtomicBool = AtomicBool::new(false);
+
+// "Creator.Pkg.1", "creator.pkg.1.var" and a path all name the same group
+fn group_key(package: &str) -> String {
+    let name = package.rsplit(['/', '\\']).next().unwrap().to_lowercase();
+    match name.strip_suffix(".var") {
+        Some(stem) => stem.to_string(),
+        None => name,
+    }
+}
+
+pub fn pause() {
+    CONTROLS.lock().unwrap().paused = true;
+    summary!("Paused, running packages finish first / 已暂停，正在处理的包会先完成");
+}
+
+pub fn resume() {
+    CONTROLS.lock().unwrap().paused = false;
+    RESUMED.notify_all();
+    summary!("Resumed / 已继续");
+}
+
+pub fn skip(package: &str) {
+    CONTROLS.lock().unwrap().skipped.push(group_key(package));
+    info!("{package} will be skipped");
+}
+
+pub fn prioritize(package: &str) {
+    CONTROLS
+        .lock()
+        .unwrap()
+        .prioritized
+        .push(group_key(package));
+    info!("{package} moves to the front of the queue");
+}
+
+pub fn is_skipped(package: &str) -> bool {
+    CONTROLS
+        .lock()
+        .unwrap()
+        .skipped
+        .contains(&group_key(package))
+}
+
+pub fn wait_if_paused() {
+    let mut controls = CONTROLS.lock().unwrap();
+    while controls.paused {
+        controls = RESUMED.wait(controls).unwrap();
+    }
+}
+
+// No new packages start; the ones running finish, so the run ends at a checkpoint
+pub fn stop() {
+    if !STOPPING.swap(true, Ordering::Relaxed) {
+        summary!("Stopping after the packages in progress / 正在处理的包完成后停止");
+    }
+}
+
+pub fn is_stopping() -> bool {
+    STOPPING.load(Ordering::Relaxed)
+}
+
+// Every worker gives up at its next entry: merges in progress are dropped with their originals
+// untouched and no new package starts. Workers would otherwise grind on for hours after a
+// failure nobody can fix from inside the run.
+pub fn abort(reason: &str) {
+    STOPPING.store(true, Ordering::Relaxed);
+    if !ABORTED.swap(true, Ordering::Relaxed) {
+        error!("{reason}, stopping every worker / {reason}，正在停止所有任务");
+    }
+}
+
+pub fn is_aborted() -> bool {
+    ABORTED.load(Ordering::Relaxed)
+}
+
+// Checked between entries by everything that reads or writes a var
+pub fn check_aborted() -> anyhow::Result<()> {
+    if is_aborted() {
+        anyhow::bail!("stopped / 已停止");
+    }
+    Ok(())
+}
+
+// Held by every pool job, a job that panics aborts the whole run
+pub struct AbortOnPanic;
+
+impl Drop for AbortOnPanic {
+    fn drop(&mut self) {
+        if std::thread::panicking() {
+            abort("A worker failed");
+        }
+    }
+}
+
+fn on_ctrl_c() {
+    if is_stopping() {
+        abort("Ctrl+C pressed again");
+    } else {
+        stop();
+    }
+}
+
+// Ctrl+C stops the run cleanly between packages instead of killing it mid-write, a second
+// Ctrl+C gives up the packages in progress too
+pub fn stop_on_ctrl_c() {
+    crate::platform::on_ctrl_c(on_ctrl_c);
+}
+
+pub fn keyboard_active() -> bool {
+    KEYBOARD.load(Ordering::Relaxed)
+}
+
+// p = pause, r = resume, s NAME = skip, f NAME = move to the front, q = quit now; one command
+// per line
+pub fn listen_keyboard() {
+    KEYBOARD.store(true, Ordering::Relaxed);
+    info!("Controls: p pause, r resume, s NAME skip, f NAME first, q quit / 控制：p暂停 r继续 s包名跳过 f包名优先 q退出");
+    std::thread::spawn(|| {
+        for line in io::stdin().lock().lines().map_while(Result::ok) {
+            match line.trim().split_once(' ') {
+                Some(("s", package)) => skip(package.trim()),
+                Some(("f", package)) => prioritize(package.trim()),
+                _ => match line.trim() {
+                    "p" => pause(),
+                    "r" => resume(),
+                    "q" => {
+                        abort("Stopped from the console");
+                        // A paused run has to wake up to stop
+                        resume();
+                    }
+                    "" => {}
+                    other => warning!("Unknown command {other}"),
+                },
+            }
+        }
+    });
+}
+
+// Waiting groups and how many packages are being merged from each drive. Groups go to the
+// least busy drives first and a drive never runs more than its drive_threads setting, so a
+// slow hard drive holding part of the library does not hold up the packages on faster ones
+struct Scheduled {
+    groups: VecDeque<(Vec<String>, LinkedList<PathBuf>)>,
+    running: HashMap<String, usize>,
+    // Drives of the groups handed out, by their first copy
+    taken: HashMap<PathBuf, Vec<String>>,
+}
+
+pub struct Queue {
+    state: Mutex<Scheduled>,
+    released: Condvar,
+}
+
+impl Queue {
+    pub fn new(groups: impl IntoIterator<Item = LinkedList<PathBuf>>) -> Queue {
+        let groups = groups
+            .into_iter()
+            .map(|group| {
+                let mut drives: Vec<String> = group.iter().map(|p| drives::of(p)).collect();
+                drives.sort();
+                drives.dedup();
+                (drives, group)
+            })
+            .collect();
+        STOPPING.store(false, Ordering::Relaxed);
+        ABORTED.store(false, Ordering::Relaxed);
+        Queue {
+            state: Mutex::new(Scheduled {
+                groups,
+                running: HashMap::new(),
+                taken: HashMap::new(),
+            }),
+            released: Condvar::new(),
+        }
+    }
+
+    // Next group to merge, prioritized ones first; waits while the run is paused or every
+    // waiting group reads from a drive already at its limit
+    pub fn pop(&self) -> Option<LinkedList<PathBuf>> {
+        wait_if_paused();
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if is_stopping() {
+                return None;
+            }
+            let pos = prioritized(&state).or_else(|| {
+                let limits = &crate::config::get().drive_threads;
+                let busy = |drive: &String| state.running.get(drive).copied().unwrap_or(0);
+                (0..state.groups.len())
+                    .filter(|&i| {
+                        state.groups[i]
+                            .0
+                            .iter()
+                            .all(|d| limits.get(d).is_none_or(|limit| busy(d) < *limit))
+                    })
+                    .min_by_key(|&i| state.groups[i].0.iter().map(busy).max())
+            });
+            if let Some(pos) = pos {
+                let (drives, group) = state.groups.remove(pos).unwrap();
+                for drive in drives.iter() {
+                    *state.running.entry(drive.clone()).or_default() += 1;
+                }
+                state.taken.insert(group.front().unwrap().clone(), drives);
+                return Some(group);
+            }
+            if state.groups.is_empty() {
+                return None;
+            }
+            state = self.released.wait(state).unwrap();
+        }
+    }
+
+    // Called when a group from pop is finished, whatever its outcome
+    pub fn done(&self, group: &LinkedList<PathBuf>) {
+        let mut state = self.state.lock().unwrap();
+        for drive in state
+            .taken
+            .remove(group.front().unwrap())
+            .unwrap_or_default()
+        {
+            *state.running.get_mut(&drive).unwrap() -= 1;
+        }
+        self.released.notify_all();
+    }
+}
+
+// A group the user asked for with f NAME, whatever its drive is doing
+fn prioritized(state: &Scheduled) -> Option<usize> {
+    let mut controls = CONTROLS.lock().unwrap();
+    while let Some(key) = controls.prioritized.pop() {
+        let found = state
+            .groups
+            .iter()
+            .position(|(_, g)| group_key(&g.front().unwrap().to_string_lossy()) == key);
+        if found.is_some() {
+            return found;
+        }
+    }
+    None
+}
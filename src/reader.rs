@@ -0,0 +1,141 @@
+use crate::content;
+use crate::index;
+use crate::index::EntryInfo;
+use crate::index::VarIndex;
+use crate::intern::intern;
+use std::fs::File;
+use std::io;
+use std::io::Read;
+use std::io::Seek;
+use std::path::Path;
+use zip::read::ZipFile;
+use zip::ZipArchive;
+
+// Entries read whole into memory (meta.json, scenes, scripts) are never legitimately this
+// large, a bigger declared or actual size is a zip bomb
+pub const MAX_READ_ENTRY: u64 = 256 * 1024 * 1024;
+
+// One opened var. Opening reads only the central directory; entry data is read when
+// an entry is opened, and meta.json once on first use
+pub struct VarReader<R: Read + Seek = File> {
+    package: String,
+    archive: ZipArchive<R>,
+    meta: Option<Option<serde_json::Value>>,
+}
+
+impl VarReader {
+    pub fn open(path: &Path) -> anyhow::Result<VarReader> {
+        Ok(VarReader {
+            package: index::package_name(path),
+            archive: index::open_var(path)?,
+            meta: None,
+        })
+    }
+}
+
+impl<R: Read + Seek> VarReader<R> {
+    // A var that is not a file on disk, e.g. one nested in another var or fuzz input
+    pub fn from_reader(package: &str, reader: R) -> anyhow::Result<VarReader<R>> {
+        Ok(VarReader {
+            package: package.to_string(),
+            archive: ZipArchive::new(reader)?,
+            meta: None,
+        })
+    }
+
+    pub fn package(&self) -> String {
+        self.package.clone()
+    }
+
+    // Parsed meta.json, None when it is missing or does not parse
+    pub fn meta(&mut self) -> Option<&serde_json::Value> {
+        if self.meta.is_none() {
+            let meta = self
+                .read_entry("meta.json")
+                .ok()
+                .and_then(|bytes| index::parse_json(&bytes).ok());
+            self.meta = Some(meta);
+        }
+        self.meta.as_ref().unwrap().as_ref()
+    }
+
+    // File entries as listed in the central directory, without reading their data
+    pub fn entries(&mut self) -> anyhow::Result<Vec<EntryInfo>> {
+        let mut entries = Vec::with_capacity(self.archive.len());
+        for i in 0..self.archive.len() {
+            let file = self.archive.by_index_raw(i)?;
+            if file.is_dir() {
+                continue;
+            }
+            entries.push(EntryInfo {
+                name: intern(file.name()),
+                size: file.size(),
+                crc32: file.crc32(),
+            });
+        }
+        Ok(entries)
+    }
+
+    pub fn index(&mut self) -> anyhow::Result<VarIndex> {
+        Ok(VarIndex {
+            package: self.package(),
+            entries: self.entries()?,
+            meta: self.meta().cloned(),
+        })
+    }
+
+    // Decompressing reader for one entry; reading it to the end checks its CRC
+    pub fn open_entry(&mut self, name: &str) -> anyhow::Result<ZipFile<'_>> {
+        Ok(self.archive.by_name(name)?)
+    }
+
+    // The whole entry, refused beyond MAX_READ_ENTRY whatever its header claims
+    pub fn read_entry(&mut self, name: &str) -> anyhow::Result<Vec<u8>> {
+        read_whole(self.open_entry(name)?)
+    }
+
+    // blake3 of every file entry's data, in archive order
+    pub fn hash_entries(&mut self) -> anyhow::Result<Vec<(String, blake3::Hash)>> {
+        let mut result = Vec::new();
+        for i in 0..self.archive.len() {
+            let mut file = self.archive.by_index(i)?;
+            if file.is_dir() {
+                continue;
+            }
+            let mut hasher = blake3::Hasher::new();
+            io::copy(&mut file, &mut hasher)?;
+            result.push((file.name().to_string(), hasher.finalize()));
+        }
+        Ok(result)
+    }
+
+    // Like hash_entries, but scene, preset and item JSON is hashed by its parsed value, so
+    // copies differing only in whitespace, key order or a BOM hash the same. JSON that does
+    // not parse is hashed as stored.
+    pub fn hash_entries_canonical(&mut self) -> anyhow::Result<Vec<(String, blake3::Hash)>> {
+        let mut result = self.hash_entries()?;
+        for (name, hash) in result.iter_mut() {
+            if !content::is_json(name) {
+                continue;
+            }
+            if let Ok(value) = index::parse_json(&self.read_entry(name)?) {
+                *hash = blake3::hash(index::canonical_json(&value).as_bytes());
+            }
+        }
+        Ok(result)
+    }
+}
+
+// An opened entry read whole, refused beyond MAX_READ_ENTRY whatever its header claims
+pub fn read_whole(file: ZipFile<'_>) -> anyhow::Result<Vec<u8>> {
+    let name = file.name().to_string();
+    if file.size() > MAX_READ_ENTRY {
+        anyhow::bail!("{name} is {} bytes, too large to read", file.size());
+    }
+    let mut bytes = Vec::new();
+    file.take(MAX_READ_ENTRY + 1).read_to_end(&mut bytes)?;
+    if bytes.len() as u64 > MAX_READ_ENTRY {
+        anyhow::bail!("{name} is larger than its header says");
+    }
+    Ok(bytes)
+}
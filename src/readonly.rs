@@ -0,0 +1,32 @@
+// Hard read-only mode for curators auditing libraries they must not change: startup refuses
+// commands that modify anything, and the writes below check here as well, so a code path the
+// command check missed fails instead of writing. Builds with the `audit` feature are always
+// read-only, whatever the command line says.
+use std::io;
+use std::path::Path;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+
+static READ_ONLY: AtomicBool = AtomicBool::new(cfg!(feature = "audit"));
+
+pub fn enable() {
+    READ_ONLY.store(true, Ordering::Relaxed);
+}
+
+pub fn is_enabled() -> bool {
+    READ_ONLY.load(Ordering::Relaxed)
+}
+
+// Call before creating, changing, moving or deleting `path`
+pub fn check(path: &Path) -> io::Result<()> {
+    if !is_enabled() {
+        return Ok(());
+    }
+    Err(io::Error::new(
+        io::ErrorKind::PermissionDenied,
+        format!(
+            "read-only mode, refusing to write {}",
+            path.to_string_lossy()
+        ),
+    ))
+}
@@ -0,0 +1,153 @@
+use crate::console::{error, info, summary, warning};
+use crate::journal::Journal;
+use crate::journal::Operation;
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+use walkdir::WalkDir;
+
+// Where a file of the reconstructed AddonPackages comes from
+enum Source {
+    File(PathBuf),
+    // A version archive-versions replaced by a delta
+    Delta(PathBuf),
+}
+
+// Journals oldest first; run ids end in the second they started
+fn load_journals(journal_folder: &Path) -> Vec<Journal> {
+    let mut journals: Vec<Journal> = fs::read_dir(journal_folder)
+        .into_iter()
+        .flatten()
+        .filter_map(|e| e.ok())
+        .filter_map(|e| serde_json::from_slice(&fs::read(e.path()).ok()?).ok())
+        .collect();
+    journals.sort_by_key(|journal: &Journal| {
+        let secs = journal.run_id.rsplit('-').next().unwrap_or_default();
+        secs.parse::<u64>().unwrap_or_default()
+    });
+    journals
+}
+
+fn files(folder: &Path) -> impl Iterator<Item = PathBuf> + '_ {
+    WalkDir::new(folder)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_file())
+        .map(|e| e.into_path())
+}
+
+// The AddonPackages layout from before VarCleaner changed it: what is there now without the
+// merged vars, every backed up original put back where it was, and the journals undone newest
+// first. Written to `target`; the VaM folder itself is left as it is.
+pub fn run(vam_folder: &Path, target: &Path) -> anyhow::Result<()> {
+    let var_folder = &crate::root::addon_packages(vam_folder);
+    let merged_folder = &var_folder.join("merged");
+    let backup_folder = &crate::root::backup_folder(vam_folder);
+    // VaM would load the reconstruction next to the library
+    if target.starts_with(vam_folder) {
+        anyhow::bail!(
+            "{} is inside the VaM folder / 目标文件夹不能位于VaM目录中",
+            target.to_string_lossy()
+        );
+    }
+    if fs::read_dir(target).is_ok_and(|mut entries| entries.next().is_some()) {
+        anyhow::bail!(
+            "{} is not empty / 目标文件夹不为空",
+            target.to_string_lossy()
+        );
+    }
+
+    // A path in the backup stands for the original it holds
+    let relative = |path: &Path| {
+        path.strip_prefix(var_folder)
+            .or_else(|_| path.strip_prefix(backup_folder))
+            .ok()
+            .map(Path::to_path_buf)
+    };
+    let mut layout: BTreeMap<PathBuf, Source> = BTreeMap::new();
+    for path in files(var_folder).filter(|p| !p.starts_with(merged_folder)) {
+        layout.insert(
+            path.strip_prefix(var_folder).unwrap().to_path_buf(),
+            Source::File(path),
+        );
+    }
+    // Backups keep their path relative to AddonPackages and hold the originals
+    for path in files(backup_folder) {
+        let original = path.strip_prefix(backup_folder).unwrap().to_path_buf();
+        layout.insert(original, Source::File(path));
+    }
+    let mut folders: BTreeSet<PathBuf> = BTreeSet::new();
+    for journal in load_journals(&vam_folder.join("VarCleaner/Journal"))
+        .iter()
+        .rev()
+    {
+        for operation in journal.operations.iter().rev() {
+            match operation {
+                Operation::Create { path, .. } => {
+                    if let Some(path) = relative(path) {
+                        layout.remove(&path);
+                    }
+                }
+                Operation::Move { from, to } => {
+                    let source = match relative(to) {
+                        Some(to) => layout.remove(&to),
+                        None => to.exists().then(|| Source::File(to.clone())),
+                    };
+                    // Merged vars restore moved aside stay out like every merged var
+                    if let (Some(from), Some(source)) = (relative(from), source) {
+                        if !var_folder.join(&from).starts_with(merged_folder) {
+                            layout.insert(from, source);
+                        }
+                    }
+                }
+                // Unless restored since, which put the var back
+                Operation::Delta { from, to } if to.exists() => {
+                    if let Some(from) = relative(from) {
+                        layout.insert(from, Source::Delta(to.clone()));
+                    }
+                }
+                Operation::Delta { .. } => {}
+                Operation::RemoveDir { path } => {
+                    if let Some(path) = relative(path) {
+                        folders.insert(path);
+                    }
+                }
+            }
+        }
+    }
+
+    let mut failed = 0;
+    for folder in folders.iter() {
+        fs::create_dir_all(target.join(folder))?;
+    }
+    for (path, source) in layout.iter() {
+        let dst = target.join(path);
+        let result = match source {
+            Source::File(src) => fs::create_dir_all(dst.parent().unwrap())
+                .and_then(|_| fs::copy(src, &dst))
+                .map(|_| ())
+                .map_err(anyhow::Error::from),
+            Source::Delta(delta) => crate::delta::rebuild_to(vam_folder, delta, &dst),
+        };
+        match result {
+            Ok(()) => info!("{}", path.to_string_lossy()),
+            Err(e) => {
+                error!("Failed to reconstruct {}: {e}", path.to_string_lossy());
+                failed += 1;
+            }
+        }
+    }
+    if failed > 0 {
+        warning!("{failed} files could not be reconstructed / {failed}个文件无法重建");
+    }
+    summary!(
+        "Reconstructed {} files in {} / 已在{}重建{}个文件",
+        layout.len() - failed,
+        target.to_string_lossy(),
+        target.to_string_lossy(),
+        layout.len() - failed
+    );
+    Ok(())
+}
@@ -0,0 +1,177 @@
+use crate::console::info;
+use std::env;
+use std::fs;
+use std::io;
+use std::io::IsTerminal;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+
+// Set by --addon-path for var repositories without a VaM install
+static ADDON_PATH: OnceLock<PathBuf> = OnceLock::new();
+// Set by --backup-dir and --tmp-dir, e.g. to keep backups or scratch space on another drive
+static BACKUP_DIR: OnceLock<PathBuf> = OnceLock::new();
+static TMP_DIR: OnceLock<PathBuf> = OnceLock::new();
+
+fn is_vam_root(folder: &Path) -> bool {
+    folder.join("VaM.exe").is_file()
+}
+
+// Library paths listed in Steam's libraryfolders.vdf, e.g. "path"  "D:\\SteamLibrary"
+fn steam_libraries() -> Vec<PathBuf> {
+    let mut result = Vec::new();
+    for steam in crate::platform::steam_folders() {
+        let text =
+            fs::read_to_string(steam.join("steamapps/libraryfolders.vdf")).unwrap_or_default();
+        result.push(steam);
+        for line in text.lines() {
+            let fields: Vec<&str> = line.split('"').filter(|f| !f.trim().is_empty()).collect();
+            if let ["path", path] = fields.as_slice() {
+                result.push(PathBuf::from(path.replace("\\\\", "\\")));
+            }
+        }
+    }
+    result
+}
+
+fn common_locations() -> Vec<PathBuf> {
+    let mut result = Vec::new();
+    for root in crate::platform::install_roots() {
+        for folder in ["VaM", "Virt-A-Mate", "Games/VaM", "Games/Virt-A-Mate"] {
+            result.push(root.join(folder));
+        }
+    }
+    for library in steam_libraries() {
+        for folder in ["VaM", "Virt-A-Mate"] {
+            result.push(library.join("steamapps/common").join(folder));
+        }
+    }
+    result
+}
+
+fn confirm(folder: &Path) -> anyhow::Result<()> {
+    if !io::stdin().is_terminal() {
+        anyhow::bail!(
+            "Found VaM at {}, pass --vam-root to use it / 在{}找到VaM，请用--vam-root指定",
+            folder.to_string_lossy(),
+            folder.to_string_lossy()
+        );
+    }
+    let question = format!(
+        "Use VaM folder {}? / 使用该VaM目录？",
+        folder.to_string_lossy()
+    );
+    if !crate::ui::confirm(&question) {
+        anyhow::bail!("Cancelled, pass --vam-root to choose another folder / 已取消");
+    }
+    Ok(())
+}
+
+pub fn addon_packages(vam_folder: &Path) -> PathBuf {
+    match ADDON_PATH.get() {
+        Some(folder) => folder.clone(),
+        None => vam_folder.join("AddonPackages"),
+    }
+}
+
+pub fn backup_folder(vam_folder: &Path) -> PathBuf {
+    match BACKUP_DIR.get() {
+        Some(folder) => folder.clone(),
+        None => vam_folder.join("VarCleaner/Backup"),
+    }
+}
+
+// Removed whole at the end of a clean, so a --tmp-dir only gets a VarCleaner folder inside it
+pub fn tmp_folder(vam_folder: &Path) -> PathBuf {
+    match TMP_DIR.get() {
+        Some(folder) => folder.join("VarCleaner"),
+        None => vam_folder.join("VarCleaner/Tmp"),
+    }
+}
+
+// Called once at startup with --backup-dir and --tmp-dir
+pub fn set_folders(backup_dir: Option<&Path>, tmp_dir: Option<&Path>) -> anyhow::Result<()> {
+    if let Some(folder) = backup_dir {
+        BACKUP_DIR.set(std::path::absolute(folder)?).unwrap();
+    }
+    if let Some(folder) = tmp_dir {
+        TMP_DIR.set(std::path::absolute(folder)?).unwrap();
+    }
+    Ok(())
+}
+
+// No VaM install around, so no Saves, prefs or profiles to look at
+pub fn is_standalone() -> bool {
+    ADDON_PATH.get().is_some()
+}
+
+// Work on a bare var folder; VarCleaner's own data goes next to it
+pub fn standalone(addon_path: &Path) -> anyhow::Result<PathBuf> {
+    if !addon_path.is_dir() {
+        anyhow::bail!(
+            "{} is not a folder / 该路径不是文件夹",
+            addon_path.to_string_lossy()
+        );
+    }
+    let addon_path = std::path::absolute(addon_path)?;
+    let data_folder = addon_path.parent().unwrap_or(&addon_path).to_path_buf();
+    info!(
+        "Skip the VaM check, cleaning {} / 跳过VaM检查，清理该目录",
+        addon_path.to_string_lossy()
+    );
+    ADDON_PATH.set(addon_path).unwrap();
+    Ok(data_folder)
+}
+
+// The VaM folder itself plus every configured profile folder that exists
+pub fn profiles(vam_folder: &Path) -> Vec<PathBuf> {
+    if is_standalone() {
+        return Vec::new();
+    }
+    let mut result = vec![vam_folder.to_path_buf()];
+    for profile in crate::config::get().profiles.iter() {
+        let profile = vam_folder.join(profile);
+        if profile.is_dir() && !result.contains(&profile) {
+            result.push(profile);
+        } else if !profile.is_dir() {
+            crate::console::warning!("Profile {} does not exist", profile.to_string_lossy());
+        }
+    }
+    result
+}
+
+// --vam-root, then the current folder and its parents, then the exe's folder and its
+// parents, then common install locations; anything but --vam-root or the current folder
+// is confirmed first
+pub fn find(explicit: Option<&Path>) -> anyhow::Result<PathBuf> {
+    if let Some(folder) = explicit {
+        if !is_vam_root(folder) {
+            anyhow::bail!(
+                "{} does not contain VaM.exe / 该目录下没有VaM.exe",
+                folder.to_string_lossy()
+            );
+        }
+        return Ok(std::path::absolute(folder)?);
+    }
+    let current = env::current_dir()?;
+    if is_vam_root(&current) {
+        return Ok(current);
+    }
+    let exe_folder = env::current_exe()?.parent().map(|p| p.to_path_buf());
+    let found = current
+        .ancestors()
+        .chain(exe_folder.iter().flat_map(|f| f.ancestors()))
+        .map(|f| f.to_path_buf())
+        .chain(common_locations())
+        .find(|f| is_vam_root(f));
+    match found {
+        Some(folder) => {
+            info!("Found VaM at {}", folder.to_string_lossy());
+            confirm(&folder)?;
+            Ok(folder)
+        }
+        None => anyhow::bail!(
+            "VaM.exe not found, put VarCleaner.exe in the VaM folder or pass --vam-root \n 未找到VaM.exe，请将VarCleaner.exe放在VaM.exe同级目录下或使用--vam-root指定"
+        ),
+    }
+}
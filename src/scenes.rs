@@ -0,0 +1,152 @@
+use crate::console::{info, summary, warning};
+use crate::index;
+use crate::intern::Name;
+use crate::jsoncache;
+use crate::package::PackageId;
+use crate::root;
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+use walkdir::WalkDir;
+
+pub enum Reference<'a> {
+    SelfEntry(&'a str),
+    Package(&'a str, &'a str),
+}
+
+// `SELF:/path` or `Creator.Pkg.Version:/path`; drive letters and urls are not references
+pub fn parse_reference(value: &str) -> Option<Reference<'_>> {
+    if let Some(path) = value.strip_prefix("SELF:/") {
+        return Some(Reference::SelfEntry(path));
+    }
+    let (package, path) = value.split_once(":/")?;
+    if package.matches('.').count() < 2 || package.contains(['/', '\\', ' ']) {
+        return None;
+    }
+    Some(Reference::Package(package, path))
+}
+
+pub fn collect_strings<'a>(value: &'a serde_json::Value, result: &mut Vec<&'a str>) {
+    match value {
+        serde_json::Value::String(s) => result.push(s),
+        serde_json::Value::Array(items) => items.iter().for_each(|v| collect_strings(v, result)),
+        serde_json::Value::Object(map) => map.values().for_each(|v| collect_strings(v, result)),
+        _ => {}
+    }
+}
+
+struct Resolver<'a> {
+    installed: &'a HashMap<String, PathBuf>,
+    entries: HashMap<PathBuf, Option<HashSet<Name>>>,
+}
+
+impl Resolver<'_> {
+    fn entries_of(&mut self, path: &Path) -> Option<&HashSet<Name>> {
+        self.entries
+            .entry(path.to_path_buf())
+            .or_insert_with(|| {
+                let entries = jsoncache::index(path).ok()?.entries;
+                Some(entries.into_iter().map(|e| e.name).collect())
+            })
+            .as_ref()
+    }
+
+    // None when the reference resolves, otherwise why it does not
+    fn check(&mut self, reference: &str, own_var: Option<&Path>) -> Option<String> {
+        let (path, target) = match parse_reference(reference)? {
+            Reference::SelfEntry(target) => (own_var?.to_path_buf(), target),
+            Reference::Package(package, target) => {
+                let resolved = PackageId::parse(package)
+                    .and_then(|id| index::resolve_dependency(&id, self.installed));
+                match resolved {
+                    Some((_, path)) => (path.clone(), target),
+                    None => return Some(format!("package {package} is not installed")),
+                }
+            }
+        };
+        match self.entries_of(&path) {
+            Some(entries) if entries.contains(target) => None,
+            Some(_) => Some(format!("{reference} does not exist")),
+            None => Some(format!("{} cannot be opened", path.to_string_lossy())),
+        }
+    }
+}
+
+fn check_scene(resolver: &mut Resolver, bytes: &[u8], own_var: Option<&Path>) -> Vec<String> {
+    let scene = match index::parse_json(bytes) {
+        Ok(scene) => scene,
+        Err(e) => return vec![format!("scene does not parse: {e}")],
+    };
+    let mut strings = Vec::new();
+    collect_strings(&scene, &mut strings);
+    let mut problems: Vec<String> = strings
+        .into_iter()
+        .filter_map(|s| resolver.check(s, own_var))
+        .collect();
+    problems.sort();
+    problems.dedup();
+    problems
+}
+
+pub fn check_all(vam_folder: &Path) {
+    let var_folder = &root::addon_packages(vam_folder);
+    let installed = index::installed_packages(var_folder);
+    let mut resolver = Resolver {
+        installed: &installed,
+        entries: HashMap::new(),
+    };
+    let mut broken: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    let mut scene_count = 0;
+
+    for entry in root::profiles(vam_folder)
+        .iter()
+        .flat_map(|profile| WalkDir::new(profile.join("Saves/scene")))
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_file() && e.file_name().to_string_lossy().ends_with(".json"))
+    {
+        let bytes = match fs::read(entry.path()) {
+            Ok(bytes) => bytes,
+            Err(_) => continue,
+        };
+        scene_count += 1;
+        let problems = check_scene(&mut resolver, &bytes, None);
+        if !problems.is_empty() {
+            let name = entry
+                .path()
+                .strip_prefix(vam_folder)
+                .unwrap_or(entry.path());
+            broken.insert(name.to_string_lossy().to_string(), problems);
+        }
+    }
+
+    let mut packages: Vec<(&String, &PathBuf)> = installed.iter().collect();
+    packages.sort();
+    for (package, path) in packages {
+        let scenes = match jsoncache::scenes(path) {
+            Ok(scenes) => scenes,
+            Err(_) => continue,
+        };
+        for (scene, text) in scenes {
+            scene_count += 1;
+            let problems = check_scene(&mut resolver, text.as_bytes(), Some(path));
+            if !problems.is_empty() {
+                broken.insert(format!("{package}:/{scene}"), problems);
+            }
+        }
+    }
+
+    for (scene, problems) in broken.iter() {
+        warning!("{scene}");
+        for problem in problems {
+            info!("  {problem}");
+        }
+    }
+    summary!(
+        "{} of {scene_count} scenes have broken references / {}个场景存在无效引用",
+        broken.len(),
+        broken.len()
+    );
+}
@@ -0,0 +1,123 @@
+use crate::backup;
+use crate::console::{error, success, verbose};
+use crate::index;
+use crate::journal::Journal;
+use crate::journal::Operation;
+use crate::reader::VarReader;
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+
+// Files VaM keeps next to a var, named after the var's file name
+const SUFFIXES: [&str; 5] = [".depend.txt", ".hide", ".fav", ".cache", ".disabled"];
+
+fn sidecar(var: &Path, suffix: &str) -> PathBuf {
+    let mut name = var.file_name().unwrap().to_os_string();
+    name.push(suffix);
+    var.with_file_name(name)
+}
+
+// VaM skips a var with a .disabled marker next to it; a folder named disabled is the
+// other common way to park vars
+pub fn is_disabled(var: &Path, var_folder: &Path) -> bool {
+    sidecar(var, ".disabled").exists()
+        || var
+            .parent()
+            .and_then(|folder| folder.strip_prefix(var_folder).ok())
+            .is_some_and(|folder| {
+                folder
+                    .components()
+                    .any(|c| c.as_os_str().eq_ignore_ascii_case("disabled"))
+            })
+}
+
+pub fn disable(var: &Path) -> anyhow::Result<PathBuf> {
+    let marker = sidecar(var, ".disabled");
+    crate::readonly::check(&marker)?;
+    fs::write(&marker, b"")?;
+    Ok(marker)
+}
+
+// The var `path` belongs to and the suffix it carries, None for anything else
+fn sidecar_of(path: &Path) -> Option<(PathBuf, &'static str)> {
+    let name = path.file_name()?.to_string_lossy().to_string();
+    let lower = name.to_ascii_lowercase();
+    SUFFIXES.iter().find_map(|suffix| {
+        let var = lower.strip_suffix(suffix)?;
+        var.ends_with(".var")
+            .then(|| (path.with_file_name(&name[..var.len()]), *suffix))
+    })
+}
+
+// One dependency of `var` per line, as VaM lists them
+fn rebuild_depend_txt(var: &Path, target: &Path) -> anyhow::Result<()> {
+    let mut reader = VarReader::open(var)?;
+    let dependencies = reader.meta().map(index::dependencies).unwrap_or_default();
+    let text: String = dependencies.iter().map(|dep| format!("{dep}\n")).collect();
+    crate::readonly::check(target)?;
+    fs::write(target, text)?;
+    Ok(())
+}
+
+// Sidecars of a var that is gone follow the package's installed var (e.g. merged), except
+// .disabled markers, and .depend.txt is rebuilt from the var it now describes.
+// Otherwise they move to the backup like the vars themselves.
+pub fn cleanup(vam_folder: &Path) {
+    let var_folder = &crate::root::addon_packages(vam_folder);
+    let backup_folder = &crate::root::backup_folder(vam_folder);
+    let installed = index::installed_packages(var_folder);
+    let mut journal = Journal::new("sidecars");
+    let mut followed = 0;
+    let sidecars: Vec<(PathBuf, PathBuf, &str)> = walkdir::WalkDir::new(var_folder)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_file())
+        .filter_map(|e| {
+            let (var, suffix) = sidecar_of(e.path())?;
+            Some((e.into_path(), var, suffix))
+        })
+        .filter(|(_, var, _)| !var.exists())
+        .collect();
+    for (path, var, suffix) in sidecars {
+        let follow = installed
+            .get(&index::package_name(&var))
+            .map(|live| (live, sidecar(live, suffix)))
+            // Whether the merged var is disabled is decided when it is written
+            .filter(|(_, target)| !target.exists() && suffix != ".disabled");
+        let result = match &follow {
+            Some((live, target)) if suffix == ".depend.txt" => rebuild_depend_txt(live, target)
+                .and_then(|_| {
+                    journal.record(Operation::Create {
+                        path: target.clone(),
+                        hash: None,
+                    });
+                    move_to_backup(&path, var_folder, backup_folder)
+                }),
+            Some((_, target)) => backup::move_verified(&path, target).map(|_| target.clone()),
+            None => move_to_backup(&path, var_folder, backup_folder),
+        };
+        match result {
+            Ok(to) => {
+                verbose!("{} -> {}", path.to_string_lossy(), to.to_string_lossy());
+                followed += usize::from(follow.is_some());
+                journal.record(Operation::Move { from: path, to });
+            }
+            Err(e) => error!("Failed to tidy {}: {e}", path.to_string_lossy()),
+        }
+    }
+    if journal.operations.is_empty() {
+        return;
+    }
+    success!(
+        "Tidied stale sidecar files, {followed} followed their merged var / 已整理失效的附属文件，其中{followed}个随合并后的Var更新"
+    );
+    if let Err(e) = journal.save(&vam_folder.join("VarCleaner/Journal")) {
+        error!("Failed to save the journal: {e}");
+    }
+}
+
+fn move_to_backup(path: &Path, var_folder: &Path, backup_folder: &Path) -> anyhow::Result<PathBuf> {
+    let target = backup::free_path(backup_folder.join(path.strip_prefix(var_folder)?));
+    backup::move_verified(path, &target)?;
+    Ok(target)
+}
@@ -0,0 +1,195 @@
+use crate::backup;
+use crate::console::{error, info, success, summary};
+use crate::format_size;
+use crate::index;
+use crate::journal::Journal;
+use crate::journal::Operation;
+use crate::protect::Protected;
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::collections::LinkedList;
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+// A folder receiving this many duplicated copies on one day looks like a bulk import
+const BULK_IMPORT: usize = 10;
+
+// Copies inside `folder` whose package also exists somewhere outside it
+fn duplicated_in(var_folder: &Path, folder: &Path) -> anyhow::Result<Vec<PathBuf>> {
+    let groups = crate::generate_duplicate_var_files(var_folder)?;
+    let mut result = Vec::new();
+    for copies in groups.values() {
+        let (inside, outside): (Vec<&PathBuf>, Vec<&PathBuf>) =
+            copies.iter().partition(|p| p.parent() == Some(folder));
+        if !outside.is_empty() {
+            result.extend(inside.into_iter().cloned());
+        }
+    }
+    result.sort();
+    Ok(result)
+}
+
+// Which folders mostly hold copies of packages that also live elsewhere
+pub fn report(var_folder: &Path) -> anyhow::Result<()> {
+    let groups = crate::generate_duplicate_var_files(var_folder)?;
+    // folder -> (duplicated vars, their size)
+    let mut folders: BTreeMap<PathBuf, (usize, u64)> = BTreeMap::new();
+    for copies in groups.values().filter(|c| c.len() > 1) {
+        for copy in copies.iter() {
+            let folder = copy.parent().unwrap().to_path_buf();
+            let entry = folders.entry(folder).or_default();
+            entry.0 += 1;
+            entry.1 += fs::metadata(copy).map(|m| m.len()).unwrap_or(0);
+        }
+    }
+    let mut folders: Vec<(PathBuf, usize, u64)> = folders
+        .into_iter()
+        .map(|(folder, (count, size))| (folder, count, size))
+        .collect();
+    folders.sort_by_key(|(_, _, size)| std::cmp::Reverse(*size));
+    for (folder, duplicated, size) in folders.iter() {
+        let total = fs::read_dir(folder)
+            .map(|entries| {
+                entries
+                    .filter_map(|e| e.ok())
+                    .filter(|e| index::is_var(&e.path()))
+                    .count()
+            })
+            .unwrap_or(0);
+        let name = folder.strip_prefix(var_folder).unwrap_or(folder);
+        info!(
+            "AddonPackages/{}: {duplicated} of {total} vars have copies elsewhere ({})",
+            name.to_string_lossy(),
+            format_size(*size)
+        );
+    }
+    summary!(
+        "{} folders hold duplicates; use --drop-folder FOLDER to back up one folder's copies / {}个文件夹包含重复Var",
+        folders.len(),
+        folders.len()
+    );
+    why(var_folder, &groups);
+    Ok(())
+}
+
+// `YYYY-MM-DD` in UTC
+fn day(time: SystemTime) -> String {
+    let days = time
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() / 86400)
+        .unwrap_or(0) as i64;
+    // Civil date from days since 1970-01-01 (Howard Hinnant's algorithm)
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let d = doy - (153 * mp + 2) / 5 + 1;
+    let m = if mp < 10 { mp + 3 } else { mp - 9 };
+    let y = yoe + era * 400 + i64::from(m <= 2);
+    format!("{y:04}-{m:02}-{d:02}")
+}
+
+// When a copy arrived: creation time where the file system keeps it, else last write
+fn arrived(path: &Path) -> Option<String> {
+    let metadata = fs::metadata(path).ok()?;
+    metadata
+        .created()
+        .or_else(|_| metadata.modified())
+        .ok()
+        .map(day)
+}
+
+// How many copies duplicated packages have, and which days and folders they arrived in,
+// pointing at the habit behind the duplicates rather than the duplicates themselves
+fn why(var_folder: &Path, groups: &HashMap<String, LinkedList<PathBuf>>) {
+    let mut sizes = [0usize; 4];
+    // (day, folder) -> copies of duplicated packages arriving there that day
+    let mut arrivals: BTreeMap<(String, PathBuf), usize> = BTreeMap::new();
+    let mut same_day = 0;
+    for copies in groups.values().filter(|c| c.len() > 1) {
+        sizes[copies.len().min(5) - 2] += 1;
+        let days: Vec<Option<String>> = copies.iter().map(|c| arrived(c)).collect();
+        if days[0].is_some() && days.iter().all(|d| d == &days[0]) {
+            same_day += 1;
+        }
+        for (copy, day) in copies.iter().zip(days) {
+            let Some(day) = day else { continue };
+            let folder = copy.parent().unwrap().to_path_buf();
+            *arrivals.entry((day, folder)).or_default() += 1;
+        }
+    }
+    info!(
+        "Copies per duplicated package: 2: {}, 3: {}, 4: {}, 5+: {} / 每个重复包的副本数: 2: {}, 3: {}, 4: {}, 5+: {}",
+        sizes[0], sizes[1], sizes[2], sizes[3], sizes[0], sizes[1], sizes[2], sizes[3]
+    );
+    let mut bulk: Vec<((String, PathBuf), usize)> = arrivals
+        .into_iter()
+        .filter(|(_, count)| *count >= BULK_IMPORT)
+        .collect();
+    bulk.sort_by_key(|(_, count)| std::cmp::Reverse(*count));
+    for ((day, folder), count) in bulk.iter() {
+        let name = folder.strip_prefix(var_folder).unwrap_or(folder);
+        info!(
+            "{day}: {count} duplicated vars arrived in AddonPackages/{}, likely a pack imported again / {day}: {count}个重复Var被放入AddonPackages/{}，可能是同一资源包被重复导入",
+            name.to_string_lossy(),
+            name.to_string_lossy()
+        );
+    }
+    if same_day > 0 {
+        summary!(
+            "{same_day} duplicated packages had every copy arrive the same day: the same files were copied into several folders at once / {same_day}个重复包的所有副本在同一天出现：同一批文件被同时复制到多个文件夹"
+        );
+    }
+}
+
+// Move every copy in `folder` that also exists elsewhere to VarCleaner/Backup
+pub fn drop_folder(vam_folder: &Path, folder: &Path, protected: &Protected) -> anyhow::Result<()> {
+    let var_folder = &crate::root::addon_packages(vam_folder);
+    let folder = &var_folder.join(folder);
+    if !folder.is_dir()
+        || !folder.starts_with(var_folder)
+        || folder
+            .components()
+            .any(|c| c == std::path::Component::ParentDir)
+    {
+        anyhow::bail!(
+            "{} is not a folder inside AddonPackages",
+            folder.to_string_lossy()
+        );
+    }
+    let backup_folder = &crate::root::backup_folder(vam_folder);
+    let mut journal = Journal::new("drop-folder");
+    let mut saved = 0;
+    for path in duplicated_in(var_folder, folder)? {
+        if protected.skips(&path) {
+            continue;
+        }
+        let size = fs::metadata(&path).map(|m| m.len()).unwrap_or(0);
+        let target = backup::free_path(backup_folder.join(path.strip_prefix(var_folder)?));
+        match backup::move_verified(&path, &target) {
+            Ok(()) => {
+                saved += size;
+                journal.record(Operation::Move {
+                    from: path,
+                    to: target,
+                });
+            }
+            Err(e) => error!("Failed to back up {}: {e}", path.to_string_lossy()),
+        }
+    }
+    success!(
+        "Moved {} duplicated vars ({}) from {} to the backup",
+        journal.operations.len(),
+        format_size(saved),
+        folder.to_string_lossy()
+    );
+    if !journal.operations.is_empty() {
+        journal.save(&vam_folder.join("VarCleaner/Journal"))?;
+    }
+    Ok(())
+}
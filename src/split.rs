@@ -0,0 +1,181 @@
+use crate::backup;
+use crate::builder::VarBuilder;
+use crate::console::{success, summary, verbose};
+use crate::content;
+use crate::index;
+use crate::journal::Journal;
+use crate::journal::Operation;
+use crate::protect::Protected;
+use crate::reader::VarReader;
+use serde_json::json;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+
+fn part_of(name: &str) -> Option<&'static str> {
+    match content::category(name) {
+        "textures" => Some("Textures"),
+        "audio" => Some("Audio"),
+        "scenes" => Some("Scenes"),
+        _ => None,
+    }
+}
+
+// Point `SELF:/path` references at the part that now holds `path`
+fn rewrite_references(
+    text: &str,
+    own_package: &str,
+    locations: &HashMap<String, String>,
+    dependencies: &mut BTreeSet<String>,
+) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(pos) = rest.find("SELF:/") {
+        result.push_str(&rest[..pos]);
+        let after = &rest[pos + "SELF:/".len()..];
+        let end = after.find('"').unwrap_or(after.len());
+        let target = &after[..end];
+        match locations.get(target) {
+            Some(package) if package != own_package => {
+                result.push_str(&format!("{package}:/"));
+                dependencies.insert(package.clone());
+            }
+            _ => result.push_str("SELF:/"),
+        }
+        result.push_str(target);
+        rest = &after[end..];
+    }
+    result.push_str(rest);
+    result
+}
+
+fn part_meta(
+    original: &Option<Value>,
+    package: &str,
+    content_list: &[String],
+    part_dependencies: &BTreeSet<String>,
+) -> Value {
+    let mut meta = original.clone().unwrap_or_else(|| json!({}));
+    let name = package.split('.').nth(1).unwrap_or(package);
+    meta["packageName"] = json!(name);
+    meta["contentList"] = json!(content_list);
+    if !meta.get("dependencies").is_some_and(|d| d.is_object()) {
+        meta["dependencies"] = json!({});
+    }
+    for dep in part_dependencies.iter() {
+        meta["dependencies"][dep] =
+            json!({ "licenseType": meta["licenseType"].clone(), "dependencies": {} });
+    }
+    meta
+}
+
+fn write_part(
+    var: &mut VarReader,
+    var_path: &Path,
+    target: &Path,
+    package: &str,
+    entries: &[String],
+    meta: &Option<Value>,
+    locations: &HashMap<String, String>,
+) -> anyhow::Result<()> {
+    let mut builder = VarBuilder::new(zip::CompressionMethod::Stored);
+    let mut dependencies = BTreeSet::new();
+    for name in entries.iter() {
+        if !content::is_text(name) {
+            builder.add_raw(name, var_path);
+            continue;
+        }
+        let buffer = var.read_entry(name)?;
+        let text = String::from_utf8_lossy(&buffer);
+        let rewritten = rewrite_references(&text, package, locations, &mut dependencies);
+        builder.add_bytes(name, rewritten.into_bytes());
+    }
+    let meta = part_meta(meta, package, entries, &dependencies);
+    builder.add_bytes("meta.json", serde_json::to_vec_pretty(&meta)?);
+    builder.write(target)
+}
+
+pub fn run(vam_folder: &Path, var_path: &Path, protected: &Protected) -> anyhow::Result<()> {
+    if protected.contains(&index::package_name(var_path)) {
+        anyhow::bail!("{} is listed in protected.txt", var_path.to_string_lossy());
+    }
+    let var_folder = &crate::root::addon_packages(vam_folder);
+    let backup_folder = &crate::root::backup_folder(vam_folder);
+    let mut var = VarReader::open(var_path)?;
+    let var_index = var.index()?;
+    let parts: Vec<&str> = var_index.package.split('.').collect();
+    if parts.len() != 3 {
+        anyhow::bail!("{} is not named Creator.Package.Version", var_index.package);
+    }
+    let (creator, name, version) = (parts[0], parts[1], parts[2]);
+
+    let mut locations: HashMap<String, String> = HashMap::new();
+    let mut groups: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for entry in var_index.entries.iter().filter(|e| &*e.name != "meta.json") {
+        let package = match part_of(&entry.name) {
+            Some(part) => format!("{creator}.{name}{part}.{version}"),
+            None => var_index.package.clone(),
+        };
+        locations.insert(entry.name.to_string(), package.clone());
+        groups
+            .entry(package)
+            .or_default()
+            .push(entry.name.to_string());
+    }
+    if groups.len() < 2 {
+        summary!("{} has nothing to split / 无需拆分", var_index.package);
+        return Ok(());
+    }
+
+    let folder = var_path.parent().unwrap();
+    let mut written: Vec<(PathBuf, PathBuf)> = Vec::new();
+    for (package, entries) in groups.iter() {
+        let target = folder.join(format!("{package}.var"));
+        let tmp = folder.join(format!("{package}.var.tmp"));
+        let result = write_part(
+            &mut var,
+            var_path,
+            &tmp,
+            package,
+            entries,
+            &var_index.meta,
+            &locations,
+        );
+        written.push((tmp, target));
+        if let Err(e) = result {
+            for (tmp, _) in written.iter() {
+                let _ = fs::remove_file(tmp);
+            }
+            return Err(e);
+        }
+    }
+    drop(var);
+
+    let mut journal = Journal::new("split");
+    let backup_path = backup::free_path(backup_folder.join(var_path.strip_prefix(var_folder)?));
+    if let Err(e) = backup::move_verified(var_path, &backup_path) {
+        for (tmp, _) in written.iter() {
+            let _ = fs::remove_file(tmp);
+        }
+        return Err(e);
+    }
+    journal.record(Operation::Move {
+        from: var_path.to_path_buf(),
+        to: backup_path,
+    });
+    for (tmp, target) in written.iter() {
+        fs::rename(tmp, target)?;
+        journal.record(Operation::Create {
+            path: target.clone(),
+            hash: None,
+        });
+        success!("Created {}", target.to_string_lossy());
+    }
+    let journal_path = journal.save(&vam_folder.join("VarCleaner/Journal"))?;
+    verbose!("Journal written to {}", journal_path.to_string_lossy());
+    Ok(())
+}
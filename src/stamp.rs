@@ -0,0 +1,100 @@
+use crate::archive;
+use crate::reader::VarReader;
+use serde::Deserialize;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::collections::LinkedList;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::path::PathBuf;
+
+// Written into merged vars by clean --stamp, next to meta.json
+pub const STAMP_ENTRY: &str = "VarCleaner.stamp.json";
+
+// Which originals a merged var came from, for packs shared around: anyone holding the
+// originals can check their blake3 against `sources`, and `entries` shows the var was not
+// changed since
+#[derive(Serialize, Deserialize)]
+struct Stamp {
+    package: String,
+    sources: Vec<StampedSource>,
+    // blake3 over the name and data hash of every other entry, in name order
+    entries: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct StampedSource {
+    name: String,
+    blake3: String,
+}
+
+fn entries_digest(var: &Path) -> anyhow::Result<String> {
+    let mut hashes = VarReader::open(var)?.hash_entries()?;
+    hashes.retain(|(name, _)| name != STAMP_ENTRY);
+    hashes.sort_by(|a, b| a.0.cmp(&b.0));
+    let mut hasher = blake3::Hasher::new();
+    for (name, hash) in hashes {
+        hasher.update(name.as_bytes());
+        hasher.update(b"\0");
+        hasher.update(hash.as_bytes());
+    }
+    Ok(hasher.finalize().to_hex().to_string())
+}
+
+// `hashes` are the whole-file hashes of `filelist`, in the same order
+pub fn write(
+    partial: &Path,
+    package: &str,
+    filelist: &LinkedList<PathBuf>,
+    hashes: &[io::Result<String>],
+) -> anyhow::Result<()> {
+    let mut sources = Vec::new();
+    for (path, hash) in filelist.iter().zip(hashes) {
+        let Ok(hash) = hash else {
+            anyhow::bail!("{} could not be hashed", path.to_string_lossy());
+        };
+        sources.push(StampedSource {
+            name: path.file_name().unwrap().to_string_lossy().to_string(),
+            blake3: hash.clone(),
+        });
+    }
+    let stamp = Stamp {
+        package: package.to_string(),
+        sources,
+        entries: entries_digest(partial)?,
+    };
+    let replacements =
+        HashMap::from([(STAMP_ENTRY.to_string(), serde_json::to_vec_pretty(&stamp)?)]);
+    let stamped = partial.with_extension("stamped");
+    if let Err(e) = archive::rewrite_var(partial, &stamped, &replacements) {
+        let _ = fs::remove_file(&stamped);
+        return Err(e);
+    }
+    fs::rename(stamped, partial)?;
+    Ok(())
+}
+
+// None for a var without a stamp, otherwise whether its entries are unchanged and every one of
+// `sources` is among the stamped originals
+pub fn check(merged: &Path, sources: &[PathBuf]) -> anyhow::Result<Option<bool>> {
+    let Ok(bytes) = VarReader::open(merged)?.read_entry(STAMP_ENTRY) else {
+        return Ok(None);
+    };
+    let stamp: Stamp = serde_json::from_slice(&bytes)?;
+    let mut ok = entries_digest(merged)? == stamp.entries;
+    if !ok {
+        crate::console::error!("Entries changed since the var was stamped");
+    }
+    for source in sources {
+        let hash = crate::hashing::hash_file(source)?;
+        if !stamp.sources.iter().any(|s| s.blake3 == hash) {
+            crate::console::error!(
+                "{} is not one of the stamped originals",
+                source.to_string_lossy()
+            );
+            ok = false;
+        }
+    }
+    Ok(Some(ok))
+}
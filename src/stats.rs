@@ -0,0 +1,265 @@
+use crate::console::{info, summary};
+use crate::content;
+use crate::format_size;
+use serde::Deserialize;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+// Per content category: duplicate bytes dropped and bytes kept, across one clean run
+static TOTALS: Mutex<BTreeMap<&'static str, (u64, u64)>> = Mutex::new(BTreeMap::new());
+
+pub fn reset() {
+    TOTALS.lock().unwrap().clear();
+}
+
+// `copies` are the sizes of every copy of each entry, `kept` the size of the one written
+pub fn record<'a>(
+    copies: impl IntoIterator<Item = (&'a str, u64)>,
+    kept: impl Fn(&str) -> Option<u64>,
+) {
+    let mut package: BTreeMap<&'static str, (u64, u64)> = BTreeMap::new();
+    for (name, total) in copies {
+        let kept = kept(name).unwrap_or(0);
+        let entry = package.entry(content::category(name)).or_default();
+        entry.0 += total.saturating_sub(kept);
+        entry.1 += kept;
+    }
+    let mut totals = TOTALS.lock().unwrap();
+    for (category, (dropped, kept)) in package {
+        let entry = totals.entry(category).or_default();
+        entry.0 += dropped;
+        entry.1 += kept;
+    }
+}
+
+// Local running total across runs, never sent anywhere
+#[derive(Serialize, Deserialize, Default)]
+struct Savings {
+    bytes: u64,
+    runs: u64,
+}
+
+fn savings_path(vam_folder: &Path) -> std::path::PathBuf {
+    vam_folder.join("VarCleaner/savings.json")
+}
+
+fn load_savings(vam_folder: &Path) -> Savings {
+    fs::read(savings_path(vam_folder))
+        .ok()
+        .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+        .unwrap_or_default()
+}
+
+pub fn show_savings(vam_folder: &Path) {
+    if !crate::config::get().track_savings {
+        return;
+    }
+    let savings = load_savings(vam_folder);
+    if savings.runs > 0 {
+        summary!(
+            "VarCleaner has reclaimed {} over {} runs / 累计{}次清理共节省{}",
+            format_size(savings.bytes),
+            savings.runs,
+            savings.runs,
+            format_size(savings.bytes)
+        );
+    }
+}
+
+// Add this run's savings to the total
+pub fn save(vam_folder: &Path) {
+    if !crate::config::get().track_savings {
+        return;
+    }
+    let mut savings = load_savings(vam_folder);
+    savings.bytes += TOTALS
+        .lock()
+        .unwrap()
+        .values()
+        .map(|(dropped, _)| dropped)
+        .sum::<u64>();
+    savings.runs += 1;
+    let path = savings_path(vam_folder);
+    let written = crate::readonly::check(&path)
+        .and_then(|_| fs::create_dir_all(path.parent().unwrap()))
+        .and_then(|_| fs::write(&path, serde_json::to_vec_pretty(&savings).unwrap()));
+    if let Err(e) = written {
+        crate::console::warning!("Failed to write {}: {e}", path.to_string_lossy());
+    }
+}
+
+// This run's progress so far, rewritten after every package with --pace overnight and removed
+// when the run finishes. One left behind belongs to an interrupted run and is added to the
+// savings by the next run.
+#[derive(Serialize, Deserialize, Default)]
+struct Checkpoint {
+    packages: usize,
+    dropped: u64,
+}
+
+static CHECKPOINTED: Mutex<usize> = Mutex::new(0);
+
+fn checkpoint_path(vam_folder: &Path) -> std::path::PathBuf {
+    vam_folder.join("VarCleaner/checkpoint.json")
+}
+
+pub fn checkpoint(vam_folder: &Path) {
+    let mut packages = CHECKPOINTED.lock().unwrap();
+    *packages += 1;
+    let checkpoint = Checkpoint {
+        packages: *packages,
+        dropped: TOTALS
+            .lock()
+            .unwrap()
+            .values()
+            .map(|(dropped, _)| dropped)
+            .sum(),
+    };
+    let path = checkpoint_path(vam_folder);
+    let tmp = path.with_extension("tmp");
+    // Renamed into place, an interruption leaves the previous checkpoint whole
+    let written = crate::readonly::check(&path)
+        .and_then(|_| fs::create_dir_all(path.parent().unwrap()))
+        .and_then(|_| fs::write(&tmp, serde_json::to_vec(&checkpoint).unwrap()))
+        .and_then(|_| fs::rename(&tmp, &path));
+    if let Err(e) = written {
+        crate::console::warning!("Failed to write {}: {e}", path.to_string_lossy());
+    }
+}
+
+// Account for a run that stopped without finishing, then start counting afresh
+pub fn recover_checkpoint(vam_folder: &Path) {
+    *CHECKPOINTED.lock().unwrap() = 0;
+    let path = checkpoint_path(vam_folder);
+    let Some(checkpoint) = fs::read(&path)
+        .ok()
+        .and_then(|bytes| serde_json::from_slice::<Checkpoint>(&bytes).ok())
+    else {
+        return;
+    };
+    info!(
+        "The last run stopped after {} packages and removed {} of duplicates / 上次运行在处理{}个包后中断",
+        checkpoint.packages,
+        format_size(checkpoint.dropped),
+        checkpoint.packages
+    );
+    if crate::config::get().track_savings {
+        let mut savings = load_savings(vam_folder);
+        savings.bytes += checkpoint.dropped;
+        savings.runs += 1;
+        let savings_path = savings_path(vam_folder);
+        let _ = fs::write(&savings_path, serde_json::to_vec_pretty(&savings).unwrap());
+    }
+    let _ = fs::remove_file(path);
+}
+
+pub fn clear_checkpoint(vam_folder: &Path) {
+    let _ = fs::remove_file(checkpoint_path(vam_folder));
+}
+
+pub fn report() {
+    let totals = TOTALS.lock().unwrap();
+    let dropped: u64 = totals.values().map(|(dropped, _)| dropped).sum();
+    if dropped == 0 {
+        return;
+    }
+    let mut categories: Vec<(&&str, &(u64, u64))> = totals.iter().collect();
+    categories.sort_by_key(|(_, (dropped, _))| std::cmp::Reverse(*dropped));
+    for (category, (dropped, kept)) in categories {
+        info!(
+            "  {category:<10} {:>12} duplicate, {:>12} kept",
+            format_size(*dropped),
+            format_size(*kept)
+        );
+    }
+    summary!(
+        "Removed {} of duplicate content / 共去除{}重复内容",
+        format_size(dropped),
+        format_size(dropped)
+    );
+}
+
+// One JSON line per finished package in VarCleaner/reports/run-<ts>.jsonl, written as each
+// package completes so a long run can be watched live and a crash loses nothing done so far
+pub struct RunLog {
+    file: Mutex<Option<File>>,
+}
+
+#[derive(Serialize)]
+struct RunLogLine<'a> {
+    package: &'a str,
+    // merged, identical, empty, skipped, in-use, limit, grew, stopped or failed
+    outcome: &'a str,
+    before: u64,
+    after: u64,
+    saved: u64,
+    finished: u64,
+}
+
+// The newest run report; names hold the start time, so they sort by age
+pub fn latest_report(vam_folder: &Path) -> Option<std::path::PathBuf> {
+    fs::read_dir(vam_folder.join("VarCleaner/reports"))
+        .ok()?
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|p| p.extension().is_some_and(|e| e == "jsonl"))
+        .max()
+}
+
+impl RunLog {
+    pub fn create(vam_folder: &Path) -> RunLog {
+        let secs = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let path = vam_folder.join(format!("VarCleaner/reports/run-{secs}.jsonl"));
+        let file = crate::readonly::check(&path)
+            .and_then(|_| fs::create_dir_all(path.parent().unwrap()))
+            // Two runs within one second share a report rather than truncate it
+            .and_then(|_| File::options().create(true).append(true).open(&path));
+        match file {
+            Ok(file) => RunLog {
+                file: Mutex::new(Some(file)),
+            },
+            Err(e) => {
+                crate::console::warning!("Failed to create {}: {e}", path.to_string_lossy());
+                RunLog {
+                    file: Mutex::new(None),
+                }
+            }
+        }
+    }
+
+    // `before` is the size of every copy, `after` what is left of the package
+    pub fn record(&self, package: &str, outcome: &str, before: u64, after: u64) {
+        let mut file = self.file.lock().unwrap();
+        let Some(out) = file.as_mut() else {
+            return;
+        };
+        let line = RunLogLine {
+            package,
+            outcome,
+            before,
+            after,
+            saved: before.saturating_sub(after),
+            finished: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_secs(),
+        };
+        let mut text = serde_json::to_string(&line).unwrap();
+        text.push('\n');
+        // Each line goes out in one write and is flushed, a reader never sees half a line
+        if let Err(e) = out.write_all(text.as_bytes()).and_then(|_| out.flush()) {
+            crate::console::warning!("Failed to write the run report, stop writing it: {e}");
+            *file = None;
+        }
+    }
+}
@@ -0,0 +1,142 @@
+use crate::console::warning;
+use serde::Serialize;
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
+
+// Progress of the running clean, rewritten every second to a file stream overlays can read:
+// JSON, or one line of text when the file ends in .txt (for a plain OBS text source)
+struct Progress {
+    file: Option<PathBuf>,
+    started: Option<Instant>,
+    total: usize,
+    done: usize,
+    // Packages being merged right now, the most recently started last
+    running: Vec<String>,
+    finished: bool,
+}
+
+static PROGRESS: Mutex<Progress> = Mutex::new(Progress {
+    file: None,
+    started: None,
+    total: 0,
+    done: 0,
+    running: Vec::new(),
+    finished: false,
+});
+
+#[derive(Serialize)]
+struct Status<'a> {
+    state: &'a str,
+    package: Option<&'a String>,
+    running: &'a [String],
+    done: usize,
+    total: usize,
+    percent: f64,
+    eta_seconds: Option<u64>,
+}
+
+pub fn start(file: &Path, total: usize) {
+    *PROGRESS.lock().unwrap() = Progress {
+        file: Some(file.to_path_buf()),
+        started: Some(Instant::now()),
+        total,
+        done: 0,
+        running: Vec::new(),
+        finished: false,
+    };
+    write();
+    thread::spawn(|| loop {
+        thread::sleep(Duration::from_secs(1));
+        if PROGRESS.lock().unwrap().finished {
+            break;
+        }
+        write();
+    });
+}
+
+pub fn begin(package: &str) {
+    let mut progress = PROGRESS.lock().unwrap();
+    if progress.file.is_some() {
+        progress.running.push(package.to_string());
+    }
+}
+
+pub fn end(package: &str) {
+    let mut progress = PROGRESS.lock().unwrap();
+    if progress.file.is_some() {
+        progress.running.retain(|p| p != package);
+        progress.done += 1;
+    }
+}
+
+pub fn finish() {
+    let mut progress = PROGRESS.lock().unwrap();
+    if progress.file.is_none() {
+        return;
+    }
+    progress.finished = true;
+    progress.running.clear();
+    drop(progress);
+    write();
+    PROGRESS.lock().unwrap().file = None;
+}
+
+fn write() {
+    let mut progress = PROGRESS.lock().unwrap();
+    let Some(file) = progress.file.clone() else {
+        return;
+    };
+    let percent = match progress.total {
+        0 => 100.0,
+        total => (progress.done * 1000 / total) as f64 / 10.0,
+    };
+    // Average time per finished package times the packages left
+    let eta_seconds = progress
+        .started
+        .filter(|_| progress.done > 0)
+        .map(|started| {
+            let left = progress.total.saturating_sub(progress.done) as u64;
+            started.elapsed().as_secs() * left / progress.done as u64
+        });
+    let status = Status {
+        state: if progress.finished { "done" } else { "running" },
+        package: progress.running.last(),
+        running: &progress.running,
+        done: progress.done,
+        total: progress.total,
+        percent,
+        eta_seconds,
+    };
+    let text = if file
+        .extension()
+        .is_some_and(|e| e.eq_ignore_ascii_case("txt"))
+    {
+        let eta = eta_seconds.map_or(String::new(), |s| {
+            format!(", {}:{:02} left", s / 60, s % 60)
+        });
+        match status.package {
+            Some(package) => format!(
+                "{package} {}% ({}/{}){eta}",
+                percent, status.done, status.total
+            ),
+            None => format!("{}% ({}/{})", percent, status.done, status.total),
+        }
+    } else {
+        serde_json::to_string(&status).unwrap()
+    };
+    // Written next to the file and renamed, so an overlay never reads half a status
+    let tmp = file.with_extension("tmp");
+    let result = crate::readonly::check(&file)
+        .and_then(|_| fs::write(&tmp, text))
+        .and_then(|_| fs::rename(&tmp, &file));
+    if let Err(e) = result {
+        // Once is enough, the clean itself goes on
+        warning!("Failed to write {}: {e}", file.to_string_lossy());
+        progress.file = None;
+    }
+}
@@ -0,0 +1,368 @@
+use crate::builder::VarBuilder;
+use crate::console::{info, success, summary, warning};
+use crate::index;
+use crate::reader::VarReader;
+use crate::validate;
+use crate::CleanArgs;
+use std::collections::hash_map::Entry;
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::collections::LinkedList;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::path::PathBuf;
+
+// First pass: read every copy once, front to back, letting the zip reader check each
+// entry's CRC, and pick the copy every entry is taken from
+fn pick_winners(
+    sources: &LinkedList<PathBuf>,
+    candidates: &mut BTreeMap<String, Vec<(usize, u64)>>,
+) -> anyhow::Result<HashMap<String, (usize, u64)>> {
+    let mut winners: HashMap<String, (usize, u64)> = HashMap::new();
+    let mut stamps: HashMap<(usize, String), crate::EntryStamp> = HashMap::new();
+    for (copy, path) in sources.iter().enumerate() {
+        let mut archive = match index::open_var(path) {
+            Ok(archive) => archive,
+            Err(_) => {
+                warning!("zipfile {} is invaild", path.to_string_lossy());
+                continue;
+            }
+        };
+        for i in 0..archive.len() {
+            crate::queue::check_aborted()?;
+            let mut file = archive.by_index(i)?;
+            if file.is_dir() || file.enclosed_name().is_none() {
+                continue;
+            }
+            if crate::nested::is_nested(file.name()) {
+                warning!("Leave nested {} out of the merged var", file.name());
+                continue;
+            }
+            let name = file.name().to_string();
+            // Sizes come from the central directory, streamed zips leave them out of the local
+            // headers; data of any other length is as corrupt as a bad CRC
+            let expected = file.size();
+            let stamp = (file.last_modified(), file.crc32());
+            let size = match io::copy(&mut file, &mut io::sink()) {
+                Ok(size) if size == expected => size,
+                Err(e) if e.kind() == io::ErrorKind::StorageFull => return Err(e.into()),
+                _ => {
+                    warning!(
+                        "file {name} in {} is corrupt, ignore",
+                        path.to_string_lossy()
+                    );
+                    continue;
+                }
+            };
+            candidates
+                .entry(name.clone())
+                .or_default()
+                .push((copy, size));
+            stamps.insert((copy, name.clone()), stamp);
+            match winners.get(&name) {
+                Some(&kept) if !crate::prefer_copy(&stamps, &name, kept, copy, size) => {}
+                _ => {
+                    winners.insert(name, (copy, size));
+                }
+            }
+        }
+    }
+    Ok(winners)
+}
+
+// Of the winners picked per entry, only categories being merged may come from a copy other
+// than the live one, and of names differing only in case one stays
+fn keep_merged(package: &str, winners: &mut HashMap<String, (usize, u64)>, args: &CleanArgs) {
+    winners.retain(|name, (copy, _)| *copy == 0 || args.merges(name));
+    for name in crate::case_collisions(package, winners) {
+        winners.remove(&name);
+    }
+}
+
+// Images nothing refers to, read from the copies that won the text entries; each copy is
+// opened once
+fn strip_promo(
+    sources: &LinkedList<PathBuf>,
+    target: &Path,
+    winners: &mut HashMap<String, (usize, u64)>,
+    args: &CleanArgs,
+) {
+    let paths: Vec<&PathBuf> = sources.iter().collect();
+    let mut readers: HashMap<usize, VarReader> = HashMap::new();
+    let mut promo = crate::strip::promo_images(winners, |name| {
+        let copy = winners[name].0;
+        let reader = match readers.entry(copy) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert(VarReader::open(paths[copy])?),
+        };
+        reader.read_entry(name)
+    });
+    promo.retain(|name| args.merges(name));
+    let saved: u64 = promo
+        .iter()
+        .map(|name| winners.remove(name).unwrap().1)
+        .sum();
+    if !promo.is_empty() {
+        success!(
+            "Stripped {} promo images from {}, saved {}",
+            promo.len(),
+            target.file_name().unwrap().to_string_lossy(),
+            crate::format_size(saved)
+        );
+    }
+}
+
+// Parts that belong together come from the same copy, or are left out together
+fn pair_parts(
+    candidates: &BTreeMap<String, Vec<(usize, u64)>>,
+    winners: &mut HashMap<String, (usize, u64)>,
+) {
+    for (name, decision) in crate::previews::pair_parts(candidates, winners) {
+        match decision {
+            Some(winner) => winners.insert(name, winner),
+            None => winners.remove(&name),
+        };
+    }
+}
+
+// The copy each entry of the merged var `target` is taken from, everything a merge leaves out
+// already dropped; `candidates` gets every copy of every entry
+fn select_winners(
+    sources: &LinkedList<PathBuf>,
+    target: &Path,
+    args: &CleanArgs,
+    builtin: Option<&crate::builtin::BuiltIn>,
+    candidates: &mut BTreeMap<String, Vec<(usize, u64)>>,
+) -> anyhow::Result<HashMap<String, (usize, u64)>> {
+    let mut winners = pick_winners(sources, candidates)?;
+    keep_merged(&index::package_name(target), &mut winners, args);
+    if args.strip_promo_images {
+        strip_promo(sources, target, &mut winners, args);
+    }
+    if let Some(builtin) = builtin {
+        let paths: Vec<&PathBuf> = sources.iter().collect();
+        let entries: Vec<(String, u64)> = winners
+            .iter()
+            .map(|(name, (_, size))| (name.clone(), *size))
+            .collect();
+        let mut shipped = builtin.duplicates(&entries, |name| {
+            VarReader::open(paths[winners[name].0])?.read_entry(name)
+        });
+        shipped.retain(|name| args.merges(name));
+        let saved: u64 = shipped
+            .iter()
+            .map(|name| winners.remove(name).unwrap().1)
+            .sum();
+        crate::builtin::report(target, shipped.len(), saved);
+    }
+    pair_parts(candidates, &mut winners);
+    Ok(winners)
+}
+
+// Second pass: read each source once more in order, copying only the entries it won
+fn write_winners(
+    sources: &LinkedList<PathBuf>,
+    winners: &HashMap<String, (usize, u64)>,
+    dst: &Path,
+    args: &CleanArgs,
+) -> anyhow::Result<()> {
+    let mut builder = VarBuilder::new(args.compression.method());
+    for (copy, path) in sources.iter().enumerate() {
+        if !winners.values().any(|(c, _)| *c == copy) {
+            continue;
+        }
+        for entry in VarReader::open(path)?.entries()? {
+            if winners.get(&*entry.name).map(|(c, _)| *c) != Some(copy) {
+                continue;
+            }
+            if args.merges(&entry.name) {
+                builder.add_entry(&entry.name, path);
+            } else {
+                builder.add_raw(&entry.name, path);
+            }
+        }
+    }
+    builder.write(dst)
+}
+
+// A merged var written next to its target, and what it kept of each entry
+pub struct Merged {
+    pub partial: PathBuf,
+    // Per entry name, the sizes of every copy together and of the copy kept
+    copies: BTreeMap<String, u64>,
+    kept: HashMap<String, u64>,
+}
+
+impl Merged {
+    // Counted only once the merged var replaces its copies
+    pub fn record_savings(&self) {
+        crate::stats::record(
+            self.copies
+                .iter()
+                .map(|(name, size)| (name.as_str(), *size)),
+            |name| self.kept.get(name).copied(),
+        );
+    }
+}
+
+// Merges the copies zip to zip: entries are read straight from the source vars and the winners
+// written into the merged one, nothing is extracted to disk
+pub fn merge(
+    sources: &LinkedList<PathBuf>,
+    target: &Path,
+    log_folder: &Path,
+    args: &CleanArgs,
+    builtin: Option<&crate::builtin::BuiltIn>,
+) -> anyhow::Result<Option<Merged>> {
+    #[cfg(test)]
+    crate::diskspace::simulate_full(&index::package_name(target))?;
+    let mut candidates = BTreeMap::new();
+    let winners = select_winners(sources, target, args, builtin, &mut candidates)?;
+    if winners.is_empty() {
+        return Ok(None);
+    }
+    if args.explain {
+        let log = log_folder.join(format!("{}.log", index::package_name(target)));
+        let kept = winners
+            .iter()
+            .map(|(name, (copy, _))| (name.clone(), *copy))
+            .collect();
+        if let Err(e) = crate::write_explanation(&log, sources, &candidates, &kept) {
+            warning!("Failed to write {}: {e}", log.to_string_lossy());
+        }
+    }
+
+    let partial = crate::archive::partial_path(target);
+    let verified = write_winners(sources, &winners, &partial, args)
+        .and_then(|_| validate::smoke_check(&partial));
+    match verified {
+        Ok(problems) => {
+            let package = target.file_name().unwrap().to_string_lossy();
+            validate::report_problems(&package, &problems);
+            Ok(Some(Merged {
+                partial,
+                copies: candidates
+                    .iter()
+                    .map(|(name, copies)| (name.clone(), copies.iter().map(|(_, size)| size).sum()))
+                    .collect(),
+                kept: winners
+                    .into_iter()
+                    .map(|(name, (_, size))| (name, size))
+                    .collect(),
+            }))
+        }
+        Err(e) => {
+            let _ = fs::remove_file(&partial);
+            Err(e)
+        }
+    }
+}
+
+// The stored size of the entries a merge of `sources` would keep, roughly the size of the
+// merged var. Picked like `merge` picks them, but from the central directories alone: nothing
+// is decompressed, so corrupt entries and entries VaM already ships are still counted.
+pub fn plan(sources: &LinkedList<PathBuf>, package: &str, args: &CleanArgs) -> anyhow::Result<u64> {
+    let mut candidates: BTreeMap<String, Vec<(usize, u64)>> = BTreeMap::new();
+    let mut winners: HashMap<String, (usize, u64)> = HashMap::new();
+    let mut stored: HashMap<(usize, String), u64> = HashMap::new();
+    let mut stamps: HashMap<(usize, String), crate::EntryStamp> = HashMap::new();
+    for (copy, path) in sources.iter().enumerate() {
+        let Ok(mut archive) = index::open_var(path) else {
+            continue;
+        };
+        for i in 0..archive.len() {
+            let file = archive.by_index_raw(i)?;
+            if file.is_dir()
+                || file.enclosed_name().is_none()
+                || crate::nested::is_nested(file.name())
+            {
+                continue;
+            }
+            let name = file.name().to_string();
+            let size = file.size();
+            stored.insert((copy, name.clone()), file.compressed_size());
+            let stamp = (file.last_modified(), file.crc32());
+            candidates
+                .entry(name.clone())
+                .or_default()
+                .push((copy, size));
+            stamps.insert((copy, name.clone()), stamp);
+            match winners.get(&name) {
+                Some(&kept) if !crate::prefer_copy(&stamps, &name, kept, copy, size) => {}
+                _ => {
+                    winners.insert(name, (copy, size));
+                }
+            }
+        }
+    }
+    keep_merged(package, &mut winners, args);
+    pair_parts(&candidates, &mut winners);
+    Ok(winners
+        .into_iter()
+        .map(|(name, (copy, _))| stored[&(copy, name)])
+        .sum())
+}
+
+// What a merge of `sources` would keep, without writing anything
+// `canonical_json` compares JSON entries by content, so only real differences count as conflicts
+pub fn preview(sources: &LinkedList<PathBuf>, canonical_json: bool) -> anyhow::Result<()> {
+    for (copy, path) in sources.iter().enumerate() {
+        info!("copy {copy}: {}", path.to_string_lossy());
+    }
+    let mut candidates = BTreeMap::new();
+    let target = sources.front().unwrap();
+    let winners = select_winners(
+        sources,
+        target,
+        &CleanArgs::default(),
+        None,
+        &mut candidates,
+    )?;
+    let hashes: Vec<HashMap<String, blake3::Hash>> = sources
+        .iter()
+        .map(|path| {
+            VarReader::open(path)
+                .and_then(|mut var| match canonical_json {
+                    true => var.hash_entries_canonical(),
+                    false => var.hash_entries(),
+                })
+                .map(|hashes| hashes.into_iter().collect())
+                .unwrap_or_default()
+        })
+        .collect();
+    let mut conflicts = 0;
+    for (name, copies) in candidates.iter() {
+        let Some(&(kept, size)) = winners.get(name) else {
+            info!("{name}: left out of the merged var");
+            continue;
+        };
+        // Copies with different content, only the winner's survives
+        let conflict = copies
+            .iter()
+            .any(|(copy, _)| hashes[*copy].get(name) != hashes[kept].get(name));
+        if conflict {
+            conflicts += 1;
+            warning!(
+                "{name}: copy {kept} ({}) wins a conflict",
+                crate::format_size(size)
+            );
+            for (copy, size) in copies.iter().filter(|(copy, _)| *copy != kept) {
+                info!("  lost copy {copy} {}", crate::format_size(*size));
+            }
+        } else {
+            info!("{name}: copy {kept} of {}", copies.len());
+        }
+    }
+    let merged: u64 = winners.values().map(|(_, size)| size).sum();
+    let total: u64 = candidates.values().flatten().map(|(_, size)| size).sum();
+    summary!(
+        "{} entries, {conflicts} conflicts, merged var holds {} of {} / 共{}个条目，{conflicts}处冲突，合并后{}",
+        winners.len(),
+        crate::format_size(merged),
+        crate::format_size(total),
+        winners.len(),
+        crate::format_size(merged)
+    );
+    Ok(())
+}
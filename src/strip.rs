@@ -0,0 +1,44 @@
+use crate::content::is_text;
+use crate::previews::is_content_thumbnail;
+use crate::textures::is_image;
+use std::collections::HashMap;
+use std::collections::HashSet;
+
+// Images that are neither the primary package thumbnail, a content thumbnail,
+// a texture under Custom/ nor referenced by any scene/preset file.
+// `files` is keyed by slash separated entry names, `read` returns the bytes of one.
+pub fn promo_images<T>(
+    files: &HashMap<String, T>,
+    mut read: impl FnMut(&str) -> anyhow::Result<Vec<u8>>,
+) -> Vec<String> {
+    let names: HashSet<&str> = files.keys().map(|k| k.as_str()).collect();
+    let mut root_images: Vec<&str> = names
+        .iter()
+        .copied()
+        .filter(|n| !n.contains('/') && is_image(n))
+        .collect();
+    root_images.sort();
+    let primary = root_images.first().copied();
+
+    let mut candidates: Vec<&str> = names
+        .iter()
+        .copied()
+        .filter(|n| is_image(n) && Some(*n) != primary)
+        .filter(|n| !n.starts_with("Custom/") && !is_content_thumbnail(n, &names))
+        .collect();
+    if candidates.is_empty() {
+        return Vec::new();
+    }
+
+    for name in files.keys() {
+        if !is_text(name) {
+            continue;
+        }
+        let text = match read(name) {
+            Ok(bytes) => String::from_utf8_lossy(&bytes).to_string(),
+            Err(_) => continue,
+        };
+        candidates.retain(|c| !text.contains(c.rsplit('/').next().unwrap()));
+    }
+    candidates.iter().map(|c| c.to_string()).collect()
+}
@@ -0,0 +1,270 @@
+// Synthetic VaM libraries for end-to-end tests: a VaM folder in the temp dir with vars
+// built entry by entry, removed again on drop
+// Audit builds only run the tests that leave the library alone
+#![cfg_attr(any(not(test), feature = "audit"), allow(dead_code))]
+
+use serde_json::json;
+use std::fs;
+use std::fs::File;
+use std::io::Read;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+use std::time::SystemTime;
+use walkdir::WalkDir;
+use zip::write::SimpleFileOptions;
+use zip::ZipWriter;
+
+static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+pub struct Library {
+    pub root: PathBuf,
+}
+
+// meta.json as VaM writes it, with `dependencies` naming other packages
+pub fn meta(package: &str, dependencies: &[&str]) -> Vec<u8> {
+    let mut parts = package.splitn(3, '.');
+    let creator = parts.next().unwrap_or_default();
+    let name = parts.next().unwrap_or_default();
+    let dependencies: serde_json::Map<String, serde_json::Value> = dependencies
+        .iter()
+        .map(|dep| {
+            (
+                dep.to_string(),
+                json!({ "licenseType": "FC", "dependencies": {} }),
+            )
+        })
+        .collect();
+    serde_json::to_vec_pretty(&json!({
+        "licenseType": "FC",
+        "creatorName": creator,
+        "packageName": name,
+        "contentList": [],
+        "dependencies": dependencies,
+    }))
+    .unwrap()
+}
+
+impl Library {
+    pub fn new(name: &str) -> Library {
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        let root = std::env::temp_dir().join(format!(
+            "varcleaner-test-{}-{id}-{name}",
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("AddonPackages")).unwrap();
+        fs::write(root.join("VaM.exe"), b"").unwrap();
+        Library { root }
+    }
+
+    pub fn var_folder(&self) -> PathBuf {
+        self.root.join("AddonPackages")
+    }
+
+    // A var at `path` relative to AddonPackages holding `entries`, meta.json included
+    pub fn add_var(&self, path: &str, entries: &[(&str, &[u8])]) -> PathBuf {
+        self.add_var_at(path, entries, zip::DateTime::default())
+    }
+
+    // Like add_var with every entry dated `modified`; add_var dates them all 1980-01-01 so
+    // tests never depend on the clock
+    pub fn add_var_at(
+        &self,
+        path: &str,
+        entries: &[(&str, &[u8])],
+        modified: zip::DateTime,
+    ) -> PathBuf {
+        let path = self.var_folder().join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        let mut zip = ZipWriter::new(File::create(&path).unwrap());
+        let options = SimpleFileOptions::default()
+            .compression_method(zip::CompressionMethod::Stored)
+            .last_modified_time(modified);
+        for (name, data) in entries {
+            zip.start_file(*name, options).unwrap();
+            zip.write_all(data).unwrap();
+        }
+        zip.finish().unwrap();
+        settle(&path);
+        path
+    }
+
+    // A copy of `from` at `path` relative to AddonPackages; fs::copy only keeps the modified
+    // time on Windows, so it is settled like every other fixture
+    pub fn copy_var(&self, from: &Path, path: &str) -> PathBuf {
+        let path = self.var_folder().join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::copy(from, &path).unwrap();
+        settle(&path);
+        path
+    }
+
+    // Like add_var, but written the way streaming zip writers do: local headers carry no
+    // CRC or sizes, a data descriptor follows each entry and only the central directory has them
+    pub fn add_streamed_var(&self, path: &str, entries: &[(&str, &[u8])]) -> PathBuf {
+        let mut bytes = Vec::new();
+        let mut central = Vec::new();
+        for (name, data) in entries {
+            let offset = bytes.len() as u32;
+            let crc = crc32(data);
+            let size = data.len() as u32;
+            // Version 2.0, flag 3 (data descriptor), stored, 1980-01-01
+            let common = |out: &mut Vec<u8>, crc: u32, size: u32| {
+                for field in [20u16, 8, 0, 0, 0x21] {
+                    out.extend(field.to_le_bytes());
+                }
+                for field in [crc, size, size] {
+                    out.extend(field.to_le_bytes());
+                }
+                out.extend((name.len() as u16).to_le_bytes());
+                out.extend(0u16.to_le_bytes());
+            };
+            bytes.extend(0x04034b50u32.to_le_bytes());
+            common(&mut bytes, 0, 0);
+            bytes.extend(name.as_bytes());
+            bytes.extend(*data);
+            for field in [0x08074b50u32, crc, size, size] {
+                bytes.extend(field.to_le_bytes());
+            }
+            central.extend(0x02014b50u32.to_le_bytes());
+            central.extend(20u16.to_le_bytes());
+            common(&mut central, crc, size);
+            // Comment length, disk, internal and external attributes
+            central.extend([0u8; 10]);
+            central.extend(offset.to_le_bytes());
+            central.extend(name.as_bytes());
+        }
+        let central_offset = bytes.len() as u32;
+        bytes.extend(&central);
+        bytes.extend(0x06054b50u32.to_le_bytes());
+        bytes.extend([0u8; 4]);
+        for _ in 0..2 {
+            bytes.extend((entries.len() as u16).to_le_bytes());
+        }
+        bytes.extend((central.len() as u32).to_le_bytes());
+        bytes.extend(central_offset.to_le_bytes());
+        bytes.extend(0u16.to_le_bytes());
+        self.add_raw(path, &bytes)
+    }
+
+    // A package with meta.json and one content entry per `(name, data)`
+    pub fn add_package(
+        &self,
+        path: &str,
+        dependencies: &[&str],
+        content: &[(&str, &[u8])],
+    ) -> PathBuf {
+        let package = Path::new(path)
+            .file_stem()
+            .unwrap()
+            .to_string_lossy()
+            .to_string();
+        let meta = meta(&package, dependencies);
+        let mut entries: Vec<(&str, &[u8])> = vec![("meta.json", &meta)];
+        entries.extend_from_slice(content);
+        self.add_var(path, &entries)
+    }
+
+    // Arbitrary bytes under a var name, e.g. a truncated download
+    pub fn add_raw(&self, path: &str, bytes: &[u8]) -> PathBuf {
+        let path = self.var_folder().join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, bytes).unwrap();
+        settle(&path);
+        path
+    }
+
+    // `Creator.Pkg.1` to `Creator.Pkg.count`, each adding one entry to the previous version
+    pub fn add_version_chain(&self, base: &str, count: u64) -> Vec<PathBuf> {
+        let mut content: Vec<(String, Vec<u8>)> = Vec::new();
+        let mut result = Vec::new();
+        for version in 1..=count {
+            content.push((
+                format!("Custom/Assets/{base}/v{version}.assetbundle"),
+                format!("{base} version {version} ").repeat(64).into_bytes(),
+            ));
+            let entries: Vec<(&str, &[u8])> = content
+                .iter()
+                .map(|(name, data)| (name.as_str(), data.as_slice()))
+                .collect();
+            result.push(self.add_package(&format!("{base}.{version}.var"), &[], &entries));
+        }
+        result
+    }
+
+    // Every file below the VaM folder as a slash path, leaving out caches and the timestamped
+    // journals and reports whose names change from run to run
+    pub fn tree(&self) -> Vec<String> {
+        let mut result: Vec<String> = WalkDir::new(&self.root)
+            .into_iter()
+            .filter_map(|e| e.ok())
+            .filter(|e| e.file_type().is_file())
+            .map(|e| {
+                e.path()
+                    .strip_prefix(&self.root)
+                    .unwrap()
+                    .to_string_lossy()
+                    .replace('\\', "/")
+            })
+            .filter(|p| {
+                !p.starts_with("VarCleaner/Cache/")
+                    && !p.starts_with("VarCleaner/Journal/")
+                    && !p.starts_with("VarCleaner/reports/")
+            })
+            .collect();
+        result.sort();
+        result
+    }
+
+    // Entry names and contents of the var at `path` relative to the VaM folder, in name order
+    pub fn entries(&self, path: &str) -> Vec<(String, Vec<u8>)> {
+        let mut archive = crate::index::open_var(&self.root.join(path)).unwrap();
+        let mut result = Vec::new();
+        for i in 0..archive.len() {
+            let mut file = archive.by_index(i).unwrap();
+            if file.is_dir() {
+                continue;
+            }
+            let mut data = Vec::new();
+            file.read_to_end(&mut data).unwrap();
+            result.push((file.name().to_string(), data));
+        }
+        result.sort();
+        result
+    }
+
+    pub fn entry(&self, path: &str, name: &str) -> Option<Vec<u8>> {
+        self.entries(path)
+            .into_iter()
+            .find(|(entry, _)| entry == name)
+            .map(|(_, data)| data)
+    }
+}
+
+// An hour old, so clean does not defer it as a var still being downloaded
+pub fn settle(path: &Path) {
+    let file = File::options().write(true).open(path).unwrap();
+    file.set_modified(SystemTime::now() - Duration::from_secs(3600))
+        .unwrap();
+}
+
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xedb88320 & (crc & 1).wrapping_neg());
+        }
+    }
+    !crc
+}
+
+impl Drop for Library {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.root);
+    }
+}
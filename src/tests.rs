@@ -0,0 +1,1420 @@
+use crate::testkit::Library;
+use crate::CleanArgs;
+use std::collections::HashMap;
+use std::fs;
+
+fn clean(lib: &Library) {
+    let protected = crate::protect::load(&lib.root);
+    crate::clean(&lib.root, &CleanArgs::default(), &protected).unwrap();
+}
+
+#[test]
+#[cfg(not(feature = "audit"))]
+fn clean_merges_duplicates_and_backs_up_originals() {
+    let lib = Library::new("merge");
+    lib.add_package("A.Pkg.1.var", &[], &[("Custom/a.txt", b"live")]);
+    lib.add_package(
+        "sub/A.Pkg.1.var",
+        &[],
+        &[("Custom/a.txt", b"other, larger"), ("Custom/b.txt", b"b")],
+    );
+    clean(&lib);
+
+    assert_eq!(
+        lib.tree(),
+        [
+            "AddonPackages/merged/A.Pkg.1.var",
+            "VaM.exe",
+            "VarCleaner/Backup/A.Pkg.1.var",
+            "VarCleaner/Backup/sub/A.Pkg.1.var",
+        ]
+    );
+    let merged = "AddonPackages/merged/A.Pkg.1.var";
+    // The copy VaM loads wins, entries only the other copy has are added
+    assert_eq!(lib.entry(merged, "Custom/a.txt").unwrap(), b"live");
+    assert_eq!(lib.entry(merged, "Custom/b.txt").unwrap(), b"b");
+    assert!(lib.entry(merged, "meta.json").is_some());
+}
+
+#[test]
+#[cfg(not(feature = "audit"))]
+fn vars_under_bracketed_folder_names_are_found_and_merged() {
+    let lib = Library::new("brackets");
+    lib.add_package("[old]{x}/E.Pkg.1.var", &[], &[("Custom/a.txt", b"live")]);
+    lib.add_package(
+        "[old]{x}/[1-2]/E.Pkg.1.var",
+        &[],
+        &[("Custom/a.txt", b"other, larger"), ("Custom/b.txt", b"b")],
+    );
+    clean(&lib);
+
+    assert_eq!(
+        lib.tree(),
+        [
+            "AddonPackages/merged/E.Pkg.1.var",
+            "VaM.exe",
+            "VarCleaner/Backup/[old]{x}/E.Pkg.1.var",
+            "VarCleaner/Backup/[old]{x}/[1-2]/E.Pkg.1.var",
+        ]
+    );
+    let merged = "AddonPackages/merged/E.Pkg.1.var";
+    assert_eq!(lib.entry(merged, "Custom/a.txt").unwrap(), b"live");
+    assert_eq!(lib.entry(merged, "Custom/b.txt").unwrap(), b"b");
+}
+
+#[test]
+#[cfg(not(feature = "audit"))]
+fn clean_keeps_live_copy_of_identical_duplicates() {
+    let lib = Library::new("identical");
+    let live = lib.add_package("C.Pkg.1.var", &[], &[("Custom/c.txt", b"c")]);
+    lib.copy_var(&live, "sub/C.Pkg.1.var");
+    clean(&lib);
+
+    assert_eq!(
+        lib.tree(),
+        [
+            "AddonPackages/C.Pkg.1.var",
+            "VaM.exe",
+            "VarCleaner/Backup/sub/C.Pkg.1.var",
+        ]
+    );
+}
+
+#[test]
+#[cfg(not(feature = "audit"))]
+fn a_second_backup_of_the_same_path_keeps_the_first() {
+    let lib = Library::new("rebackup");
+    let live = lib.add_package("D.Pkg.1.var", &[], &[("Custom/d.txt", b"d")]);
+    lib.copy_var(&live, "sub/D.Pkg.1.var");
+    clean(&lib);
+    lib.copy_var(&live, "sub/D.Pkg.1.var");
+    clean(&lib);
+
+    assert_eq!(
+        lib.tree(),
+        [
+            "AddonPackages/D.Pkg.1.var",
+            "VaM.exe",
+            "VarCleaner/Backup/sub/D.Pkg.1 (1).var",
+            "VarCleaner/Backup/sub/D.Pkg.1.var",
+        ]
+    );
+}
+
+#[test]
+#[cfg(not(feature = "audit"))]
+fn clean_quarantines_empty_and_corrupt_vars() {
+    let lib = Library::new("quarantine");
+    lib.add_raw("Empty.Pkg.1.var", b"");
+    lib.add_raw("Broken.Pkg.1.var", b"<html>404</html>");
+    lib.add_package("Good.Pkg.1.var", &[], &[("Custom/g.txt", b"g")]);
+    clean(&lib);
+
+    assert_eq!(
+        lib.tree(),
+        [
+            "AddonPackages/Good.Pkg.1.var",
+            "VaM.exe",
+            "VarCleaner/Quarantine/Broken.Pkg.1.var",
+            "VarCleaner/Quarantine/Empty.Pkg.1.var",
+        ]
+    );
+}
+
+#[test]
+fn clean_leaves_protected_packages_alone() {
+    let lib = Library::new("protected");
+    lib.add_package("MeshedVR.Tutorial.1.var", &[], &[("Custom/m.txt", b"m")]);
+    lib.add_package(
+        "sub/MeshedVR.Tutorial.1.var",
+        &[],
+        &[("Custom/n.txt", b"n")],
+    );
+    clean(&lib);
+
+    assert_eq!(
+        lib.tree(),
+        [
+            "AddonPackages/MeshedVR.Tutorial.1.var",
+            "AddonPackages/sub/MeshedVR.Tutorial.1.var",
+            "VaM.exe",
+        ]
+    );
+}
+
+#[test]
+#[cfg(not(feature = "audit"))]
+fn archived_versions_restore_byte_for_byte() {
+    let lib = Library::new("versions");
+    lib.add_version_chain("D.Pkg", 3);
+    let original = lib.entries("AddonPackages/D.Pkg.1.var");
+    let protected = crate::protect::load(&lib.root);
+    crate::delta::archive_superseded(&lib.root, &protected).unwrap();
+
+    assert_eq!(
+        lib.tree(),
+        [
+            "AddonPackages/D.Pkg.3.var",
+            "VaM.exe",
+            "VarCleaner/Archive/D.Pkg.1.delta.var",
+            "VarCleaner/Archive/D.Pkg.2.delta.var",
+        ]
+    );
+
+    crate::delta::restore(&lib.root, "D.Pkg.1", true).unwrap();
+    assert_eq!(lib.entries("AddonPackages/D.Pkg.1.var"), original);
+    assert!(!lib
+        .root
+        .join("VarCleaner/Archive/D.Pkg.1.delta.var")
+        .exists());
+}
+
+#[test]
+fn dependency_references_resolve_against_installed_versions() {
+    let lib = Library::new("dependencies");
+    let scene = lib.add_package("E.Scene.1.var", &["F.Asset.latest", "G.Look.min2"], &[]);
+    lib.add_package("F.Asset.1.var", &[], &[]);
+    lib.add_package("F.Asset.2.var", &[], &[]);
+    lib.add_package("G.Look.1.var", &[], &[]);
+    let installed = crate::index::installed_packages(&lib.var_folder());
+
+    let meta = crate::reader::VarReader::open(&scene)
+        .unwrap()
+        .meta()
+        .cloned()
+        .unwrap();
+    let resolved: HashMap<String, Option<String>> = crate::index::dependencies(&meta)
+        .iter()
+        .map(|dep| {
+            let found = crate::index::resolve_dependency(dep, &installed);
+            (dep.to_string(), found.map(|(package, _)| package.clone()))
+        })
+        .collect();
+    assert_eq!(resolved["F.Asset.latest"], Some("F.Asset.2".to_string()));
+    assert_eq!(resolved["G.Look.min2"], None);
+}
+
+#[test]
+#[cfg(not(feature = "audit"))]
+fn only_content_leaves_other_categories_to_the_live_copy() {
+    let lib = Library::new("only-content");
+    lib.add_package(
+        "H.Pkg.1.var",
+        &[],
+        &[("Saves/scene/live.json", b"{\"live\":1}")],
+    );
+    lib.add_package(
+        "sub/H.Pkg.1.var",
+        &[],
+        &[
+            ("Saves/scene/live.json", b"{\"other\":2, \"larger\":true}"),
+            ("Saves/scene/extra.json", b"{}"),
+            ("Custom/Atom/Person/Textures/skin.png", b"png"),
+        ],
+    );
+    let args = CleanArgs {
+        only_content: vec!["textures".to_string()],
+        ..CleanArgs::default()
+    };
+    let protected = crate::protect::load(&lib.root);
+    crate::clean(&lib.root, &args, &protected).unwrap();
+
+    let merged = "AddonPackages/merged/H.Pkg.1.var";
+    let names: Vec<String> = lib.entries(merged).into_iter().map(|(n, _)| n).collect();
+    assert_eq!(
+        names,
+        [
+            "Custom/Atom/Person/Textures/skin.png",
+            "Saves/scene/live.json",
+            "meta.json",
+        ]
+    );
+    assert_eq!(
+        lib.entry(merged, "Saves/scene/live.json").unwrap(),
+        b"{\"live\":1}"
+    );
+}
+
+#[test]
+#[cfg(not(feature = "audit"))]
+fn clean_moves_sidecars_to_the_merged_var_or_the_backup() {
+    let lib = Library::new("sidecars");
+    lib.add_package("J.Pkg.1.var", &["K.Dep.1"], &[("Custom/j.txt", b"j")]);
+    lib.add_package("sub/J.Pkg.1.var", &[], &[("Custom/k.txt", b"k")]);
+    lib.add_raw("J.Pkg.1.var.hide", b"");
+    lib.add_raw("J.Pkg.1.var.depend.txt", b"stale\n");
+    lib.add_raw("Gone.Pkg.1.var.fav", b"");
+    clean(&lib);
+
+    assert_eq!(
+        lib.tree(),
+        [
+            "AddonPackages/merged/J.Pkg.1.var",
+            "AddonPackages/merged/J.Pkg.1.var.depend.txt",
+            "AddonPackages/merged/J.Pkg.1.var.hide",
+            "VaM.exe",
+            "VarCleaner/Backup/Gone.Pkg.1.var.fav",
+            "VarCleaner/Backup/J.Pkg.1.var",
+            "VarCleaner/Backup/J.Pkg.1.var.depend.txt",
+            "VarCleaner/Backup/sub/J.Pkg.1.var",
+        ]
+    );
+    let depend = fs::read(lib.var_folder().join("merged/J.Pkg.1.var.depend.txt")).unwrap();
+    assert_eq!(depend, b"K.Dep.1\n");
+}
+
+#[test]
+#[cfg(not(feature = "audit"))]
+fn disabled_copies_can_be_left_out_and_disable_the_merge() {
+    let lib = Library::new("disabled");
+    lib.add_package("L.Pkg.1.var", &[], &[("Custom/old.txt", b"old")]);
+    lib.add_raw("L.Pkg.1.var.disabled", b"");
+    lib.add_package("sub/L.Pkg.1.var", &[], &[("Custom/new.txt", b"new")]);
+    let args = CleanArgs {
+        disabled_copies: crate::DisabledCopies::Exclude,
+        merged_state: crate::MergedState::Live,
+        ..CleanArgs::default()
+    };
+    let protected = crate::protect::load(&lib.root);
+    crate::clean(&lib.root, &args, &protected).unwrap();
+
+    assert_eq!(
+        lib.tree(),
+        [
+            "AddonPackages/merged/L.Pkg.1.var",
+            "AddonPackages/merged/L.Pkg.1.var.disabled",
+            "VaM.exe",
+            "VarCleaner/Backup/L.Pkg.1.var",
+            "VarCleaner/Backup/L.Pkg.1.var.disabled",
+            "VarCleaner/Backup/sub/L.Pkg.1.var",
+        ]
+    );
+    let merged = "AddonPackages/merged/L.Pkg.1.var";
+    assert!(lib.entry(merged, "Custom/old.txt").is_none());
+    assert_eq!(lib.entry(merged, "Custom/new.txt").unwrap(), b"new");
+}
+
+#[test]
+#[cfg(not(feature = "audit"))]
+fn strip_builtin_leaves_out_unreferenced_game_files() {
+    let lib = Library::new("builtin");
+    let textures = lib.root.join("Custom/Atom/Person/Textures");
+    fs::create_dir_all(&textures).unwrap();
+    fs::write(textures.join("skin.jpg"), b"game skin").unwrap();
+    fs::write(textures.join("face.jpg"), b"game face").unwrap();
+    let content: &[(&str, &[u8])] = &[
+        ("Custom/Atom/Person/Textures/skin.jpg", b"game skin"),
+        ("Custom/Atom/Person/Textures/face.jpg", b"game face"),
+        (
+            "Saves/scene/s.json",
+            b"{\"url\":\"SELF:/Custom/Atom/Person/Textures/face.jpg\"}",
+        ),
+    ];
+    lib.add_package("M.Pkg.1.var", &[], content);
+    lib.add_package("sub/M.Pkg.1.var", &[], &[("Custom/m.txt", b"m")]);
+    let args = CleanArgs {
+        strip_builtin: true,
+        ..CleanArgs::default()
+    };
+    let protected = crate::protect::load(&lib.root);
+    crate::clean(&lib.root, &args, &protected).unwrap();
+
+    let merged = "AddonPackages/merged/M.Pkg.1.var";
+    assert!(lib
+        .entry(merged, "Custom/Atom/Person/Textures/skin.jpg")
+        .is_none());
+    assert!(lib
+        .entry(merged, "Custom/Atom/Person/Textures/face.jpg")
+        .is_some());
+    assert!(lib.entry(merged, "Custom/m.txt").is_some());
+}
+
+#[test]
+#[cfg(not(feature = "audit"))]
+fn clean_writes_a_line_per_package_to_the_run_report() {
+    let lib = Library::new("run-report");
+    lib.add_package("N.Pkg.1.var", &[], &[("Custom/n.txt", b"n")]);
+    lib.add_package("sub/N.Pkg.1.var", &[], &[("Custom/o.txt", b"o")]);
+    clean(&lib);
+
+    let reports: Vec<_> = fs::read_dir(lib.root.join("VarCleaner/reports"))
+        .unwrap()
+        .map(|e| e.unwrap().path())
+        .collect();
+    assert_eq!(reports.len(), 1);
+    let text = fs::read_to_string(&reports[0]).unwrap();
+    let lines: Vec<serde_json::Value> = text
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(lines.len(), 1);
+    assert_eq!(lines[0]["package"], "N.Pkg.1.var");
+    assert_eq!(lines[0]["outcome"], "merged");
+}
+
+#[test]
+#[cfg(not(feature = "audit"))]
+fn thumbnails_come_from_the_copy_their_content_file_comes_from() {
+    let lib = Library::new("thumbnails");
+    let preset = "Custom/Atom/Person/Appearance/look.vap";
+    let thumbnail = "Custom/Atom/Person/Appearance/look.jpg";
+    lib.add_package("P.Pkg.1.var", &[], &[("Custom/p.txt", b"p")]);
+    lib.add_package(
+        "a/P.Pkg.1.var",
+        &[],
+        &[(preset, b"{\"larger preset\":1}"), (thumbnail, b"a")],
+    );
+    lib.add_package(
+        "b/P.Pkg.1.var",
+        &[],
+        &[(preset, b"{}"), (thumbnail, b"larger thumbnail")],
+    );
+    clean(&lib);
+
+    let merged = "AddonPackages/merged/P.Pkg.1.var";
+    assert_eq!(lib.entry(merged, preset).unwrap(), b"{\"larger preset\":1}");
+    assert_eq!(lib.entry(merged, thumbnail).unwrap(), b"a");
+}
+
+#[test]
+#[cfg(not(feature = "audit"))]
+fn clean_stops_at_the_change_limit() {
+    let lib = Library::new("limit");
+    for package in ["Q.One.1.var", "Q.Two.1.var"] {
+        lib.add_package(package, &[], &[("Custom/q.txt", b"q")]);
+        lib.add_package(&format!("sub/{package}"), &[], &[("Custom/r.txt", b"r")]);
+    }
+    let args = CleanArgs {
+        max_changes: Some(1),
+        ..CleanArgs::default()
+    };
+    let protected = crate::protect::load(&lib.root);
+    crate::clean(&lib.root, &args, &protected).unwrap();
+
+    let merged = lib
+        .tree()
+        .iter()
+        .filter(|p| p.starts_with("AddonPackages/merged/"))
+        .count();
+    let untouched = lib
+        .tree()
+        .iter()
+        .filter(|p| p.starts_with("AddonPackages/sub/"))
+        .count();
+    assert_eq!((merged, untouched), (1, 1));
+}
+
+#[test]
+fn remembered_decisions_apply_on_later_runs() {
+    let lib = Library::new("decisions");
+    lib.add_package("D.Pkg.1.var", &[], &[("Custom/a.txt", b"a")]);
+    lib.add_package("sub/D.Pkg.1.var", &[], &[("Custom/b.txt", b"b")]);
+    let hashes: Vec<String> = ["AddonPackages/D.Pkg.1.var", "AddonPackages/sub/D.Pkg.1.var"]
+        .iter()
+        .map(|p| crate::hashing::hash_file(&lib.root.join(p)).unwrap())
+        .collect();
+    let decisions = crate::decisions::Decisions::load(&lib.root);
+    decisions.remember(
+        crate::decisions::key("D.Pkg.1.var", &hashes),
+        crate::decisions::Decision::Keep,
+    );
+    let packages = || -> Vec<String> {
+        lib.tree()
+            .into_iter()
+            .filter(|p| p.starts_with("AddonPackages/"))
+            .collect()
+    };
+    let before = packages();
+    clean(&lib);
+
+    assert_eq!(packages(), before);
+}
+
+#[test]
+#[cfg(not(feature = "audit"))]
+fn status_file_reports_the_finished_run() {
+    let lib = Library::new("status");
+    lib.add_package("S.Pkg.1.var", &[], &[("Custom/a.txt", b"a")]);
+    lib.add_package("sub/S.Pkg.1.var", &[], &[("Custom/b.txt", b"b")]);
+    let file = lib.root.join("status.json");
+    let args = CleanArgs {
+        status_file: Some(file.clone()),
+        ..CleanArgs::default()
+    };
+    let protected = crate::protect::load(&lib.root);
+    crate::clean(&lib.root, &args, &protected).unwrap();
+
+    let status: serde_json::Value = serde_json::from_slice(&fs::read(file).unwrap()).unwrap();
+    // Tests clean in parallel and progress is process wide, so counts are not checked
+    assert_eq!(status["state"], "done");
+    assert!(status["total"].is_u64());
+}
+
+#[test]
+#[cfg(not(feature = "audit"))]
+fn streamed_zips_merge_by_their_central_directory_sizes() {
+    let preset = "Custom/Atom/Person/Pose/p.vap";
+    let lib = Library::new("streamed");
+    // The live copy has no preset, so the larger of the other two wins
+    lib.add_package("Z.Pkg.1.var", &[], &[]);
+    lib.add_package("a/Z.Pkg.1.var", &[], &[(preset, b"{}")]);
+    let meta = crate::testkit::meta("Z.Pkg.1", &[]);
+    lib.add_streamed_var(
+        "sub/Z.Pkg.1.var",
+        &[("meta.json", &meta), (preset, b"{\"larger\":1}")],
+    );
+    let args = CleanArgs::default();
+    let protected = crate::protect::load(&lib.root);
+    crate::clean(&lib.root, &args, &protected).unwrap();
+
+    let merged = "AddonPackages/merged/Z.Pkg.1.var";
+    assert_eq!(lib.entry(merged, preset).unwrap(), b"{\"larger\":1}");
+}
+
+#[test]
+#[cfg(not(feature = "audit"))]
+fn promo_images_are_stripped_while_merging_zip_to_zip() {
+    let lib = Library::new("promo");
+    let content: &[(&str, &[u8])] = &[
+        ("P.Pkg.jpg", b"thumbnail"),
+        ("Saves/scene/s.json", b"{\"texture\":\"used.jpg\"}"),
+        ("Saves/scene/used.jpg", b"used"),
+        ("Saves/scene/promo.jpg", b"promo"),
+    ];
+    lib.add_package("P.Pkg.1.var", &[], content);
+    let mut larger = content.to_vec();
+    larger.push(("Custom/x.txt", b"only in this copy"));
+    lib.add_package("sub/P.Pkg.1.var", &[], &larger);
+    let args = CleanArgs {
+        strip_promo_images: true,
+        ..CleanArgs::default()
+    };
+    let protected = crate::protect::load(&lib.root);
+    crate::clean(&lib.root, &args, &protected).unwrap();
+
+    let merged = "AddonPackages/merged/P.Pkg.1.var";
+    assert_eq!(
+        lib.entry(merged, "Custom/x.txt").unwrap(),
+        b"only in this copy"
+    );
+    assert!(lib.entry(merged, "Saves/scene/used.jpg").is_some());
+    assert!(lib.entry(merged, "P.Pkg.jpg").is_some());
+    assert!(lib.entry(merged, "Saves/scene/promo.jpg").is_none());
+    assert!(!lib.root.join("VarCleaner/Tmp").exists());
+}
+
+#[test]
+#[cfg(not(feature = "audit"))]
+fn deflate_merges_compress_entries_on_the_pool() {
+    let lib = Library::new("deflate");
+    let entries: Vec<(String, Vec<u8>)> = (0..8)
+        .map(|i| (format!("Custom/{i}.txt"), vec![b'a' + i as u8; 4096]))
+        .collect();
+    let content: Vec<(&str, &[u8])> = entries
+        .iter()
+        .map(|(name, data)| (name.as_str(), data.as_slice()))
+        .collect();
+    lib.add_package("D.Deflate.1.var", &[], &content[..4]);
+    lib.add_package("sub/D.Deflate.1.var", &[], &content);
+    let before = crate::builder::COMPRESSED_IN_POOL.load(std::sync::atomic::Ordering::Relaxed);
+    let args = CleanArgs {
+        compression: crate::Compression::Deflate,
+        ..CleanArgs::default()
+    };
+    let protected = crate::protect::load(&lib.root);
+    crate::clean(&lib.root, &args, &protected).unwrap();
+
+    let pooled = crate::builder::COMPRESSED_IN_POOL.load(std::sync::atomic::Ordering::Relaxed);
+    assert!(
+        pooled >= before + entries.len(),
+        "entries were compressed serially"
+    );
+    let merged = "AddonPackages/merged/D.Deflate.1.var";
+    for (name, data) in entries.iter() {
+        assert_eq!(&lib.entry(merged, name).unwrap(), data);
+    }
+}
+
+#[test]
+#[cfg(not(feature = "audit"))]
+fn headless_merges_give_up_on_a_full_disk() {
+    let lib = Library::new("diskfull");
+    lib.add_package("F.Full.1.var", &[], &[("Custom/a.txt", b"a")]);
+    lib.add_package("sub/F.Full.1.var", &[], &[("Custom/b.txt", b"b")]);
+    let before = lib.tree();
+    crate::diskspace::FULL_FOR
+        .lock()
+        .unwrap()
+        .push("F.Full.1".to_string());
+    crate::ui::set_headless();
+    clean(&lib);
+
+    let report = fs::read_to_string(crate::stats::latest_report(&lib.root).unwrap()).unwrap();
+    let line: serde_json::Value = serde_json::from_str(report.lines().next().unwrap()).unwrap();
+    assert_eq!(line["package"], "F.Full.1.var");
+    assert_eq!(line["outcome"], "failed");
+    let addon = |tree: Vec<String>| -> Vec<String> {
+        tree.into_iter()
+            .filter(|p| p.starts_with("AddonPackages/"))
+            .collect()
+    };
+    assert_eq!(addon(lib.tree()), addon(before));
+}
+
+#[test]
+#[cfg(not(feature = "audit"))]
+fn a_merge_that_cannot_be_put_in_place_moves_the_originals_back() {
+    let lib = Library::new("rollback");
+    lib.add_package("R.Roll.1.var", &[], &[("Custom/a.txt", b"a")]);
+    lib.add_package("sub/R.Roll.1.var", &[], &[("Custom/b.txt", b"b")]);
+    // A folder in the way of the merged var makes the final rename fail
+    let blocker = lib.root.join("AddonPackages/merged/R.Roll.1.var");
+    fs::create_dir_all(&blocker).unwrap();
+    fs::write(blocker.join("keep"), b"").unwrap();
+    let before = lib.tree();
+    clean(&lib);
+
+    let report = fs::read_to_string(crate::stats::latest_report(&lib.root).unwrap()).unwrap();
+    let line: serde_json::Value = serde_json::from_str(report.lines().next().unwrap()).unwrap();
+    assert_eq!(line["outcome"], "failed");
+    let addon = |tree: Vec<String>| -> Vec<String> {
+        tree.into_iter()
+            .filter(|p| p.starts_with("AddonPackages/"))
+            .collect()
+    };
+    assert_eq!(addon(lib.tree()), addon(before));
+}
+
+#[test]
+#[cfg(not(feature = "audit"))]
+fn case_variant_entries_keep_one_copy() {
+    let lib = Library::new("case");
+    lib.add_package("C.Pkg.1.var", &[], &[("Custom/Scripts/foo.cs", b"live")]);
+    lib.add_package(
+        "sub/C.Pkg.1.var",
+        &[],
+        &[("custom/scripts/Foo.cs", b"larger, other copy")],
+    );
+    let args = CleanArgs::default();
+    let protected = crate::protect::load(&lib.root);
+    crate::clean(&lib.root, &args, &protected).unwrap();
+
+    let scripts: Vec<(String, Vec<u8>)> = lib
+        .entries("AddonPackages/merged/C.Pkg.1.var")
+        .into_iter()
+        .filter(|(name, _)| name.to_lowercase().ends_with("foo.cs"))
+        .collect();
+    assert_eq!(
+        scripts,
+        vec![("Custom/Scripts/foo.cs".to_string(), b"live".to_vec())]
+    );
+}
+
+#[test]
+#[cfg(unix)]
+fn the_daemon_never_removes_a_file_in_place_of_its_socket() {
+    let lib = Library::new("pipe");
+    let file = lib.root.join("VaM.exe");
+    assert!(crate::platform::listen(&file.to_string_lossy()).is_err());
+    assert!(file.exists());
+    let socket = lib.root.join("daemon.sock");
+    drop(crate::platform::listen(&socket.to_string_lossy()).unwrap());
+    // Left behind as by a daemon that was killed
+    assert!(crate::platform::listen(&socket.to_string_lossy()).is_ok());
+}
+
+#[test]
+#[cfg(not(feature = "audit"))]
+fn generated_meta_takes_the_whole_package_name() {
+    let lib = Library::new("metaname");
+    let target = lib.var_folder().join("Creator.variants.1.var");
+    let mut builder = crate::builder::VarBuilder::new(zip::CompressionMethod::Deflated);
+    builder.add_bytes("Custom/a.txt", b"a".to_vec());
+    builder.write(&target).unwrap();
+
+    let meta: serde_json::Value = serde_json::from_slice(
+        &lib.entry("AddonPackages/Creator.variants.1.var", "meta.json")
+            .unwrap(),
+    )
+    .unwrap();
+    assert_eq!(meta["creatorName"], "Creator");
+    assert_eq!(meta["packageName"], "variants");
+}
+
+#[test]
+fn drives_are_named_by_letter_whatever_the_spelling() {
+    let lib = Library::new("drives");
+    let var = lib.add_package("D.Pkg.1.var", &[], &[]);
+    #[cfg(windows)]
+    assert_eq!(
+        crate::drives::of(&var),
+        crate::drives::normalize(&lib.root.to_string_lossy()[..2])
+    );
+    // Without drive letters the drive is the mount point holding the library
+    #[cfg(unix)]
+    assert!(lib.root.starts_with(crate::drives::of(&var)));
+    assert_eq!(crate::drives::normalize("d:\\"), "D:");
+    assert_eq!(crate::drives::normalize("D"), "D:");
+}
+
+#[test]
+#[cfg(not(feature = "audit"))]
+fn merges_that_grow_past_the_largest_copy_keep_the_originals() {
+    let lib = Library::new("growth");
+    let one = vec![1u8; 3 << 20];
+    let two = vec![2u8; 3 << 20];
+    lib.add_package("G.Pkg.1.var", &[], &[("Custom/one.assetbundle", &one)]);
+    lib.add_package("sub/G.Pkg.1.var", &[], &[("Custom/two.assetbundle", &two)]);
+    let args = CleanArgs {
+        max_growth: Some(50),
+        ..CleanArgs::default()
+    };
+    let protected = crate::protect::load(&lib.root);
+    crate::clean(&lib.root, &args, &protected).unwrap();
+
+    let tree = lib.tree();
+    assert!(tree.contains(&"AddonPackages/sub/G.Pkg.1.var".to_string()));
+    assert!(!tree.iter().any(|p| p.starts_with("AddonPackages/merged/")));
+}
+
+#[test]
+#[cfg(not(feature = "audit"))]
+fn refresh_vam_cache_clears_package_caches_only() {
+    let lib = Library::new("vamcache");
+    lib.add_package("V.Pkg.1.var", &[], &[("Custom/a.txt", b"a")]);
+    lib.add_package("sub/V.Pkg.1.var", &[], &[("Custom/b.txt", b"b")]);
+    for cache in [
+        "Cache/AllPackagesJSON/V.Pkg.1.json",
+        "Cache/Textures/t.vamcache",
+    ] {
+        let path = lib.root.join(cache);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, b"{}").unwrap();
+    }
+    let args = CleanArgs {
+        refresh_vam_cache: true,
+        ..CleanArgs::default()
+    };
+    let protected = crate::protect::load(&lib.root);
+    crate::clean(&lib.root, &args, &protected).unwrap();
+
+    assert!(!lib.root.join("Cache/AllPackagesJSON").exists());
+    assert!(lib.root.join("Cache/Textures/t.vamcache").exists());
+}
+
+#[test]
+#[cfg(not(feature = "audit"))]
+fn overnight_pace_merges_and_leaves_no_checkpoint() {
+    let lib = Library::new("overnight");
+    lib.add_package("O.Pkg.1.var", &[], &[("Custom/a.txt", b"a")]);
+    lib.add_package("sub/O.Pkg.1.var", &[], &[("Custom/b.txt", b"b")]);
+    // Left behind by an interrupted run, taken into account and removed
+    let checkpoint = lib.root.join("VarCleaner/checkpoint.json");
+    fs::create_dir_all(checkpoint.parent().unwrap()).unwrap();
+    fs::write(&checkpoint, br#"{"packages":3,"dropped":1024}"#).unwrap();
+    let args = CleanArgs {
+        pace: crate::Pace::Overnight,
+        ..CleanArgs::default()
+    };
+    let protected = crate::protect::load(&lib.root);
+    crate::clean(&lib.root, &args, &protected).unwrap();
+
+    assert!(lib
+        .tree()
+        .contains(&"AddonPackages/merged/O.Pkg.1.var".to_string()));
+    assert!(!checkpoint.exists());
+}
+
+#[test]
+fn archives_that_are_no_vam_package_stay_out_of_merges() {
+    let lib = Library::new("foreign");
+    lib.add_package("F.Pkg.1.var", &[], &[("Custom/f.txt", b"f")]);
+    let mut rar = b"Rar!\x1a\x07\x01\x00".to_vec();
+    rar.resize(4096, 0);
+    lib.add_raw("sub/F.Pkg.1.var", &rar);
+    lib.add_package("R.Pkg.1.var", &[], &[("Custom/r.txt", b"r")]);
+    lib.add_var("sub/R.Pkg.1.var", &[("readme.txt", b"not a package")]);
+    let before = lib.tree();
+    clean(&lib);
+
+    assert_eq!(lib.tree(), before);
+}
+
+#[test]
+#[cfg(not(feature = "audit"))]
+fn verify_merge_accepts_real_merges_and_catches_altered_ones() {
+    let lib = Library::new("verifymerge");
+    let preset = "Custom/Atom/Person/Pose/p.vap";
+    lib.add_package(
+        "M.Pkg.1.var",
+        &[],
+        &[(preset, b"{}"), ("Custom/a.txt", b"a")],
+    );
+    lib.add_package("sub/M.Pkg.1.var", &[], &[(preset, b"{\"larger\":1}")]);
+    clean(&lib);
+
+    let sources = [
+        lib.root.join("VarCleaner/Backup/M.Pkg.1.var"),
+        lib.root.join("VarCleaner/Backup/sub/M.Pkg.1.var"),
+    ];
+    let merged = lib.var_folder().join("merged/M.Pkg.1.var");
+    assert!(crate::validate::verify_merge(&merged, &sources).unwrap());
+
+    let meta = crate::testkit::meta("M.Pkg.1", &[]);
+    let altered = lib.add_var(
+        "Altered.var",
+        &[
+            ("meta.json", &meta),
+            (preset, b"{}"),
+            ("Custom/a.txt", b"b"),
+        ],
+    );
+    assert!(!crate::validate::verify_merge(&altered, &sources).unwrap());
+}
+
+#[test]
+#[cfg(not(feature = "audit"))]
+fn stamped_merges_list_their_originals() {
+    let lib = Library::new("stamp");
+    lib.add_package("S.Pkg.1.var", &[], &[("Custom/a.txt", b"a")]);
+    lib.add_package("sub/S.Pkg.1.var", &[], &[("Custom/b.txt", b"b")]);
+    let args = CleanArgs {
+        stamp: true,
+        ..CleanArgs::default()
+    };
+    let protected = crate::protect::load(&lib.root);
+    crate::clean(&lib.root, &args, &protected).unwrap();
+
+    let sources = [
+        lib.root.join("VarCleaner/Backup/S.Pkg.1.var"),
+        lib.root.join("VarCleaner/Backup/sub/S.Pkg.1.var"),
+    ];
+    let merged = "AddonPackages/merged/S.Pkg.1.var";
+    let stamp: serde_json::Value =
+        serde_json::from_slice(&lib.entry(merged, crate::stamp::STAMP_ENTRY).unwrap()).unwrap();
+    for source in &sources {
+        let hash = crate::hashing::hash_file(source).unwrap();
+        assert!(stamp["sources"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .any(|s| s["blake3"] == hash.as_str()));
+    }
+    let merged = lib.root.join(merged);
+    assert!(crate::validate::verify_merge(&merged, &sources).unwrap());
+
+    // Same entries, but not one of the originals
+    let other = lib.add_package(
+        "Other.var",
+        &["X.Dep.1"],
+        &[("Custom/a.txt", b"a"), ("Custom/b.txt", b"b")],
+    );
+    assert!(!crate::validate::verify_merge(&merged, &[sources[0].clone(), other]).unwrap());
+}
+
+#[test]
+#[cfg(not(feature = "audit"))]
+fn output_folder_gets_the_merge_and_originals_stay() {
+    let lib = Library::new("output");
+    lib.add_package("X.Pkg.1.var", &[], &[("Custom/a.txt", b"a")]);
+    lib.add_package("sub/X.Pkg.1.var", &[], &[("Custom/b.txt", b"b")]);
+    lib.add_package("sub/Single.Pkg.1.var", &[], &[("Custom/s.txt", b"s")]);
+    let before = lib.tree();
+    let output = lib.root.join("CleanLibrary");
+    let args = CleanArgs {
+        output: Some(output.clone()),
+        export: true,
+        ..CleanArgs::default()
+    };
+    let protected = crate::protect::load(&lib.root);
+    crate::clean(&lib.root, &args, &protected).unwrap();
+
+    let tree = lib.tree();
+    assert!(before.iter().all(|p| tree.contains(p)));
+    assert!(tree.contains(&"CleanLibrary/X.Pkg.1.var".to_string()));
+    assert!(tree.contains(&"CleanLibrary/sub/Single.Pkg.1.var".to_string()));
+    assert_eq!(
+        lib.entries("CleanLibrary/X.Pkg.1.var").len(),
+        3,
+        "meta.json and both copies' entries"
+    );
+}
+
+#[test]
+#[cfg(not(feature = "audit"))]
+fn size_ties_between_copies_are_settled_by_date_then_crc() {
+    let preset = "Custom/Atom/Person/Pose/p.vap";
+    let date = |year| zip::DateTime::from_date_and_time(year, 1, 1, 0, 0, 0).unwrap();
+    let lib = Library::new("ties");
+    lib.add_package("T.Pkg.1.var", &[], &[]);
+    let meta = crate::testkit::meta("T.Pkg.1", &[]);
+    lib.add_var_at(
+        "a/T.Pkg.1.var",
+        &[("meta.json", &meta), (preset, b"new")],
+        date(2024),
+    );
+    lib.add_var_at(
+        "b/T.Pkg.1.var",
+        &[("meta.json", &meta), (preset, b"old")],
+        date(2020),
+    );
+    lib.add_var(
+        "c/T.Pkg.1.var",
+        &[("meta.json", &meta), ("Custom/x.txt", b"xx")],
+    );
+    lib.add_var("c/U.Pkg.1.var", &[("meta.json", &meta), (preset, b"one")]);
+    lib.add_var("d/U.Pkg.1.var", &[("meta.json", &meta), (preset, b"two")]);
+    lib.add_package("U.Pkg.1.var", &[], &[]);
+    let args = CleanArgs::default();
+    let protected = crate::protect::load(&lib.root);
+    crate::clean(&lib.root, &args, &protected).unwrap();
+
+    let merged = |package: &str| lib.entry(&format!("AddonPackages/merged/{package}"), preset);
+    assert_eq!(merged("T.Pkg.1.var").unwrap(), b"new");
+    // Same date, the higher CRC wins
+    let crc = crate::testkit::crc32;
+    let expected: &[u8] = if crc(b"one") > crc(b"two") {
+        b"one"
+    } else {
+        b"two"
+    };
+    assert_eq!(merged("U.Pkg.1.var").unwrap(), expected);
+}
+
+#[test]
+#[cfg(not(feature = "audit"))]
+fn maintain_merges_identical_copies_prunes_their_backups_and_reports() {
+    let lib = Library::new("maintain");
+    let live = lib.add_package("C.Pkg.1.var", &[], &[("Custom/c.txt", b"c")]);
+    lib.copy_var(&live, "sub/C.Pkg.1.var");
+    lib.add_package("D.Pkg.1.var", &[], &[("Custom/d.txt", b"live")]);
+    lib.add_package("sub/D.Pkg.1.var", &[], &[("Custom/d.txt", b"other")]);
+    let protected = crate::protect::load(&lib.root);
+    assert!(crate::maintain::run(
+        &lib.root,
+        &crate::maintain::ALL_PASSES,
+        &protected
+    ));
+
+    // The identical copy went to the backup and was pruned from it, differing copies wait
+    assert_eq!(
+        lib.tree(),
+        [
+            "AddonPackages/C.Pkg.1.var",
+            "AddonPackages/D.Pkg.1.var",
+            "AddonPackages/sub/D.Pkg.1.var",
+            "VaM.exe",
+        ]
+    );
+    let reports: Vec<_> = fs::read_dir(lib.root.join("VarCleaner/reports"))
+        .unwrap()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_name().to_string_lossy().starts_with("maintain-"))
+        .collect();
+    assert_eq!(reports.len(), 1);
+}
+
+#[test]
+fn scan_duplicates_yields_groups_as_copies_turn_up() {
+    let lib = Library::new("scan-duplicates");
+    lib.add_package("A.Pkg.1.var", &[], &[]);
+    lib.add_package("B.Pkg.1.var", &[], &[]);
+    lib.add_package("x/a.pkg.1.var", &[], &[]);
+    lib.add_package("y/A.Pkg.1.var", &[], &[]);
+    let groups: Vec<_> = varcleaner::index::scan_duplicates(&lib.var_folder()).collect();
+
+    let sizes: Vec<_> = groups
+        .iter()
+        .map(|g| (g.name.as_str(), g.copies.len()))
+        .collect();
+    assert_eq!(sizes, [("a.pkg.1.var", 2), ("a.pkg.1.var", 3)]);
+    // The copy VaM loads stays first
+    assert_eq!(groups[1].copies[0], lib.var_folder().join("A.Pkg.1.var"));
+}
+
+#[test]
+#[cfg(not(feature = "audit"))]
+fn browser_download_copies_join_their_base_name() {
+    let lib = Library::new("downloads");
+    let live = lib.add_package("B.Pkg.1.var", &[], &[("Custom/b.txt", b"b")]);
+    lib.copy_var(&live, "B.Pkg.1 (1).var");
+    let download = lib.add_package("C.Pkg.1 (1).var", &[], &[("Custom/c.txt", b"c")]);
+    lib.copy_var(&download, "C.Pkg.1 - Copy (2).var");
+    clean(&lib);
+
+    assert_eq!(
+        lib.tree(),
+        [
+            "AddonPackages/B.Pkg.1.var",
+            "AddonPackages/merged/C.Pkg.1.var",
+            "VaM.exe",
+            "VarCleaner/Backup/B.Pkg.1 (1).var",
+            "VarCleaner/Backup/C.Pkg.1 (1).var",
+            "VarCleaner/Backup/C.Pkg.1 - Copy (2).var",
+        ]
+    );
+    assert_eq!(
+        varcleaner::index::download_base_name("C.Pkg.1 - 副本 (3).var"),
+        "C.Pkg.1.var"
+    );
+}
+
+#[test]
+#[cfg(not(feature = "audit"))]
+fn target_free_stops_once_there_is_room() {
+    let lib = Library::new("target-free");
+    let live = lib.add_package("C.Pkg.1.var", &[], &[("Custom/c.txt", b"c")]);
+    lib.copy_var(&live, "sub/C.Pkg.1.var");
+    let protected = crate::protect::load(&lib.root);
+
+    // Any drive has a byte free, nothing needs to be done
+    assert!(crate::maintain::free_up(&lib.root, 1, &protected));
+    assert!(lib.var_folder().join("sub/C.Pkg.1.var").exists());
+
+    // No drive is that large: every step runs and the target is reported as missed
+    assert!(!crate::maintain::free_up(&lib.root, u64::MAX, &protected));
+    assert_eq!(lib.tree(), ["AddonPackages/C.Pkg.1.var", "VaM.exe"]);
+}
+
+#[test]
+fn vars_still_being_written_are_deferred() {
+    let lib = Library::new("deferred");
+    lib.add_package("A.Pkg.1.var", &[], &[("Custom/a.txt", b"a")]);
+    let downloading = lib.add_package("sub/A.Pkg.1.var", &[], &[("Custom/b.txt", b"b")]);
+    // Half a download, and a var that looks like one because it was just written
+    lib.add_raw("B.Pkg.1.var", b"PK\x03\x04");
+    for path in [downloading, lib.var_folder().join("B.Pkg.1.var")] {
+        let file = fs::File::options().write(true).open(path).unwrap();
+        file.set_modified(std::time::SystemTime::now()).unwrap();
+    }
+    let before = lib.tree();
+    clean(&lib);
+
+    assert_eq!(lib.tree(), before);
+}
+
+#[test]
+#[cfg(not(feature = "audit"))]
+fn broken_vars_are_remembered_until_they_change() {
+    let lib = Library::new("remembered-foreign");
+    lib.add_package("F.Pkg.1.var", &[], &[("Custom/f.txt", b"f")]);
+    let mut broken = b"PK\x03\x04".to_vec();
+    broken.resize(4096, 0);
+    let path = lib.add_raw("sub/F.Pkg.1.var", &broken);
+    clean(&lib);
+
+    let memory: serde_json::Value =
+        serde_json::from_slice(&fs::read(lib.root.join("VarCleaner/Cache/foreign.json")).unwrap())
+            .unwrap();
+    let known = &memory[path.to_string_lossy().as_ref()];
+    assert_eq!(
+        known["hash"],
+        crate::hashing::hash_file(&path).unwrap().as_str()
+    );
+
+    // Downloaded again: the new file is checked afresh and merged
+    fs::remove_file(&path).unwrap();
+    lib.add_package("sub/F.Pkg.1.var", &[], &[("Custom/g.txt", b"g")]);
+    clean(&lib);
+    assert!(lib
+        .entry("AddonPackages/merged/F.Pkg.1.var", "Custom/g.txt")
+        .is_some());
+}
+
+#[test]
+fn canonical_json_hashes_ignore_formatting_only() {
+    let lib = Library::new("canonical-json");
+    let preset = "Custom/Atom/Person/Pose/p.vap";
+    let compact = lib.add_var(
+        "J.Pkg.1.var",
+        &[(preset, br#"{"a":1,"b":[1,{"c":2,"d":3}]}"#)],
+    );
+    let pretty = lib.add_var(
+        "sub/J.Pkg.1.var",
+        &[(
+            preset,
+            "\u{feff}{\n  \"b\": [1, {\"d\": 3, \"c\": 2}],\n  \"a\": 1\n}".as_bytes(),
+        )],
+    );
+    let changed = lib.add_var(
+        "other/J.Pkg.1.var",
+        &[(preset, br#"{"a":2,"b":[1,{"c":2,"d":3}]}"#)],
+    );
+    let hashes = |path: &std::path::Path, canonical: bool| {
+        let mut var = varcleaner::reader::VarReader::open(path).unwrap();
+        let hashes = match canonical {
+            true => var.hash_entries_canonical(),
+            false => var.hash_entries(),
+        };
+        hashes.unwrap()
+    };
+
+    assert_ne!(hashes(&compact, false), hashes(&pretty, false));
+    assert_eq!(hashes(&compact, true), hashes(&pretty, true));
+    assert_ne!(hashes(&compact, true), hashes(&changed, true));
+}
+
+#[test]
+fn scanned_entry_names_are_shared_between_vars() {
+    let lib = Library::new("intern");
+    let a = lib.add_package("I.Pkg.1.var", &[], &[("Custom/Scripts/shared.cs", b"a")]);
+    let b = lib.add_package("I.Pkg.2.var", &[], &[("Custom/Scripts/shared.cs", b"b")]);
+    let name = |path: &std::path::Path| {
+        let var_index = crate::reader::VarReader::open(path)
+            .unwrap()
+            .index()
+            .unwrap();
+        var_index
+            .entries
+            .into_iter()
+            .find(|e| &*e.name == "Custom/Scripts/shared.cs")
+            .unwrap()
+            .name
+    };
+    assert!(std::sync::Arc::ptr_eq(&name(&a), &name(&b)));
+}
+
+#[test]
+fn repacked_clothing_is_found_by_its_uid() {
+    let lib = Library::new("items");
+    let dress = br#"{"uid":"Custom/Clothing/Female/A/Dress/Dress.vam","displayName":"Dress"}"#;
+    lib.add_package(
+        "A.Dress.1.var",
+        &[],
+        &[("Custom/Clothing/Female/A/Dress/Dress.vam", dress)],
+    );
+    lib.add_package(
+        "B.Outfits.1.var",
+        &[],
+        &[("Custom/Clothing/Female/B/Copied/Dress.vam", dress)],
+    );
+    // Another version of the same package is no repack
+    lib.add_package(
+        "A.Dress.2.var",
+        &[],
+        &[("Custom/Clothing/Female/A/Dress/Dress.vam", dress)],
+    );
+    let hair = br#"{"uid":"Custom/Hair/Female/A/Bob/Bob.vam","displayName":"Bob"}"#;
+    lib.add_package(
+        "A.Hair.1.var",
+        &[],
+        &[("Custom/Hair/Female/A/Bob/Bob.vam", hair)],
+    );
+    lib.add_package(
+        "A.Hair.2.var",
+        &[],
+        &[("Custom/Hair/Female/A/Bob/Bob.vam", hair)],
+    );
+
+    let shared = crate::items::shared(&lib.var_folder());
+    assert_eq!(shared.len(), 1);
+    let (uid, copies) = &shared[0];
+    assert_eq!(uid, "Custom/Clothing/Female/A/Dress/Dress.vam");
+    let entries: Vec<&str> = copies.iter().map(|item| item.entry.as_str()).collect();
+    assert_eq!(
+        entries,
+        [
+            "Custom/Clothing/Female/A/Dress/Dress.vam",
+            "Custom/Clothing/Female/A/Dress/Dress.vam",
+            "Custom/Clothing/Female/B/Copied/Dress.vam",
+        ]
+    );
+}
+
+#[test]
+#[cfg(not(feature = "audit"))]
+fn latest_run_stages_only_this_runs_merges() {
+    let lib = Library::new("stagelatest");
+    lib.add_package("L.Pkg.1.var", &[], &[("Custom/a.txt", b"a")]);
+    lib.add_package("sub/L.Pkg.1.var", &[], &[("Custom/b.txt", b"b")]);
+    let args = CleanArgs {
+        stage_latest: true,
+        ..CleanArgs::default()
+    };
+    let protected = crate::protect::load(&lib.root);
+    crate::clean(&lib.root, &args, &protected).unwrap();
+    let staged = "AddonPackages/merged/_latest-run/L.Pkg.1.var";
+    assert_eq!(
+        lib.entries(staged),
+        lib.entries("AddonPackages/merged/L.Pkg.1.var")
+    );
+
+    lib.add_package("M.Pkg.1.var", &[], &[("Custom/a.txt", b"a")]);
+    lib.add_package("sub/M.Pkg.1.var", &[], &[("Custom/b.txt", b"b")]);
+    crate::clean(&lib.root, &args, &protected).unwrap();
+    let tree = lib.tree();
+    assert!(!tree.iter().any(|path| path == staged));
+    assert!(tree
+        .iter()
+        .any(|path| path == "AddonPackages/merged/_latest-run/M.Pkg.1.var"));
+}
+
+#[test]
+#[cfg(not(feature = "audit"))]
+fn reconstruct_rebuilds_the_library_from_before_the_clean() {
+    let lib = Library::new("reconstruct");
+    lib.add_package("R.Pkg.1.var", &[], &[("Custom/a.txt", b"a")]);
+    lib.add_package("sub/R.Pkg.1.var", &[], &[("Custom/b.txt", b"b")]);
+    lib.add_package("Other/U.Pkg.1.var", &[], &[("Custom/u.txt", b"u")]);
+    let originals = ["R.Pkg.1.var", "sub/R.Pkg.1.var", "Other/U.Pkg.1.var"];
+    let before: Vec<Vec<u8>> = originals
+        .iter()
+        .map(|path| fs::read(lib.var_folder().join(path)).unwrap())
+        .collect();
+    clean(&lib);
+    // The merged output lost afterwards
+    fs::remove_dir_all(lib.var_folder().join("merged")).unwrap();
+
+    let target = Library::new("reconstruct-target");
+    let folder = target.var_folder();
+    crate::reconstruct::run(&lib.root, &folder).unwrap();
+    assert_eq!(
+        target.tree(),
+        [
+            "AddonPackages/Other/U.Pkg.1.var",
+            "AddonPackages/R.Pkg.1.var",
+            "AddonPackages/sub/R.Pkg.1.var",
+            "VaM.exe",
+        ]
+    );
+    for (path, bytes) in originals.iter().zip(before) {
+        assert_eq!(fs::read(folder.join(path)).unwrap(), bytes);
+    }
+}
+
+#[test]
+#[cfg(not(feature = "audit"))]
+fn restore_puts_originals_back_and_merged_vars_aside() {
+    let lib = Library::new("restore");
+    lib.add_package("T.Pkg.1.var", &[], &[("Custom/a.txt", b"a")]);
+    lib.add_package("sub/T.Pkg.1.var", &[], &[("Custom/b.txt", b"b")]);
+    let before = fs::read(lib.var_folder().join("sub/T.Pkg.1.var")).unwrap();
+    clean(&lib);
+
+    crate::backup::restore(&lib.root, true).unwrap();
+    let tree = lib.tree();
+    for path in [
+        "AddonPackages/T.Pkg.1.var",
+        "AddonPackages/sub/T.Pkg.1.var",
+        "VarCleaner/Unmerged/T.Pkg.1.var",
+    ] {
+        assert!(tree.iter().any(|p| p == path), "{path} missing");
+    }
+    assert!(!tree.iter().any(|p| p.starts_with("VarCleaner/Backup/")));
+    assert!(!tree.iter().any(|p| p.starts_with("AddonPackages/merged/")));
+    assert_eq!(
+        fs::read(lib.var_folder().join("sub/T.Pkg.1.var")).unwrap(),
+        before
+    );
+}
+
+#[test]
+#[cfg(not(feature = "audit"))]
+fn restoring_a_run_id_moves_originals_back_and_deletes_its_merges() {
+    let lib = Library::new("restore-run");
+    lib.add_package("R.Pkg.1.var", &[], &[("Custom/a.txt", b"a")]);
+    lib.add_package("sub/R.Pkg.1.var", &[], &[("Custom/b.txt", b"b")]);
+    let before = lib.tree();
+    clean(&lib);
+    assert!(lib.root.join("AddonPackages/merged/R.Pkg.1.var").exists());
+
+    let run_id = fs::read_dir(lib.root.join("VarCleaner/Journal"))
+        .unwrap()
+        .filter_map(|e| e.ok())
+        .map(|e| e.path().file_stem().unwrap().to_string_lossy().to_string())
+        .find(|id| id.starts_with("clean-"))
+        .unwrap();
+    assert!(crate::backup::restore_run(&lib.root, "clean-0", true).is_err());
+    crate::backup::restore_run(&lib.root, &run_id, true).unwrap();
+    let addon = |tree: Vec<String>| -> Vec<String> {
+        tree.into_iter()
+            .filter(|p| p.starts_with("AddonPackages/"))
+            .collect()
+    };
+    assert_eq!(addon(lib.tree()), addon(before));
+    assert!(!lib
+        .tree()
+        .iter()
+        .any(|p| p.starts_with("VarCleaner/Backup/")));
+}
+
+#[test]
+#[cfg(not(feature = "audit"))]
+fn restoring_a_re_merge_puts_the_earlier_merge_back() {
+    let lib = Library::new("restore-remerge");
+    lib.add_package("M.Pkg.1.var", &[], &[("Custom/a.txt", b"a")]);
+    lib.add_package("sub/M.Pkg.1.var", &[], &[("Custom/b.txt", b"b")]);
+    clean(&lib);
+    let merged = lib.root.join("AddonPackages/merged/M.Pkg.1.var");
+    let first_merge = fs::read(&merged).unwrap();
+    crate::testkit::settle(&merged);
+    lib.add_package("later/M.Pkg.1.var", &[], &[("Custom/c.txt", b"c")]);
+    let before = lib.tree();
+    clean(&lib);
+    assert_ne!(fs::read(&merged).unwrap(), first_merge);
+
+    let run_id = fs::read_dir(lib.root.join("VarCleaner/Journal"))
+        .unwrap()
+        .filter_map(|e| e.ok())
+        .map(|e| e.path().file_stem().unwrap().to_string_lossy().to_string())
+        .filter(|id| id.starts_with("clean-"))
+        .max()
+        .unwrap();
+    crate::backup::restore_run(&lib.root, &run_id, true).unwrap();
+    assert_eq!(fs::read(&merged).unwrap(), first_merge);
+    let addon = |tree: Vec<String>| -> Vec<String> {
+        tree.into_iter()
+            .filter(|p| p.starts_with("AddonPackages/"))
+            .collect()
+    };
+    assert_eq!(addon(lib.tree()), addon(before));
+}
+
+#[test]
+fn dry_run_estimates_without_changing_anything() {
+    let lib = Library::new("dryrun");
+    let big = vec![b'x'; 4096];
+    lib.add_package(
+        "D.Pkg.1.var",
+        &[],
+        &[("Custom/a.txt", b"a"), ("Custom/big.bin", &big)],
+    );
+    lib.add_package("sub/D.Pkg.1.var", &[], &[("Custom/big.bin", &big)]);
+    let before = lib.tree();
+    let args = CleanArgs {
+        dry_run: true,
+        ..CleanArgs::default()
+    };
+    let protected = crate::protect::load(&lib.root);
+    crate::clean(&lib.root, &args, &protected).unwrap();
+    assert_eq!(lib.tree(), before);
+
+    let sources: std::collections::LinkedList<_> = ["D.Pkg.1.var", "sub/D.Pkg.1.var"]
+        .iter()
+        .map(|path| lib.var_folder().join(path))
+        .collect();
+    let merged = crate::streaming::plan(&sources, "D.Pkg.1", &args).unwrap();
+    let live = fs::metadata(sources.front().unwrap()).unwrap().len();
+    // The second copy adds nothing, the merge is about as large as the live copy
+    assert!(merged > 4096 && merged < live);
+}
+
+#[test]
+#[cfg(not(feature = "audit"))]
+fn renamed_identical_vars_are_found_by_content() {
+    let lib = Library::new("by-content");
+    let live = lib.add_package("C.Pkg.1.var", &[], &[("Custom/c.txt", b"c")]);
+    lib.copy_var(&live, "downloads/c_pkg_fixed.var");
+    // Another version with the same bytes is still its own package
+    let version = lib.add_package("D.Pkg.1.var", &[], &[("Custom/d.txt", b"d")]);
+    lib.copy_var(&version, "D.Pkg.2.var");
+    let args = CleanArgs {
+        by_content: true,
+        ..CleanArgs::default()
+    };
+    let protected = crate::protect::load(&lib.root);
+    crate::clean(&lib.root, &args, &protected).unwrap();
+
+    assert_eq!(
+        lib.tree(),
+        [
+            "AddonPackages/C.Pkg.1.var",
+            "AddonPackages/D.Pkg.1.var",
+            "AddonPackages/D.Pkg.2.var",
+            "VaM.exe",
+            "VarCleaner/Backup/downloads/c_pkg_fixed.var",
+        ]
+    );
+}
+
+#[test]
+#[cfg(not(feature = "audit"))]
+fn prune_versions_keeps_the_newest_and_depended_on_versions() {
+    let lib = Library::new("prune-versions");
+    for version in 1..=3 {
+        lib.add_package(&format!("A.Pkg.{version}.var"), &[], &[]);
+    }
+    lib.add_package("B.Scene.1.var", &["A.Pkg.1"], &[]);
+    let protected = crate::protect::load(&lib.root);
+    crate::versions::keep_latest(&lib.root, 1, true, &protected);
+
+    assert_eq!(
+        lib.tree(),
+        [
+            "AddonPackages/A.Pkg.1.var",
+            "AddonPackages/A.Pkg.3.var",
+            "AddonPackages/B.Scene.1.var",
+            "VaM.exe",
+            "VarCleaner/Backup/A.Pkg.2.var",
+        ]
+    );
+}
+
+#[test]
+#[cfg(not(feature = "audit"))]
+fn merged_content_list_matches_the_merged_entries() {
+    let lib = Library::new("content-list");
+    let meta = |items: &[&str]| {
+        serde_json::to_vec(&serde_json::json!({
+            "creatorName": "M",
+            "packageName": "Pkg",
+            "contentList": items,
+            "dependencies": {},
+        }))
+        .unwrap()
+    };
+    let live = meta(&["Custom/a.txt", "Custom/gone.txt"]);
+    lib.add_var(
+        "M.Pkg.1.var",
+        &[("meta.json", &live), ("Custom/a.txt", b"a")],
+    );
+    let other = meta(&["Custom/a.txt", "Custom/b.txt"]);
+    lib.add_var(
+        "sub/M.Pkg.1.var",
+        &[
+            ("meta.json", &other),
+            ("Custom/a.txt", b"a"),
+            ("Custom/b.txt", b"b"),
+        ],
+    );
+    clean(&lib);
+
+    let merged = lib
+        .entry("AddonPackages/merged/M.Pkg.1.var", "meta.json")
+        .unwrap();
+    let merged = crate::meta::Meta::parse(&merged).unwrap();
+    assert_eq!(merged.content_list, ["Custom/a.txt", "Custom/b.txt"]);
+    // What contentList does not cover is kept as it was
+    assert_eq!(merged.other["dependencies"], serde_json::json!({}));
+}
+
+#[test]
+fn dependency_graph_finds_missing_dependencies_and_orphans() {
+    let lib = Library::new("deps");
+    lib.add_package("A.Scene.1.var", &["B.Lib.latest", "Gone.Pkg.2"], &[]);
+    lib.add_package("B.Lib.1.var", &[], &[]);
+    lib.add_package("C.Look.1.var", &["Gone.Pkg.2"], &[]);
+    let installed = varcleaner::index::installed_packages(&lib.var_folder());
+    let graph = crate::deps::Graph::build(&installed);
+
+    let missing = graph.missing();
+    assert_eq!(missing.keys().collect::<Vec<_>>(), ["Gone.Pkg.2"]);
+    assert_eq!(
+        missing["Gone.Pkg.2"].iter().collect::<Vec<_>>(),
+        ["A.Scene.1", "C.Look.1"]
+    );
+    assert_eq!(graph.orphans(), ["A.Scene.1", "C.Look.1"]);
+}
+
+#[test]
+#[cfg(feature = "audit")]
+fn audit_builds_refuse_to_change_the_library() {
+    let lib = Library::new("audit");
+    lib.add_package("A.Pkg.1.var", &[], &[("Custom/a.txt", b"a")]);
+    lib.add_package("sub/A.Pkg.1.var", &[], &[("Custom/b.txt", b"b")]);
+    let before = lib.tree();
+    assert!(crate::readonly::is_enabled());
+    let protected = crate::protect::load(&lib.root);
+    let _ = crate::clean(&lib.root, &CleanArgs::default(), &protected);
+    assert_eq!(lib.tree(), before);
+}
@@ -0,0 +1,223 @@
+use crate::console::{info, summary, warning};
+use crate::format_size;
+use crate::index;
+use crate::reader::VarReader;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::io;
+use std::io::Read;
+use std::path::Path;
+use std::sync::Mutex;
+use threadpool::ThreadPool;
+use threadpool_scope::scope_with;
+
+pub struct TextureInfo {
+    pub width: u32,
+    pub height: u32,
+    pub channels: u32,
+}
+
+impl TextureInfo {
+    pub fn decoded_size(&self) -> u64 {
+        self.width as u64 * self.height as u64 * self.channels as u64
+    }
+}
+
+struct VarTextures {
+    package: String,
+    count: usize,
+    bytes: u64,
+    scenes: Vec<String>,
+    dependencies: Vec<crate::package::PackageId>,
+}
+
+pub fn is_image(name: &str) -> bool {
+    let lower = name.to_ascii_lowercase();
+    lower.ends_with(".jpg") || lower.ends_with(".jpeg") || lower.ends_with(".png")
+}
+
+fn read_u16_be(reader: &mut dyn Read) -> Option<u16> {
+    let mut buf = [0u8; 2];
+    reader.read_exact(&mut buf).ok()?;
+    Some(u16::from_be_bytes(buf))
+}
+
+fn png_info(reader: &mut dyn Read) -> Option<TextureInfo> {
+    // Signature (8) + IHDR length/type (8) + width, height, bit depth, color type
+    let mut header = [0u8; 26];
+    reader.read_exact(&mut header).ok()?;
+    if &header[1..4] != b"PNG" || &header[12..16] != b"IHDR" {
+        return None;
+    }
+    let channels = match header[25] {
+        0 => 1,
+        4 => 2,
+        2 | 3 => 3,
+        _ => 4,
+    };
+    Some(TextureInfo {
+        width: u32::from_be_bytes(header[16..20].try_into().unwrap()),
+        height: u32::from_be_bytes(header[20..24].try_into().unwrap()),
+        channels,
+    })
+}
+
+fn jpeg_info(reader: &mut dyn Read) -> Option<TextureInfo> {
+    if read_u16_be(reader)? != 0xFFD8 {
+        return None;
+    }
+    let mut byte = [0u8; 1];
+    loop {
+        reader.read_exact(&mut byte).ok()?;
+        if byte[0] != 0xFF {
+            continue;
+        }
+        let mut marker = 0xFF;
+        while marker == 0xFF {
+            reader.read_exact(&mut byte).ok()?;
+            marker = byte[0];
+        }
+        if marker == 0x01 || (0xD0..=0xD9).contains(&marker) {
+            continue;
+        }
+        let length = read_u16_be(reader)?.checked_sub(2)? as u64;
+        let is_sof = (0xC0..=0xCF).contains(&marker) && ![0xC4, 0xC8, 0xCC].contains(&marker);
+        if is_sof {
+            let mut sof = [0u8; 6];
+            reader.read_exact(&mut sof).ok()?;
+            return Some(TextureInfo {
+                width: u16::from_be_bytes([sof[3], sof[4]]) as u32,
+                height: u16::from_be_bytes([sof[1], sof[2]]) as u32,
+                channels: sof[5] as u32,
+            });
+        }
+        io::copy(&mut reader.take(length), &mut io::sink()).ok()?;
+    }
+}
+
+pub fn texture_info(name: &str, reader: &mut dyn Read) -> Option<TextureInfo> {
+    if name.to_ascii_lowercase().ends_with(".png") {
+        png_info(reader)
+    } else {
+        jpeg_info(reader)
+    }
+}
+
+fn scan_var(path: &Path) -> anyhow::Result<VarTextures> {
+    let mut var = VarReader::open(path)?;
+    let var_index = var.index()?;
+    let mut result = VarTextures {
+        package: var_index.package,
+        count: 0,
+        bytes: 0,
+        scenes: Vec::new(),
+        dependencies: var_index
+            .meta
+            .as_ref()
+            .map(index::dependencies)
+            .unwrap_or_default(),
+    };
+    for entry in var_index.entries.iter() {
+        if entry.name.starts_with("Saves/scene/") && entry.name.ends_with(".json") {
+            result.scenes.push(entry.name.to_string());
+        }
+        if entry.size == 0 || !is_image(&entry.name) {
+            continue;
+        }
+        let mut file = match var.open_entry(&entry.name) {
+            Ok(file) => file,
+            Err(_) => continue,
+        };
+        if let Some(info) = texture_info(&entry.name, &mut file) {
+            result.count += 1;
+            result.bytes += info.decoded_size();
+        }
+    }
+    Ok(result)
+}
+
+fn dependency_closure(
+    package: &str,
+    scanned: &HashMap<String, VarTextures>,
+    installed: &HashMap<String, std::path::PathBuf>,
+) -> HashSet<String> {
+    let mut closure = HashSet::new();
+    let mut pending = vec![package.to_string()];
+    while let Some(name) = pending.pop() {
+        if !closure.insert(name.clone()) {
+            continue;
+        }
+        if let Some(var) = scanned.get(&name) {
+            for dep in var.dependencies.iter() {
+                if let Some((resolved, _)) = index::resolve_dependency(dep, installed) {
+                    pending.push(resolved.clone());
+                }
+            }
+        }
+    }
+    closure
+}
+
+pub fn report(var_folder: &Path, top: usize) {
+    let installed = index::installed_packages(var_folder);
+    let scanned: Mutex<HashMap<String, VarTextures>> = Mutex::new(HashMap::new());
+    let pool = ThreadPool::new(crate::config::get().threads);
+    scope_with(&pool, |scope| {
+        for path in installed.values() {
+            let scanned = &scanned;
+            scope.execute(move || match scan_var(path) {
+                Ok(result) => {
+                    scanned
+                        .lock()
+                        .unwrap()
+                        .insert(result.package.clone(), result);
+                }
+                Err(_) => warning!("zipfile {} is invaild", path.to_string_lossy()),
+            });
+        }
+    });
+    let scanned = scanned.into_inner().unwrap();
+
+    let mut vars: Vec<&VarTextures> = scanned.values().filter(|v| v.count > 0).collect();
+    vars.sort_by_key(|v| std::cmp::Reverse(v.bytes));
+    let total: u64 = vars.iter().map(|v| v.bytes).sum();
+    summary!(
+        "Decoded texture size of {} vars: {}",
+        vars.len(),
+        format_size(total)
+    );
+    info!("Top vars by texture memory / 贴图显存占用最高的Var:");
+    for var in vars.iter().take(top) {
+        info!(
+            "  {:>10}  {:>5} textures  {}",
+            format_size(var.bytes),
+            var.count,
+            var.package
+        );
+    }
+
+    let mut scenes: Vec<(String, u64, usize)> = Vec::new();
+    for var in scanned.values().filter(|v| !v.scenes.is_empty()) {
+        let closure = dependency_closure(&var.package, &scanned, &installed);
+        let bytes = closure
+            .iter()
+            .filter_map(|p| scanned.get(p))
+            .map(|v| v.bytes)
+            .sum();
+        for scene in var.scenes.iter() {
+            scenes.push((format!("{}:/{}", var.package, scene), bytes, closure.len()));
+        }
+    }
+    scenes.sort_by_key(|s| std::cmp::Reverse(s.1));
+    info!(
+        "Top scenes by texture memory including dependencies / 包含依赖后贴图显存占用最高的场景:"
+    );
+    for (scene, bytes, count) in scenes.iter().take(top) {
+        info!(
+            "  {:>10}  {:>5} vars  {}",
+            format_size(*bytes),
+            count,
+            scene
+        );
+    }
+}
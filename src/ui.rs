@@ -0,0 +1,150 @@
+// Every message box and console prompt runs on one UI thread. Workers queue a request and
+// block until it is answered, so system modal popups never stack and a prompt never runs
+// on a pool thread the rest of the run is waiting for.
+use crate::console;
+use crate::console::Level;
+use std::io;
+use std::io::IsTerminal;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::sync::mpsc;
+use std::sync::mpsc::Sender;
+use std::sync::OnceLock;
+use std::thread;
+
+enum Request {
+    MessageBox {
+        title: String,
+        message: String,
+    },
+    // Yes/no question on the console, no without a console
+    Confirm {
+        question: String,
+    },
+    WaitForEnter {
+        prompt: String,
+    },
+    // Summary of a finished run with buttons opening what it left behind
+    Completion {
+        message: String,
+        report: PathBuf,
+        merged: PathBuf,
+        logs: Option<PathBuf>,
+    },
+}
+
+static UI: OnceLock<Sender<(Request, Sender<bool>)>> = OnceLock::new();
+
+fn spawn() -> Sender<(Request, Sender<bool>)> {
+    let (requests, received) = mpsc::channel::<(Request, Sender<bool>)>();
+    thread::Builder::new()
+        .name("ui".to_string())
+        .spawn(move || {
+            for (request, reply) in received {
+                let _ = reply.send(handle(request));
+            }
+        })
+        .unwrap();
+    requests
+}
+
+fn ask(request: Request) -> bool {
+    let (reply, answer) = mpsc::channel();
+    UI.get_or_init(spawn).send((request, reply)).unwrap();
+    answer.recv().unwrap_or(false)
+}
+
+// Set by scheduled runs: nobody is there to answer, so no message box shows and every
+// question is answered no
+static HEADLESS: AtomicBool = AtomicBool::new(false);
+
+pub fn set_headless() {
+    HEADLESS.store(true, Ordering::Relaxed);
+}
+
+fn is_headless() -> bool {
+    HEADLESS.load(Ordering::Relaxed)
+}
+
+// Whether someone can answer a prompt on the console: not a scheduled run, and stdin is a
+// terminal rather than a pipe or nothing at all
+pub fn can_prompt() -> bool {
+    !is_headless() && io::stdin().is_terminal()
+}
+
+// Message boxes are skipped with message_boxes = false, the console already says it all
+pub fn message_box(title: &str, message: &str) {
+    if !crate::config::get().message_boxes || is_headless() {
+        return;
+    }
+    ask(Request::MessageBox {
+        title: title.to_string(),
+        message: message.to_string(),
+    });
+}
+
+pub fn confirm(question: &str) -> bool {
+    if is_headless() {
+        return false;
+    }
+    ask(Request::Confirm {
+        question: question.to_string(),
+    })
+}
+
+pub fn wait_for_enter(prompt: &str) {
+    if is_headless() {
+        return;
+    }
+    ask(Request::WaitForEnter {
+        prompt: prompt.to_string(),
+    });
+}
+
+pub fn completion(message: &str, report: &Path, merged: &Path, logs: Option<PathBuf>) {
+    if !crate::config::get().message_boxes || is_headless() {
+        return;
+    }
+    ask(Request::Completion {
+        message: message.to_string(),
+        report: report.to_path_buf(),
+        merged: merged.to_path_buf(),
+        logs,
+    });
+}
+
+fn handle(request: Request) -> bool {
+    match request {
+        Request::MessageBox { title, message } => {
+            crate::platform::message_box(&title, &message);
+            true
+        }
+        Request::Confirm { question } => {
+            if !io::stdin().is_terminal() {
+                return false;
+            }
+            console::print(Level::Summary, &format!("{question} [Y/n]"));
+            let mut answer = String::new();
+            if io::stdin().read_line(&mut answer).is_err() {
+                return false;
+            }
+            matches!(answer.trim(), "" | "y" | "Y" | "yes")
+        }
+        Request::WaitForEnter { prompt } => {
+            console::print(Level::Summary, &prompt);
+            let mut line = String::new();
+            io::stdin().read_line(&mut line).is_ok()
+        }
+        Request::Completion {
+            message,
+            report,
+            merged,
+            logs,
+        } => {
+            crate::platform::show_completion(&message, &report, &merged, logs);
+            true
+        }
+    }
+}
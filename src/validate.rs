@@ -0,0 +1,192 @@
+use crate::archive;
+use crate::config;
+use crate::console::{error, info, summary, verbose, warning};
+use crate::content;
+use crate::index;
+use crate::intern::Name;
+use crate::reader::VarReader;
+use std::collections::BTreeMap;
+use std::collections::HashSet;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use threadpool::ThreadPool;
+use threadpool_scope::scope_with;
+
+// Mimic what VaM does when it opens a package: meta.json parses, its contentList
+// exists, scene files parse and their SELF:/ references resolve
+pub fn smoke_check(path: &Path) -> anyhow::Result<Vec<String>> {
+    let mut var = VarReader::open(path)?;
+    let var_index = var.index()?;
+    let names: HashSet<&str> = var_index.entries.iter().map(|e| &*e.name).collect();
+    let exists = |name: &str| {
+        names.contains(name) || names.iter().any(|n| n.starts_with(&format!("{name}/")))
+    };
+    let mut problems = Vec::new();
+
+    let mut buffer = Vec::new();
+    if names.contains("meta.json") {
+        buffer = var.read_entry("meta.json")?;
+    } else {
+        problems.push("meta.json is missing".to_string());
+    }
+    if !buffer.is_empty() {
+        match index::parse_json(&buffer) {
+            Ok(meta) => {
+                let content_list = meta.get("contentList").and_then(|c| c.as_array());
+                for item in content_list
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|i| i.as_str())
+                {
+                    if !exists(item) {
+                        problems.push(format!("contentList entry {item} is missing"));
+                    }
+                }
+            }
+            Err(e) => problems.push(format!("meta.json does not parse: {e}")),
+        }
+    }
+
+    for entry in var_index
+        .entries
+        .iter()
+        .filter(|e| content::is_text(&e.name))
+    {
+        let buffer = var.read_entry(&entry.name)?;
+        if entry.name.starts_with("Saves/") && entry.name.ends_with(".json") {
+            if let Err(e) = index::parse_json(&buffer) {
+                problems.push(format!("{} does not parse: {e}", entry.name));
+                continue;
+            }
+        }
+        let text = String::from_utf8_lossy(&buffer);
+        for target in content::self_references(&text) {
+            if !exists(target) {
+                problems.push(format!("{} references missing SELF:/{target}", entry.name));
+            }
+        }
+    }
+    Ok(problems)
+}
+
+pub fn report_problems(package: &str, problems: &[String]) {
+    const SHOWN: usize = 10;
+    for problem in problems.iter().take(SHOWN) {
+        warning!("{package}: {problem}");
+    }
+    if problems.len() > SHOWN {
+        warning!(
+            "{package}: ... and {} more problems",
+            problems.len() - SHOWN
+        );
+    }
+}
+
+// CRC-check every entry of `vars` in parallel and list the packages that fail
+pub fn verify(vars: &[PathBuf]) {
+    let pool = ThreadPool::new(config::get().threads);
+    let broken: Mutex<BTreeMap<String, Vec<String>>> = Mutex::new(BTreeMap::new());
+    scope_with(&pool, |scope| {
+        for path in vars {
+            let broken = &broken;
+            scope.execute(move || {
+                let problems = match archive::verify_crc(path) {
+                    Ok(problems) => problems,
+                    Err(e) => vec![format!("cannot be opened: {e}")],
+                };
+                let name = path.to_string_lossy().to_string();
+                if problems.is_empty() {
+                    verbose!("{name} OK");
+                } else {
+                    broken.lock().unwrap().insert(name, problems);
+                }
+            });
+        }
+    });
+    let broken = broken.into_inner().unwrap();
+    for (name, problems) in broken.iter() {
+        error!("{name}");
+        for problem in problems {
+            info!("  {problem}");
+        }
+    }
+    summary!(
+        "{} of {} vars are corrupt / {}个Var已损坏",
+        broken.len(),
+        vars.len(),
+        broken.len()
+    );
+}
+
+// Check `merged` against its `sources` (the copy VaM loads first) without trusting the merge
+// code: every entry should hold the data of the copy the rules pick, the live copy first and
+// otherwise the largest. Entries taken from another copy on purpose (thumbnails following
+// their content, --only-content) are listed but pass; missing, unknown or altered data fails,
+// so check merges made without the options that leave entries out.
+pub fn verify_merge(merged: &Path, sources: &[PathBuf]) -> anyhow::Result<bool> {
+    // Name -> (copy, size, hash) of the expected winner, and every copy's hash of each name
+    let mut expected: BTreeMap<String, (usize, u64, blake3::Hash)> = BTreeMap::new();
+    let mut copies: BTreeMap<String, Vec<blake3::Hash>> = BTreeMap::new();
+    for (copy, path) in sources.iter().enumerate() {
+        let mut var = VarReader::open(path)?;
+        let sizes: BTreeMap<Name, u64> = var
+            .entries()?
+            .into_iter()
+            .map(|e| (e.name, e.size))
+            .collect();
+        for (name, hash) in var.hash_entries()? {
+            if crate::nested::is_nested(&name) {
+                continue;
+            }
+            let size = sizes[name.as_str()];
+            copies.entry(name.clone()).or_default().push(hash);
+            let wins = match expected.get(&name) {
+                None => true,
+                Some((kept, kept_size, _)) => *kept != 0 && size > *kept_size,
+            };
+            if wins {
+                expected.insert(name, (copy, size, hash));
+            }
+        }
+    }
+    let actual: BTreeMap<String, blake3::Hash> = VarReader::open(merged)?
+        .hash_entries()?
+        .into_iter()
+        .collect();
+    let mut failures = 0;
+    let mut other_copy = 0;
+    for (name, (copy, _, hash)) in expected.iter() {
+        match actual.get(name) {
+            Some(found) if found == hash => verbose!("{name}: copy {copy}"),
+            Some(found) if copies[name].contains(found) => {
+                other_copy += 1;
+                info!("{name}: taken from another copy than {copy}");
+            }
+            Some(_) => {
+                failures += 1;
+                error!("{name}: matches no source copy");
+            }
+            None => {
+                failures += 1;
+                error!("{name}: missing from the merged var");
+            }
+        }
+    }
+    for name in actual.keys().filter(|name| !expected.contains_key(*name)) {
+        // A generated meta.json and the stamp are the entries no source needs to have
+        if name != "meta.json" && name != crate::stamp::STAMP_ENTRY {
+            failures += 1;
+            error!("{name}: in the merged var but in no source");
+        }
+    }
+    if crate::stamp::check(merged, sources)? == Some(false) {
+        failures += 1;
+    }
+    summary!(
+        "{} entries checked, {other_copy} from another copy, {failures} problems / 检查{}个条目，{failures}个问题",
+        expected.len(),
+        expected.len()
+    );
+    Ok(failures == 0)
+}
@@ -0,0 +1,34 @@
+use crate::console::{error, success, verbose};
+use std::fs;
+use std::path::Path;
+
+// What VaM caches about installed packages, relative to the VaM folder. It rebuilds them from
+// AddonPackages on the next launch; until then a stale cache lists vars that were merged or
+// moved. Cache/Textures is left alone, rebuilding it takes far longer and it is keyed by
+// texture content, not by package.
+const PACKAGE_CACHES: [&str; 2] = ["Cache/AllPackagesJSON", "AddonPackages.json"];
+
+pub fn refresh(vam_folder: &Path) {
+    let mut cleared = 0;
+    for cache in PACKAGE_CACHES {
+        let path = vam_folder.join(cache);
+        let result = crate::readonly::check(&path).and_then(|_| {
+            if path.is_dir() {
+                fs::remove_dir_all(&path)
+            } else {
+                fs::remove_file(&path)
+            }
+        });
+        match result {
+            Ok(()) => {
+                verbose!("Removed {}", path.to_string_lossy());
+                cleared += 1;
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => error!("Failed to remove {}: {e}", path.to_string_lossy()),
+        }
+    }
+    if cleared > 0 {
+        success!("Cleared VaM's package cache, the next launch lists the cleaned library / 已清除VaM的包缓存，下次启动即显示清理后的库");
+    }
+}
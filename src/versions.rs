@@ -0,0 +1,121 @@
+use crate::console::{error, info, summary};
+use crate::format_size;
+use crate::index;
+use crate::journal::Journal;
+use crate::journal::Operation;
+use crate::package::PackageId;
+use crate::protect::Protected;
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+// Installed packages grouped by `Creator.Pkg`, versions in ascending order
+pub fn group_versions(
+    installed: &HashMap<String, PathBuf>,
+) -> BTreeMap<String, Vec<(u64, PathBuf)>> {
+    let mut result: BTreeMap<String, Vec<(u64, PathBuf)>> = BTreeMap::new();
+    for (package, path) in installed.iter() {
+        let Some(id) = PackageId::parse(package) else {
+            continue;
+        };
+        if let Some(version) = id.exact_version() {
+            result
+                .entry(id.base())
+                .or_default()
+                .push((version, path.clone()));
+        }
+    }
+    for versions in result.values_mut() {
+        versions.sort_by_key(|(version, _)| *version);
+    }
+    result
+}
+
+// Hands out version numbers above every installed one, never the same one twice
+pub struct VersionBumper {
+    latest: Mutex<HashMap<String, u64>>,
+}
+
+impl VersionBumper {
+    pub fn new(installed: &HashMap<String, PathBuf>) -> VersionBumper {
+        let latest = group_versions(installed)
+            .into_iter()
+            .map(|(base, versions)| (base.to_lowercase(), versions.last().unwrap().0))
+            .collect();
+        VersionBumper {
+            latest: Mutex::new(latest),
+        }
+    }
+
+    // `Creator.Pkg.3` -> `Creator.Pkg.5` when 4 is already installed
+    pub fn next(&self, package: &str) -> Option<String> {
+        let id = PackageId::parse(package)?;
+        let mut latest = self.latest.lock().unwrap();
+        let next = latest
+            .entry(id.base().to_lowercase())
+            .or_insert(id.exact_version()?);
+        *next += 1;
+        Some(id.with_version(*next).to_string())
+    }
+}
+
+// Moves every version but the newest `keep` of each package to the backup, journaled so
+// restore puts them back. With `keep_referenced` versions other vars depend on stay too.
+pub fn keep_latest(vam_folder: &Path, keep: usize, keep_referenced: bool, protected: &Protected) {
+    let var_folder = &crate::root::addon_packages(vam_folder);
+    let backup_folder = &crate::root::backup_folder(vam_folder);
+    let installed = index::installed_packages(var_folder);
+    let referenced = if keep_referenced {
+        crate::deps::depended_on(&installed)
+    } else {
+        HashSet::new()
+    };
+    let mut journal = Journal::new("prune-versions");
+    let mut freed = 0;
+    for (base_name, versions) in group_versions(&installed) {
+        let superseded = versions.len().saturating_sub(keep);
+        for (version, path) in versions.iter().take(superseded) {
+            let package = format!("{base_name}.{version}");
+            if protected.skips(path) {
+                continue;
+            }
+            if referenced.contains(&index::package_name(path)) {
+                info!("Keep {package}, another var depends on it / 保留{package}，有其他Var依赖它");
+                continue;
+            }
+            let size = fs::metadata(path).map_or(0, |m| m.len());
+            let to = crate::backup::free_path(
+                backup_folder.join(path.strip_prefix(var_folder).unwrap()),
+            );
+            match crate::backup::move_verified(path, &to) {
+                Ok(()) => {
+                    info!("Moved {package} to the backup / 已将{package}移入备份");
+                    freed += size;
+                    journal.record(Operation::Move {
+                        from: path.clone(),
+                        to,
+                    });
+                }
+                Err(e) => error!("Failed to back up {package}: {e}"),
+            }
+        }
+    }
+    if journal.operations.is_empty() {
+        summary!("No superseded versions to move / 没有需要移出的旧版本Var");
+        return;
+    }
+    summary!(
+        "Moved {} old versions ({}) to the backup / 已将{}个旧版本Var（{}）移入备份",
+        journal.operations.len(),
+        format_size(freed),
+        journal.operations.len(),
+        format_size(freed)
+    );
+    if let Err(e) = journal.save(&vam_folder.join("VarCleaner/Journal")) {
+        error!("Failed to save the journal: {e}");
+    }
+}